@@ -1,7 +1,238 @@
+//! AnchorHash, following Algorithm 3 of Mendelson _et al_,
+//! "AnchorHash: A Scalable Consistent Hash".
+//!
+//! The anchor set `A` holds every bucket that can ever be used (its
+//! capacity) and the working set `W` the buckets currently in use.
+//! A key is hashed into the anchor set and, if it lands on a removed
+//! bucket, rehashed into the buckets that were still working when that
+//! bucket was removed, until it lands on a working one.
+
+use std::hash::{BuildHasher, Hash};
+
+use ringhash::hash::BuildXxHash64;
+
+pub struct AnchorHash<S = BuildXxHash64> {
+    // A[b] is 0 when b is working, otherwise the size of
+    // the working set just after b was removed
+    a: Vec<u32>,
+    // W, L and K let a removed bucket find its replacement
+    // in O(1), without rewriting the rest of A
+    w: Vec<u32>,
+    l: Vec<u32>,
+    k: Vec<u32>,
+    // removed buckets, re-added last in first out
+    r: Vec<u32>,
+    // size of the working set
+    n: u32,
+    build_hasher: S,
+}
+
+impl AnchorHash {
+    /// Buckets `0..working` are in use, and `capacity - working`
+    /// more can be added later
+    pub fn new(capacity: u32, working: u32) -> AnchorHash {
+        AnchorHash::with_hasher(capacity, working, BuildXxHash64::default())
+    }
+}
+
+impl<S: BuildHasher> AnchorHash<S> {
+    pub fn with_hasher(capacity: u32, working: u32, build_hasher: S) -> AnchorHash<S> {
+        assert!(working > 0, "at least one bucket must be working");
+        assert!(working <= capacity, "working set can't be larger than the anchor");
+
+        let mut a = vec![0; capacity as usize];
+        let mut r = Vec::with_capacity((capacity - working) as usize);
+        for b in (working..capacity).rev() {
+            a[b as usize] = b;
+            r.push(b);
+        }
+        let identity: Vec<u32> = (0..capacity).collect();
+
+        AnchorHash {
+            a,
+            w: identity.clone(),
+            l: identity.clone(),
+            k: identity,
+            r,
+            n: working,
+            build_hasher,
+        }
+    }
+
+    /// The number of working buckets
+    pub fn len(&self) -> usize {
+        self.n as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// The number of buckets in the anchor set
+    pub fn capacity(&self) -> usize {
+        self.a.len()
+    }
+
+    pub fn is_working(&self, b: u32) -> bool {
+        (b as usize) < self.a.len() && self.a[b as usize] == 0
+    }
+
+    /// The working bucket for `k`, in O(1) expected time
+    pub fn get_bucket<K: Hash>(&self, k: &K) -> u32 {
+        let key = self.build_hasher.hash_one(k);
+        let mut b = (key % self.a.len() as u64) as u32;
+
+        while self.a[b as usize] > 0 {
+            // rehash into the buckets that were working when b was removed
+            let mut h = (self.build_hasher.hash_one((key, b)) % self.a[b as usize] as u64) as u32;
+            // h was removed before b, so follow its replacements
+            while self.a[h as usize] >= self.a[b as usize] {
+                h = self.k[h as usize];
+            }
+            b = h;
+        }
+        b
+    }
+
+    /// Brings back the most recently removed bucket, or `None`
+    /// if every bucket in the anchor set is already working
+    pub fn add_bucket(&mut self) -> Option<u32> {
+        let b = self.r.pop()?;
+        let n = self.n as usize;
+
+        self.a[b as usize] = 0;
+        self.l[self.w[n] as usize] = self.n;
+        self.w[self.l[b as usize] as usize] = b;
+        self.k[b as usize] = b;
+        self.n += 1;
+        Some(b)
+    }
+
+    /// Removes a working bucket; only the keys mapped to it move.
+    /// Returns false if `b` isn't working or is the last working bucket.
+    pub fn remove_bucket(&mut self, b: u32) -> bool {
+        if !self.is_working(b) || self.n == 1 {
+            return false;
+        }
+
+        self.r.push(b);
+        self.n -= 1;
+        let n = self.n as usize;
+
+        self.a[b as usize] = self.n;
+        let last = self.w[n];
+        self.w[self.l[b as usize] as usize] = last;
+        self.k[b as usize] = last;
+        self.l[last as usize] = self.l[b as usize];
+        true
+    }
+}
+
 #[cfg(test)]
 mod tests {
+
+    use super::AnchorHash;
+
+    const KEYS: u32 = 50_000;
+
+    fn buckets(anchor: &AnchorHash) -> Vec<u32> {
+        (0..KEYS).map(|k| anchor.get_bucket(&k)).collect()
+    }
+
+    #[test]
+    fn keys_only_land_on_working_buckets() {
+        let mut anchor = AnchorHash::new(20, 10);
+        assert!(anchor.remove_bucket(3));
+        assert!(anchor.remove_bucket(7));
+        assert_eq!(anchor.len(), 8);
+
+        for b in buckets(&anchor) {
+            assert!(b < 10);
+            assert!(anchor.is_working(b));
+        }
+    }
+
+    #[test]
+    fn removing_a_bucket_only_moves_its_keys() {
+        let mut anchor = AnchorHash::new(32, 16);
+        let before = buckets(&anchor);
+
+        assert!(anchor.remove_bucket(5));
+        let after = buckets(&anchor);
+
+        for (old, new) in before.iter().zip(after.iter()) {
+            if *old != 5 {
+                assert_eq!(old, new);
+            } else {
+                assert_ne!(*new, 5);
+            }
+        }
+    }
+
+    #[test]
+    fn adding_a_bucket_only_moves_keys_to_it() {
+        let mut anchor = AnchorHash::new(32, 16);
+        anchor.remove_bucket(2);
+        anchor.remove_bucket(11);
+        let before = buckets(&anchor);
+
+        let added = anchor.add_bucket().unwrap();
+        let after = buckets(&anchor);
+
+        for (old, new) in before.iter().zip(after.iter()) {
+            assert!(old == new || *new == added);
+        }
+    }
+
+    #[test]
+    fn removals_are_undone_in_reverse() {
+        let mut anchor = AnchorHash::new(16, 16);
+        let before = buckets(&anchor);
+
+        for b in &[4, 9, 0, 15] {
+            assert!(anchor.remove_bucket(*b));
+        }
+        for b in &[15, 0, 9, 4] {
+            assert_eq!(anchor.add_bucket(), Some(*b));
+        }
+
+        assert_eq!(before, buckets(&anchor));
+    }
+
+    #[test]
+    fn buckets_are_balanced() {
+        let mut anchor = AnchorHash::new(40, 20);
+        for b in &[1, 6, 13, 18] {
+            anchor.remove_bucket(*b);
+        }
+        anchor.add_bucket();
+
+        let mut counts = vec![0u32; anchor.capacity()];
+        for b in buckets(&anchor) {
+            counts[b as usize] += 1;
+        }
+
+        let mean = KEYS as f64 / anchor.len() as f64;
+        for (b, count) in counts.iter().enumerate() {
+            if anchor.is_working(b as u32) {
+                let ratio = *count as f64 / mean;
+                assert!((ratio - 1.0).abs() < 0.1, "bucket {} has {} keys", b, count);
+            } else {
+                assert_eq!(*count, 0);
+            }
+        }
+    }
+
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    fn bounds_on_membership() {
+        let mut anchor = AnchorHash::new(2, 1);
+        assert!(!anchor.remove_bucket(0));
+        assert!(!anchor.remove_bucket(1));
+        assert!(!anchor.remove_bucket(5));
+
+        assert_eq!(anchor.add_bucket(), Some(1));
+        assert_eq!(anchor.add_bucket(), None);
+        assert_eq!(anchor.len(), 2);
     }
+
 }
@@ -0,0 +1,146 @@
+//! Jump Consistent Hash, from Lamping & Veach,
+//! "A Fast, Minimal Memory, Consistent Hash Algorithm".
+//!
+//! Keys are mapped straight to a bucket number in `0..n` without any
+//! ring to store. The catch is that buckets can only be added or removed
+//! at the end, so removing any other node moves the last one into its
+//! place (and with it, the last node's keys).
+
+use std::hash::{BuildHasher, Hash};
+
+use ringhash::hash::BuildXxHash64;
+use ringhash::ConsistentHasher;
+
+/// The bucket in `0..buckets` for a (well distributed) 64-bit key
+pub fn jump_consistent_hash(mut key: u64, buckets: u32) -> u32 {
+    let mut b: i64 = -1;
+    let mut j: i64 = 0;
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as u32
+}
+
+pub struct JumpHash<N, S = BuildXxHash64> {
+    nodes: Vec<N>,
+    build_hasher: S,
+}
+
+impl<N: Hash> Default for JumpHash<N> {
+    fn default() -> Self {
+        JumpHash::new()
+    }
+}
+
+impl<N: Hash> JumpHash<N> {
+    pub fn new() -> JumpHash<N> {
+        JumpHash::with_hasher(BuildXxHash64::default())
+    }
+}
+
+impl<N: Hash, S: BuildHasher> JumpHash<N, S> {
+    pub fn with_hasher(build_hasher: S) -> JumpHash<N, S> {
+        JumpHash {
+            nodes: vec![],
+            build_hasher,
+        }
+    }
+}
+
+impl<N: Hash, S: BuildHasher> ConsistentHasher<N> for JumpHash<N, S> {
+    fn add(&mut self, node: N) {
+        let id = self.build_hasher.hash_one(&node);
+        if self.nodes.iter().all(|n| self.build_hasher.hash_one(n) != id) {
+            self.nodes.push(node);
+        }
+    }
+
+    /// Removing the last node added is free of extra movement; any
+    /// other node is swapped with the last one first
+    fn remove(&mut self, node: &N) -> Option<N> {
+        let id = self.build_hasher.hash_one(node);
+        let pos = self
+            .nodes
+            .iter()
+            .position(|n| self.build_hasher.hash_one(n) == id)?;
+        Some(self.nodes.swap_remove(pos))
+    }
+
+    fn get<K: Hash>(&self, k: &K) -> Option<&N> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let key = self.build_hasher.hash_one(k);
+        let bucket = jump_consistent_hash(key, self.nodes.len() as u32);
+        self.nodes.get(bucket as usize)
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use ringhash::ConsistentHasher;
+
+    use super::{jump_consistent_hash, JumpHash};
+
+    #[test]
+    fn growing_only_moves_keys_to_new_bucket() {
+        for key in 0..10_000u64 {
+            let key = key.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            let mut prev = jump_consistent_hash(key, 1);
+            assert_eq!(prev, 0);
+            for n in 2..50 {
+                let b = jump_consistent_hash(key, n);
+                assert!(b == prev || b == n - 1);
+                prev = b;
+            }
+        }
+    }
+
+    #[test]
+    fn buckets_are_balanced() {
+        let mut jump = JumpHash::new();
+        for node in 0..10u32 {
+            jump.add(node);
+        }
+
+        let mut counts = [0u32; 10];
+        for k in 0..100_000u32 {
+            counts[*jump.get(&k).unwrap() as usize] += 1;
+        }
+        for count in counts.iter() {
+            assert!((*count as i64 - 10_000).abs() < 500, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn removing_last_node_only_moves_its_keys() {
+        let mut jump = JumpHash::new();
+        for node in 0..8u32 {
+            jump.add(node);
+        }
+        let before: Vec<u32> = (0..10_000u32).map(|k| *jump.get(&k).unwrap()).collect();
+
+        assert_eq!(jump.remove(&7), Some(7));
+        for (k, old) in before.iter().enumerate() {
+            let new = *jump.get(&(k as u32)).unwrap();
+            assert!(*old == 7 || *old == new);
+        }
+
+        assert_eq!(jump.remove(&42), None);
+        assert_eq!(jump.len(), 7);
+    }
+
+    #[test]
+    fn empty() {
+        let jump: JumpHash<u32> = JumpHash::new();
+        assert!(jump.get(b"test key").is_none());
+    }
+
+}
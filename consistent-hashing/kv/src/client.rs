@@ -0,0 +1,86 @@
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::protocol::{read_frame, write_frame, Request, Response, Versioned};
+
+/// Sends one request on a fresh connection and waits for the response
+pub(crate) fn call(addr: SocketAddr, req: &Request, timeout: Duration) -> Result<Response> {
+    Connection::open(addr, timeout)?.call(req)
+}
+
+/// A connection to another node for a run of requests, one at a time
+pub(crate) struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    pub(crate) fn open(addr: SocketAddr, timeout: Duration) -> Result<Connection> {
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Connection { stream })
+    }
+
+    /// Sends `req` and waits for the response
+    pub(crate) fn call(&mut self, req: &Request) -> Result<Response> {
+        write_frame(&mut self.stream, req)?;
+        read_frame(&mut self.stream)?
+            .ok_or_else(|| Error::Protocol("connection closed".to_string()))
+    }
+}
+
+/// Talks to any node in the cluster, which coordinates
+/// the request with the key's replicas
+pub struct Client {
+    addr: SocketAddr,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(addr: SocketAddr) -> Client {
+        Client {
+            addr,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let req = Request::Get {
+            key: key.to_string(),
+        };
+        self.value(req).map(|v| v.map(|v| v.value))
+    }
+
+    pub fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        let req = Request::Put {
+            key: key.to_string(),
+            value: value.to_vec(),
+        };
+        match call(self.addr, &req, self.timeout)? {
+            Response::Stored => Ok(()),
+            res => Err(unexpected(res)),
+        }
+    }
+
+    /// The node's own copy of `key`, without asking any other replica
+    pub fn read_local(&self, key: &str) -> Result<Option<Versioned>> {
+        self.value(Request::Read {
+            key: key.to_string(),
+        })
+    }
+
+    fn value(&self, req: Request) -> Result<Option<Versioned>> {
+        match call(self.addr, &req, self.timeout)? {
+            Response::Value(v) => Ok(v),
+            res => Err(unexpected(res)),
+        }
+    }
+}
+
+fn unexpected(res: Response) -> Error {
+    match res {
+        Response::Unavailable { needed, answered } => Error::Unavailable { needed, answered },
+        res => Error::Protocol(format!("unexpected response: {:?}", res)),
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Fewer replicas answered than the quorum needs
+    Unavailable { needed: usize, answered: usize },
+    /// The other side sent something we didn't expect
+    Protocol(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Unavailable { needed, answered } => write!(
+                f,
+                "quorum not reached: {} of {} replicas answered",
+                answered, needed
+            ),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Protocol(e.to_string())
+    }
+}
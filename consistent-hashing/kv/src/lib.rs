@@ -0,0 +1,18 @@
+//! A partitioned, replicated key-value store, following Section 4
+//! of DeCandia _et al_, "Dynamo: Amazon's Highly Available Key-value
+//! Store". Keys are placed with `ringhash`: each is stored on the first
+//! `n` nodes of its preference list, and reads and writes succeed once
+//! `r` and `w` of them respond. Replicas that drift apart are brought
+//! back in sync by comparing Merkle trees of the ranges they share.
+
+pub mod client;
+pub mod error;
+pub mod merkle;
+pub mod protocol;
+pub mod server;
+mod store;
+pub mod sync;
+
+pub use client::Client;
+pub use error::{Error, Result};
+pub use server::{Config, Quorum, Server};
@@ -0,0 +1,118 @@
+use std::env;
+use std::net::{SocketAddr, TcpListener};
+use std::process;
+use std::time::Duration;
+
+use kv::{Config, Quorum, Server};
+
+const USAGE: &str = "usage: kv --bind ADDR [--peers ADDR,..] [--n N] [--r R] [--w W] [--vnodes N]
+          [--anti-entropy MS]
+
+Runs one node of the store. Every node must be given the same peers.";
+
+fn parse_args(args: &[String]) -> Result<Config, String> {
+    let mut bind = None;
+    let mut peers = vec![];
+    let default = Quorum::default();
+    let (mut n, mut r, mut w) = (default.n, default.r, default.w);
+    let mut vnodes = None;
+    let mut anti_entropy = None;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        let invalid = || format!("invalid value for {}: {}", flag, value);
+        match flag.as_str() {
+            "--bind" => bind = Some(value.parse().map_err(|_| invalid())?),
+            "--peers" => {
+                peers = value
+                    .split(',')
+                    .map(|p| p.parse().map_err(|_| invalid()))
+                    .collect::<Result<Vec<SocketAddr>, _>>()?
+            }
+            "--n" => n = value.parse().map_err(|_| invalid())?,
+            "--r" => r = value.parse().map_err(|_| invalid())?,
+            "--w" => w = value.parse().map_err(|_| invalid())?,
+            "--vnodes" => vnodes = Some(value.parse().map_err(|_| invalid())?),
+            "--anti-entropy" => {
+                let ms = value
+                    .parse()
+                    .ok()
+                    .filter(|ms| *ms > 0)
+                    .ok_or_else(invalid)?;
+                anti_entropy = Some(Duration::from_millis(ms))
+            }
+            _ => return Err(format!("unknown flag: {}", flag)),
+        }
+    }
+
+    let bind = bind.ok_or_else(|| "--bind is required".to_string())?;
+    let mut config = Config::new(bind, peers);
+    config.quorum = Quorum::new(n, r, w).ok_or_else(|| "r and w must be between 1 and n".to_string())?;
+    if let Some(vnodes) = vnodes {
+        config.replicas = vnodes;
+    }
+    if let Some(interval) = anti_entropy {
+        config.anti_entropy_interval = interval;
+    }
+    Ok(config)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match parse_args(&args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+
+    let listener = match TcpListener::bind(config.addr) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("couldn't bind to {}: {}", config.addr, e);
+            process::exit(1);
+        }
+    };
+    println!("listening on {}", config.addr);
+
+    if let Err(e) = Server::new(config).serve(listener) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_flags() {
+        let config = parse_args(&args(
+            "--bind 127.0.0.1:4000 --peers 127.0.0.1:4001,127.0.0.1:4002 --n 2 --r 1 --w 2 --anti-entropy 250",
+        ))
+        .unwrap();
+        assert_eq!(config.addr, "127.0.0.1:4000".parse().unwrap());
+        assert_eq!(config.peers.len(), 2);
+        assert_eq!(config.quorum, Quorum::new(2, 1, 2).unwrap());
+        assert_eq!(config.anti_entropy_interval, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn rejects_bad_flags() {
+        assert!(parse_args(&args("--peers 127.0.0.1:4001")).is_err());
+        assert!(parse_args(&args("--bind localhost")).is_err());
+        assert!(parse_args(&args("--bind 127.0.0.1:4000 --r 4")).is_err());
+        assert!(parse_args(&args("--bind 127.0.0.1:4000 --verbose true")).is_err());
+        assert!(parse_args(&args("--bind 127.0.0.1:4000 --anti-entropy 0")).is_err());
+    }
+
+}
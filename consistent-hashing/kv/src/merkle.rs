@@ -0,0 +1,194 @@
+//! Merkle trees over a token range, so two replicas can find which
+//! parts of a range they disagree on without exchanging the keys.
+//!
+//! The range is split into `2^depth` equal leaves by ring position.
+//! A leaf's hash combines the hashes of every (key, version) in it, and
+//! each parent hashes its two children, so replicas whose roots match
+//! hold the same data. Where they don't, comparing children level by
+//! level narrows the difference down to a few leaves.
+
+use std::hash::BuildHasher;
+
+use ringhash::hash::BuildXxHash64;
+use ringhash::rebalance::TokenRange;
+
+use crate::protocol::Version;
+
+/// Deep enough that a leaf holds a handful of keys for
+/// modest ranges, small enough to rebuild every sync
+pub const DEPTH: u32 = 8;
+
+pub struct MerkleTree {
+    range: TokenRange,
+    depth: u32,
+    // heap ordered: the root is node 1, the children of i are 2i and
+    // 2i + 1, and the leaves are the last 2^depth nodes
+    nodes: Vec<u64>,
+}
+
+fn entry_hash(key: &str, version: &Version) -> u64 {
+    BuildXxHash64::default().hash_one((key, version))
+}
+
+impl MerkleTree {
+    /// A tree of the `(position, key, version)` entries in `range`.
+    /// Entries outside it are ignored.
+    pub fn build<'a, I>(range: TokenRange, depth: u32, entries: I) -> MerkleTree
+    where
+        I: IntoIterator<Item = (u64, &'a str, &'a Version)>,
+    {
+        let mut trees = MerkleTree::build_all(&[range], depth, entries);
+        trees.remove(0)
+    }
+
+    /// A tree for each of `ranges`, which can't overlap, in one pass
+    /// over the entries rather than one per range. Entries outside all
+    /// of them are ignored.
+    pub fn build_all<'a, I>(ranges: &[TokenRange], depth: u32, entries: I) -> Vec<MerkleTree>
+    where
+        I: IntoIterator<Item = (u64, &'a str, &'a Version)>,
+    {
+        let leaves = 1usize << depth;
+        let mut trees: Vec<MerkleTree> = ranges
+            .iter()
+            .map(|range| MerkleTree {
+                range: *range,
+                depth,
+                nodes: vec![0; 2 * leaves],
+            })
+            .collect();
+        let mut by_start: Vec<usize> = (0..ranges.len()).collect();
+        by_start.sort_by_key(|i| ranges[*i].start);
+
+        for (position, key, version) in entries {
+            // the last range starting at or before it, if it's in that
+            let i = match by_start.partition_point(|i| ranges[*i].start <= position) {
+                0 => continue,
+                i => by_start[i - 1],
+            };
+            if ranges[i].contains(position) {
+                // xor, so the order entries come in doesn't matter
+                trees[i].nodes[leaves + leaf(ranges[i], depth, position)] ^=
+                    entry_hash(key, version);
+            }
+        }
+        for tree in trees.iter_mut() {
+            for i in (1..leaves).rev() {
+                tree.nodes[i] =
+                    BuildXxHash64::default().hash_one((tree.nodes[2 * i], tree.nodes[2 * i + 1]));
+            }
+        }
+        trees
+    }
+
+    pub fn range(&self) -> TokenRange {
+        self.range
+    }
+
+    pub fn root(&self) -> u64 {
+        self.nodes[1]
+    }
+
+    /// The hash of the `index`th node on `level`, where the root
+    /// is level 0 and the leaves are level `depth`
+    pub fn hash(&self, level: u32, index: usize) -> Option<u64> {
+        if level > self.depth || index >= 1 << level {
+            return None;
+        }
+        Some(self.nodes[(1 << level) + index])
+    }
+
+    /// The leaf `position` falls in
+    pub fn leaf(&self, position: u64) -> usize {
+        leaf(self.range, self.depth, position)
+    }
+}
+
+/// The leaf `position` falls in, in a tree of `range` of the given depth
+pub fn leaf(range: TokenRange, depth: u32, position: u64) -> usize {
+    let offset = (position - range.start) as u128;
+    ((offset << depth) / range.size()) as usize
+}
+
+#[cfg(test)]
+mod tests {
+
+    use ringhash::rebalance::TokenRange;
+
+    use super::MerkleTree;
+    use crate::protocol::Version;
+
+    fn version(timestamp: u64) -> Version {
+        Version {
+            timestamp,
+            coordinator: "127.0.0.1:4000".parse().unwrap(),
+        }
+    }
+
+    const RANGE: TokenRange = TokenRange {
+        start: 1000,
+        end: 1999,
+    };
+
+    #[test]
+    fn same_entries_same_root() {
+        let v = version(1);
+        let entries = [(1000, "a", &v), (1500, "b", &v), (1999, "c", &v)];
+        let tree = MerkleTree::build(RANGE, 4, entries.iter().copied());
+        let reversed = MerkleTree::build(RANGE, 4, entries.iter().rev().copied());
+        assert_eq!(tree.root(), reversed.root());
+
+        // out of range entries don't count
+        let extra = entries.iter().copied().chain(Some((2000, "d", &v)));
+        assert_eq!(MerkleTree::build(RANGE, 4, extra).root(), tree.root());
+    }
+
+    #[test]
+    fn differences_are_confined_to_their_leaf() {
+        let (v1, v2) = (version(1), version(2));
+        let ours = MerkleTree::build(RANGE, 4, vec![(1100, "a", &v1), (1900, "b", &v1)]);
+        let theirs = MerkleTree::build(RANGE, 4, vec![(1100, "a", &v1), (1900, "b", &v2)]);
+        assert_ne!(ours.root(), theirs.root());
+
+        let leaf = ours.leaf(1900);
+        assert_eq!(leaf, 14);
+        for i in 0..16 {
+            assert_eq!(ours.hash(4, i) == theirs.hash(4, i), i != leaf);
+        }
+        assert_eq!(ours.hash(5, 0), None);
+        assert_eq!(ours.hash(4, 16), None);
+    }
+
+    #[test]
+    fn trees_built_together_match_those_built_alone() {
+        let v = version(1);
+        let other = TokenRange {
+            start: 3000,
+            end: 3999,
+        };
+        let entries = [(1100, "a", &v), (3500, "b", &v), (2500, "c", &v), (500, "d", &v)];
+        let trees = MerkleTree::build_all(&[other, RANGE], 4, entries.iter().copied());
+        assert_eq!(trees.len(), 2);
+        for tree in trees {
+            let alone = MerkleTree::build(tree.range(), 4, entries.iter().copied());
+            assert_eq!(tree.root(), alone.root());
+        }
+    }
+
+    #[test]
+    fn leaves_split_the_range_evenly() {
+        let tree = MerkleTree::build(RANGE, 2, vec![]);
+        assert_eq!(tree.leaf(1000), 0);
+        assert_eq!(tree.leaf(1249), 0);
+        assert_eq!(tree.leaf(1250), 1);
+        assert_eq!(tree.leaf(1999), 3);
+
+        let full = TokenRange {
+            start: 0,
+            end: u64::MAX,
+        };
+        let tree = MerkleTree::build(full, 2, vec![]);
+        assert_eq!(tree.leaf(u64::MAX), 3);
+    }
+
+}
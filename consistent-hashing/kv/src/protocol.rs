@@ -0,0 +1,135 @@
+//! Requests and responses are bincode encoded and framed with
+//! a 4 byte big endian length, over a plain TCP stream.
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+
+use ringhash::rebalance::TokenRange;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+// nothing we send comes close, so anything
+// bigger means the stream is out of sync
+const MAX_FRAME: u32 = 16 * 1024 * 1024;
+
+/// Orders writes to the same key: the later timestamp wins, and the
+/// coordinator's address breaks ties between simultaneous writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Version {
+    pub timestamp: u64,
+    pub coordinator: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Versioned {
+    pub value: Vec<u8>,
+    pub version: Version,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// From a client: read `key` from a quorum of its replicas
+    Get { key: String },
+    /// From a client: write `key` to a quorum of its replicas
+    Put { key: String, value: Vec<u8> },
+    /// From a coordinator: read this node's copy of `key`
+    Read { key: String },
+    /// From a coordinator: store this copy of `key`
+    Replicate { key: String, value: Versioned },
+    /// From a coordinator: hold `key` for `intended`, which is
+    /// down, and hand it over once it's back
+    Hint {
+        key: String,
+        value: Versioned,
+        intended: SocketAddr,
+    },
+    /// From a replica syncing `range`: hashes of
+    /// these nodes on a level of its Merkle tree
+    Hashes {
+        range: TokenRange,
+        level: u32,
+        indexes: Vec<usize>,
+    },
+    /// From a replica syncing `range`: the keys
+    /// and versions in these leaves of its tree
+    Digests { range: TokenRange, leaves: Vec<usize> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Value(Option<Versioned>),
+    Stored,
+    Hashes(Vec<u64>),
+    Digests(Vec<(String, Version)>),
+    Unavailable { needed: usize, answered: usize },
+}
+
+pub fn write_frame<W: Write, T: Serialize>(w: &mut W, msg: &T) -> Result<()> {
+    let body = bincode::serialize(msg)?;
+    // in one write, since a second small one on a connection that's
+    // reused would wait on Nagle's algorithm for the first's ack
+    let mut buf = Vec::with_capacity(4 + body.len());
+    buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&body);
+    w.write_all(&buf)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// The next message on the stream, or `None` once it's closed
+pub fn read_frame<R: Read, T: DeserializeOwned>(r: &mut R) -> Result<Option<T>> {
+    let mut len = [0; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME {
+        return Err(Error::Protocol(format!("frame of {} bytes is too large", len)));
+    }
+    let mut buf = vec![0; len as usize];
+    r.read_exact(&mut buf)?;
+    Ok(Some(bincode::deserialize(&buf)?))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Cursor;
+
+    use super::{read_frame, write_frame, Request, Response, Version, Versioned};
+
+    #[test]
+    fn frames_round_trip() {
+        let put = Request::Put {
+            key: "user:42".to_string(),
+            value: b"hello".to_vec(),
+        };
+        let value = Response::Value(Some(Versioned {
+            value: b"hello".to_vec(),
+            version: Version {
+                timestamp: 7,
+                coordinator: "127.0.0.1:4000".parse().unwrap(),
+            },
+        }));
+
+        let mut buf = vec![];
+        write_frame(&mut buf, &put).unwrap();
+        write_frame(&mut buf, &value).unwrap();
+
+        let mut r = Cursor::new(buf);
+        assert_eq!(read_frame::<_, Request>(&mut r).unwrap(), Some(put));
+        assert_eq!(read_frame::<_, Response>(&mut r).unwrap(), Some(value));
+        assert_eq!(read_frame::<_, Request>(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let mut r = Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0]);
+        assert!(read_frame::<_, Request>(&mut r).is_err());
+    }
+
+}
@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ringhash::rebalance::{self, TokenRange};
+use ringhash::HashRing;
+
+use crate::client;
+use crate::error::Result;
+use crate::protocol::{read_frame, write_frame, Request, Response, Version, Versioned};
+use crate::store::Store;
+use crate::sync::{self, LocalReplica, RemoteReplica, Replica};
+
+/// Every key is stored on `n` nodes. A read waits for `r` of them to
+/// answer and a write for `w` of them to acknowledge, so `r + w > n`
+/// means every read overlaps the latest successful write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quorum {
+    pub n: usize,
+    pub r: usize,
+    pub w: usize,
+}
+
+impl Quorum {
+    pub fn new(n: usize, r: usize, w: usize) -> Option<Quorum> {
+        if n == 0 || r == 0 || w == 0 || r > n || w > n {
+            return None;
+        }
+        Some(Quorum { n, r, w })
+    }
+}
+
+impl Default for Quorum {
+    fn default() -> Self {
+        Quorum { n: 3, r: 2, w: 2 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The address other nodes and clients reach this one on
+    pub addr: SocketAddr,
+    /// Every node in the cluster. Nodes must agree on it, since
+    /// it decides which nodes are a key's replicas.
+    pub peers: Vec<SocketAddr>,
+    pub quorum: Quorum,
+    /// Virtual nodes given to each node on the ring
+    pub replicas: u32,
+    /// How long to wait on another node before treating it as down
+    pub timeout: Duration,
+    /// How often to try handing hints back to their nodes
+    pub handoff_interval: Duration,
+    /// How often to sync every range with its other replicas
+    pub anti_entropy_interval: Duration,
+}
+
+impl Config {
+    pub fn new(addr: SocketAddr, peers: Vec<SocketAddr>) -> Config {
+        Config {
+            addr,
+            peers,
+            quorum: Quorum::default(),
+            replicas: 64,
+            timeout: Duration::from_millis(500),
+            handoff_interval: Duration::from_millis(500),
+            anti_entropy_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A node that both stores replicas and coordinates client requests
+/// for any key, following Dynamo's sloppy quorums.
+///
+/// Conflicting writes are resolved by last writer wins, on the
+/// coordinator's clock, so nodes' clocks should roughly agree.
+pub struct Server {
+    config: Config,
+    ring: HashRing<SocketAddr>,
+    // the ranges this node replicates, and their other replicas
+    shared: Vec<(TokenRange, Vec<SocketAddr>)>,
+    ranges: Vec<TokenRange>,
+    store: Mutex<Store>,
+    // the last version timestamp handed out
+    clock: AtomicU64,
+}
+
+impl Server {
+    pub fn new(config: Config) -> Server {
+        let mut ring = HashRing::with_replicas(config.replicas);
+        for peer in config.peers.iter().chain(Some(&config.addr)) {
+            ring.add(*peer);
+        }
+        let shared: Vec<(TokenRange, Vec<SocketAddr>)> =
+            rebalance::replica_sets(&ring, config.quorum.n)
+                .into_iter()
+                .filter(|(_, replicas)| replicas.contains(&&config.addr))
+                .map(|(range, replicas)| {
+                    let others = replicas.into_iter().filter(|r| **r != config.addr);
+                    (range, others.copied().collect())
+                })
+                .collect();
+        let ranges = shared.iter().map(|(range, _)| *range).collect();
+        Server {
+            config,
+            ring,
+            shared,
+            ranges,
+            store: Mutex::new(Store::new()),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Serves connections on `listener` until it fails, handing hints
+    /// over to their nodes and syncing replicas in the background
+    pub fn serve(self, listener: TcpListener) -> Result<()> {
+        let server = Arc::new(self);
+
+        let handoff = server.clone();
+        thread::spawn(move || loop {
+            thread::sleep(handoff.config.handoff_interval);
+            handoff.hand_off();
+        });
+
+        let anti_entropy = server.clone();
+        thread::spawn(move || loop {
+            thread::sleep(anti_entropy.config.anti_entropy_interval);
+            anti_entropy.anti_entropy();
+        });
+
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = server.clone();
+            thread::spawn(move || server.handle_stream(stream));
+        }
+        Ok(())
+    }
+
+    fn handle_stream(&self, mut stream: TcpStream) {
+        // a replica syncing with this one sends all its requests on one
+        // connection, so the trees built for it are kept until it closes
+        let local = self.local();
+        while let Ok(Some(req)) = read_frame(&mut stream) {
+            let res = self.respond(req, &local);
+            if write_frame(&mut stream, &res).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn respond(&self, req: Request, local: &LocalReplica<'_>) -> Response {
+        match req {
+            Request::Get { key } => self.get(&key),
+            Request::Put { key, value } => self.put(key, value),
+            Request::Read { key } => Response::Value(self.lock().get(&key).cloned()),
+            Request::Replicate { key, value } => {
+                self.lock().put(key, value);
+                Response::Stored
+            }
+            Request::Hint {
+                key,
+                value,
+                intended,
+            } => {
+                self.lock().put_hint(intended, key, value);
+                Response::Stored
+            }
+            Request::Hashes {
+                range,
+                level,
+                indexes,
+            } => match local.hashes(range, level, &indexes) {
+                Ok(hashes) => Response::Hashes(hashes),
+                Err(_) => Response::Hashes(vec![]),
+            },
+            Request::Digests { range, leaves } => match local.digests(range, &leaves) {
+                Ok(digests) => Response::Digests(digests),
+                Err(_) => Response::Digests(vec![]),
+            },
+        }
+    }
+
+    fn local(&self) -> LocalReplica<'_> {
+        LocalReplica::new(&self.store, &self.ring, &self.ranges)
+    }
+
+    // calls another node, or answers directly when it's this one
+    fn call(&self, addr: SocketAddr, req: &Request) -> Result<Response> {
+        if addr == self.config.addr {
+            return Ok(self.respond(req.clone(), &self.local()));
+        }
+        client::call(addr, req, self.config.timeout)
+    }
+
+    fn fan_out(&self, nodes: &[SocketAddr], req: &Request) -> Vec<Result<Response>> {
+        thread::scope(|s| {
+            let calls: Vec<_> = nodes
+                .iter()
+                .map(|addr| s.spawn(move || self.call(*addr, req)))
+                .collect();
+            calls
+                .into_iter()
+                .map(|c| c.join().expect("calls don't panic"))
+                .collect()
+        })
+    }
+
+    // the key's replicas, followed by the nodes
+    // that stand in for them when they're down
+    fn preference_list(&self, key: &str) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        let mut replicas: Vec<SocketAddr> = self.ring.preference_list(&key).copied().collect();
+        let fallbacks = replicas.split_off(self.config.quorum.n.min(replicas.len()));
+        (replicas, fallbacks)
+    }
+
+    fn next_version(&self) -> Version {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        // never hand out the same timestamp twice
+        let prev = self
+            .clock
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+            .unwrap_or(now);
+        Version {
+            timestamp: now.max(prev + 1),
+            coordinator: self.config.addr,
+        }
+    }
+
+    /// Writes to the key's replicas. Each replica that's down is stood
+    /// in for by the next healthy node on the ring, which holds the
+    /// value as a hint until the replica is back.
+    fn put(&self, key: String, value: Vec<u8>) -> Response {
+        let value = Versioned {
+            value,
+            version: self.next_version(),
+        };
+        let (replicas, fallbacks) = self.preference_list(&key);
+        let req = Request::Replicate {
+            key: key.clone(),
+            value: value.clone(),
+        };
+        let results = self.fan_out(&replicas, &req);
+
+        let mut acked = 0;
+        let mut fallbacks = fallbacks.into_iter();
+        for (intended, res) in replicas.into_iter().zip(results) {
+            if let Ok(Response::Stored) = res {
+                acked += 1;
+                continue;
+            }
+            let hint = Request::Hint {
+                key: key.clone(),
+                value: value.clone(),
+                intended,
+            };
+            if fallbacks.any(|f| matches!(self.call(f, &hint), Ok(Response::Stored))) {
+                acked += 1;
+            }
+        }
+
+        let needed = self.config.quorum.w;
+        if acked >= needed {
+            Response::Stored
+        } else {
+            Response::Unavailable {
+                needed,
+                answered: acked,
+            }
+        }
+    }
+
+    /// Reads from the first `n` healthy nodes for the key, answering
+    /// with the newest value once `r` have. Replicas that answered with
+    /// an older value (or none) are then repaired in the background.
+    fn get(&self, key: &str) -> Response {
+        let (replicas, fallbacks) = self.preference_list(key);
+        let req = Request::Read {
+            key: key.to_string(),
+        };
+        let results = self.fan_out(&replicas, &req);
+
+        let mut answers: Vec<(SocketAddr, Option<Versioned>)> = vec![];
+        let mut down = 0;
+        for (addr, res) in replicas.iter().zip(results) {
+            match res {
+                Ok(Response::Value(v)) => answers.push((*addr, v)),
+                _ => down += 1,
+            }
+        }
+        for addr in fallbacks {
+            if down == 0 {
+                break;
+            }
+            if let Ok(Response::Value(v)) = self.call(addr, &req) {
+                answers.push((addr, v));
+                down -= 1;
+            }
+        }
+
+        let needed = self.config.quorum.r;
+        if answers.len() < needed {
+            return Response::Unavailable {
+                needed,
+                answered: answers.len(),
+            };
+        }
+
+        let newest = answers
+            .iter()
+            .filter_map(|(_, v)| v.as_ref())
+            .max_by_key(|v| v.version)
+            .cloned();
+        if let Some(newest) = newest.as_ref() {
+            let stale: Vec<SocketAddr> = answers
+                .iter()
+                .filter(|(addr, v)| {
+                    replicas.contains(addr) && v.as_ref().map(|v| v.version) != Some(newest.version)
+                })
+                .map(|(addr, _)| *addr)
+                .collect();
+            self.repair(stale, key, newest);
+        }
+        Response::Value(newest)
+    }
+
+    fn repair(&self, stale: Vec<SocketAddr>, key: &str, newest: &Versioned) {
+        let req = Request::Replicate {
+            key: key.to_string(),
+            value: newest.clone(),
+        };
+        for addr in stale {
+            if addr == self.config.addr {
+                self.respond(req.clone(), &self.local());
+                continue;
+            }
+            let req = req.clone();
+            let timeout = self.config.timeout;
+            thread::spawn(move || client::call(addr, &req, timeout));
+        }
+    }
+
+    /// Syncs every range this node replicates with the other replicas.
+    /// This node's trees are built in one pass over its store, and each
+    /// peer is synced over one connection. Once a peer fails, the rest
+    /// of the ranges it shares are left for the next round.
+    fn anti_entropy(&self) {
+        let local = self.local();
+        let mut by_peer: BTreeMap<SocketAddr, Vec<TokenRange>> = BTreeMap::new();
+        for (range, peers) in self.shared.iter() {
+            for peer in peers {
+                by_peer.entry(*peer).or_default().push(*range);
+            }
+        }
+        for (peer, ranges) in by_peer {
+            let remote = RemoteReplica::new(peer, self.config.timeout);
+            for range in ranges {
+                if sync::sync(&local, &remote, range).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn hand_off(&self) {
+        let hints = self.lock().hints();
+        let mut unreachable = vec![];
+        for (intended, key, value) in hints {
+            if unreachable.contains(&intended) {
+                continue;
+            }
+            let req = Request::Replicate {
+                key: key.clone(),
+                value: value.clone(),
+            };
+            match self.call(intended, &req) {
+                Ok(Response::Stored) => self.lock().remove_hint(intended, &key, &value),
+                _ => unreachable.push(intended),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Quorum;
+
+    #[test]
+    fn quorums_must_fit_replicas() {
+        assert_eq!(Quorum::new(3, 2, 2), Some(Quorum::default()));
+        assert!(Quorum::new(3, 1, 3).is_some());
+        assert!(Quorum::new(3, 4, 1).is_none());
+        assert!(Quorum::new(3, 0, 2).is_none());
+        assert!(Quorum::new(0, 0, 0).is_none());
+    }
+
+}
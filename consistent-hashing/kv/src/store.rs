@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::protocol::Versioned;
+
+/// One node's share of the data: the keys it's a replica for, and
+/// the hints it's holding for replicas that were down
+#[derive(Default)]
+pub struct Store {
+    data: HashMap<String, Versioned>,
+    hints: HashMap<SocketAddr, HashMap<String, Versioned>>,
+}
+
+// keeps whichever of the two versions is newer
+fn merge(slot: &mut HashMap<String, Versioned>, key: String, value: Versioned) -> bool {
+    match slot.get(&key) {
+        Some(current) if current.version >= value.version => false,
+        _ => {
+            slot.insert(key, value);
+            true
+        }
+    }
+}
+
+impl Store {
+    pub fn new() -> Store {
+        Store::default()
+    }
+
+    /// The newest copy of `key`, including any hinted copies
+    pub fn get(&self, key: &str) -> Option<&Versioned> {
+        self.hints
+            .values()
+            .filter_map(|hints| hints.get(key))
+            .chain(self.data.get(key))
+            .max_by_key(|v| v.version)
+    }
+
+    /// Stores `value` unless a newer version is already stored
+    pub fn put(&mut self, key: String, value: Versioned) -> bool {
+        merge(&mut self.data, key, value)
+    }
+
+    /// Every key this node is a replica for, without the hints
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Versioned)> {
+        self.data.iter()
+    }
+
+    pub fn put_hint(&mut self, intended: SocketAddr, key: String, value: Versioned) -> bool {
+        merge(self.hints.entry(intended).or_default(), key, value)
+    }
+
+    /// Every hint being held, by the node it's meant for
+    pub fn hints(&self) -> Vec<(SocketAddr, String, Versioned)> {
+        self.hints
+            .iter()
+            .flat_map(|(addr, hints)| {
+                hints
+                    .iter()
+                    .map(move |(key, value)| (*addr, key.clone(), value.clone()))
+            })
+            .collect()
+    }
+
+    /// Drops a hint once it's been handed over, unless
+    /// it was overwritten by a newer one in the meantime
+    pub fn remove_hint(&mut self, intended: SocketAddr, key: &str, value: &Versioned) {
+        if let Some(hints) = self.hints.get_mut(&intended) {
+            if hints.get(key) == Some(value) {
+                hints.remove(key);
+            }
+            if hints.is_empty() {
+                self.hints.remove(&intended);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::SocketAddr;
+
+    use super::Store;
+    use crate::protocol::{Version, Versioned};
+
+    fn versioned(value: &str, timestamp: u64) -> Versioned {
+        Versioned {
+            value: value.as_bytes().to_vec(),
+            version: Version {
+                timestamp,
+                coordinator: "127.0.0.1:4000".parse().unwrap(),
+            },
+        }
+    }
+
+    #[test]
+    fn newest_version_wins() {
+        let mut store = Store::new();
+        assert!(store.put("k".to_string(), versioned("b", 2)));
+        assert!(!store.put("k".to_string(), versioned("a", 1)));
+        assert_eq!(store.get("k"), Some(&versioned("b", 2)));
+
+        assert!(store.put("k".to_string(), versioned("c", 3)));
+        assert_eq!(store.get("k").unwrap().value, b"c");
+    }
+
+    #[test]
+    fn hints_are_held_until_handed_over() {
+        let down: SocketAddr = "127.0.0.1:4001".parse().unwrap();
+        let mut store = Store::new();
+        store.put("k".to_string(), versioned("a", 1));
+        store.put_hint(down, "k".to_string(), versioned("b", 2));
+
+        // hinted copies are served to readers too
+        assert_eq!(store.get("k").unwrap().value, b"b");
+        assert_eq!(store.hints(), vec![(down, "k".to_string(), versioned("b", 2))]);
+
+        // handing over a hint that has since been replaced keeps it
+        store.put_hint(down, "k".to_string(), versioned("c", 3));
+        store.remove_hint(down, "k", &versioned("b", 2));
+        assert_eq!(store.hints().len(), 1);
+
+        store.remove_hint(down, "k", &versioned("c", 3));
+        assert!(store.hints().is_empty());
+    }
+
+}
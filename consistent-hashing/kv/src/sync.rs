@@ -0,0 +1,401 @@
+//! Anti-entropy between two replicas of a token range. Both sides
+//! build a Merkle tree of the range and compare it top down, only
+//! descending into nodes whose hashes differ. At the leaves they swap
+//! the keys and versions they hold, and each key one side is missing
+//! or holds an older version of is copied over, so only the keys that
+//! actually differ are transferred.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ringhash::rebalance::TokenRange;
+use ringhash::HashRing;
+
+use crate::client::Connection;
+use crate::error::{Error, Result};
+use crate::merkle::{self, MerkleTree, DEPTH};
+use crate::protocol::{Request, Response, Version, Versioned};
+use crate::store::Store;
+
+/// One side of a sync
+pub trait Replica {
+    /// The hashes of the nodes at `indexes` on `level` of the
+    /// replica's tree for `range`
+    fn hashes(&self, range: TokenRange, level: u32, indexes: &[usize]) -> Result<Vec<u64>>;
+
+    /// Every key the replica holds in the given leaves of `range`
+    fn digests(&self, range: TokenRange, leaves: &[usize]) -> Result<Vec<(String, Version)>>;
+
+    fn read(&self, key: &str) -> Result<Option<Versioned>>;
+
+    fn write(&self, key: String, value: Versioned) -> Result<()>;
+}
+
+/// What a sync had to do. Replicas already in sync
+/// only compare their roots, i.e. one level.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SyncStats {
+    pub levels: u32,
+    pub pulled: usize,
+    pub pushed: usize,
+}
+
+/// Brings `local` and `remote` to the same, newest, copy of every key in `range`
+pub fn sync<L: Replica, R: Replica>(local: &L, remote: &R, range: TokenRange) -> Result<SyncStats> {
+    let mut stats = SyncStats::default();
+
+    let mut indexes = vec![0];
+    for level in 0..=DEPTH {
+        let ours = local.hashes(range, level, &indexes)?;
+        let theirs = remote.hashes(range, level, &indexes)?;
+        stats.levels += 1;
+
+        let differing: Vec<usize> = indexes
+            .iter()
+            .zip(ours.iter().zip(theirs.iter()))
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, _)| *i)
+            .collect();
+        if differing.is_empty() {
+            return Ok(stats);
+        }
+        indexes = if level < DEPTH {
+            differing.iter().flat_map(|i| vec![2 * i, 2 * i + 1]).collect()
+        } else {
+            differing
+        };
+    }
+
+    // `indexes` are now the leaves that differ
+    let ours: HashMap<String, Version> = local.digests(range, &indexes)?.into_iter().collect();
+    let theirs: HashMap<String, Version> = remote.digests(range, &indexes)?.into_iter().collect();
+
+    for (key, version) in theirs.iter() {
+        if ours.get(key).is_none_or(|v| v < version) {
+            if let Some(value) = remote.read(key)? {
+                local.write(key.clone(), value)?;
+                stats.pulled += 1;
+            }
+        }
+    }
+    for (key, version) in ours.iter() {
+        if theirs.get(key).is_none_or(|v| v < version) {
+            if let Some(value) = local.read(key)? {
+                remote.write(key.clone(), value)?;
+                stats.pushed += 1;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// This node's own store. Hinted copies aren't part of its
+/// trees, since they're held for another replica.
+///
+/// Trees are built the first time they're needed and kept for as long
+/// as the replica is, i.e. one sync round, rather than rebuilt for every
+/// level compared. The first builds all of `ranges` in one pass over the
+/// store. Writes a round makes drop the tree they land in, while writes
+/// from elsewhere meanwhile aren't seen until the next round.
+pub(crate) struct LocalReplica<'a> {
+    store: &'a Mutex<Store>,
+    ring: &'a HashRing<SocketAddr>,
+    // the ranges this node replicates, which don't overlap
+    ranges: &'a [TokenRange],
+    trees: RefCell<HashMap<TokenRange, MerkleTree>>,
+}
+
+impl<'a> LocalReplica<'a> {
+    pub(crate) fn new(
+        store: &'a Mutex<Store>,
+        ring: &'a HashRing<SocketAddr>,
+        ranges: &'a [TokenRange],
+    ) -> LocalReplica<'a> {
+        LocalReplica {
+            store,
+            ring,
+            ranges,
+            trees: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn with_tree<T>(&self, range: TokenRange, f: impl FnOnce(&MerkleTree) -> T) -> T {
+        let mut trees = self.trees.borrow_mut();
+        if !trees.contains_key(&range) {
+            // any range other than this node's is built on its own
+            let mut missing: Vec<TokenRange> = self
+                .ranges
+                .iter()
+                .filter(|r| !trees.contains_key(r))
+                .copied()
+                .collect();
+            if !missing.contains(&range) {
+                missing = vec![range];
+            }
+            let store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+            let entries = store
+                .entries()
+                .map(|(key, value)| (self.ring.hash_key(key), key.as_str(), &value.version));
+            let built = MerkleTree::build_all(&missing, DEPTH, entries);
+            trees.extend(missing.into_iter().zip(built));
+        }
+        f(&trees[&range])
+    }
+}
+
+impl Replica for LocalReplica<'_> {
+    fn hashes(&self, range: TokenRange, level: u32, indexes: &[usize]) -> Result<Vec<u64>> {
+        Ok(self.with_tree(range, |tree| {
+            indexes
+                .iter()
+                .map(|i| tree.hash(level, *i).unwrap_or_default())
+                .collect()
+        }))
+    }
+
+    fn digests(&self, range: TokenRange, leaves: &[usize]) -> Result<Vec<(String, Version)>> {
+        let store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(store
+            .entries()
+            .filter(|(key, _)| {
+                let position = self.ring.hash_key(key);
+                range.contains(position) && leaves.contains(&merkle::leaf(range, DEPTH, position))
+            })
+            .map(|(key, value)| (key.clone(), value.version))
+            .collect())
+    }
+
+    fn read(&self, key: &str) -> Result<Option<Versioned>> {
+        let store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(store.get(key).cloned())
+    }
+
+    fn write(&self, key: String, value: Versioned) -> Result<()> {
+        let position = self.ring.hash_key(&key);
+        self.trees.borrow_mut().retain(|range, _| !range.contains(position));
+        let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        store.put(key, value);
+        Ok(())
+    }
+}
+
+/// Another node, over the network. Every request goes over the same
+/// connection, so the node can keep the trees it builds for the rest
+/// of the sync, and a new one is only opened once that fails.
+pub struct RemoteReplica {
+    addr: SocketAddr,
+    timeout: Duration,
+    conn: RefCell<Option<Connection>>,
+}
+
+impl RemoteReplica {
+    pub fn new(addr: SocketAddr, timeout: Duration) -> RemoteReplica {
+        RemoteReplica {
+            addr,
+            timeout,
+            conn: RefCell::new(None),
+        }
+    }
+
+    fn call(&self, req: Request) -> Result<Response> {
+        let mut conn = self.conn.borrow_mut();
+        let mut open = match conn.take() {
+            Some(open) => open,
+            None => Connection::open(self.addr, self.timeout)?,
+        };
+        let res = open.call(&req);
+        // a connection that failed may be out of step, so isn't reused
+        if res.is_ok() {
+            *conn = Some(open);
+        }
+        res
+    }
+}
+
+fn unexpected(res: Response) -> Error {
+    Error::Protocol(format!("unexpected response: {:?}", res))
+}
+
+impl Replica for RemoteReplica {
+    fn hashes(&self, range: TokenRange, level: u32, indexes: &[usize]) -> Result<Vec<u64>> {
+        let req = Request::Hashes {
+            range,
+            level,
+            indexes: indexes.to_vec(),
+        };
+        match self.call(req)? {
+            Response::Hashes(hashes) if hashes.len() == indexes.len() => Ok(hashes),
+            res => Err(unexpected(res)),
+        }
+    }
+
+    fn digests(&self, range: TokenRange, leaves: &[usize]) -> Result<Vec<(String, Version)>> {
+        let req = Request::Digests {
+            range,
+            leaves: leaves.to_vec(),
+        };
+        match self.call(req)? {
+            Response::Digests(digests) => Ok(digests),
+            res => Err(unexpected(res)),
+        }
+    }
+
+    fn read(&self, key: &str) -> Result<Option<Versioned>> {
+        match self.call(Request::Read {
+            key: key.to_string(),
+        })? {
+            Response::Value(v) => Ok(v),
+            res => Err(unexpected(res)),
+        }
+    }
+
+    fn write(&self, key: String, value: Versioned) -> Result<()> {
+        match self.call(Request::Replicate { key, value })? {
+            Response::Stored => Ok(()),
+            res => Err(unexpected(res)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+
+    use ringhash::rebalance::TokenRange;
+    use ringhash::HashRing;
+
+    use super::{sync, LocalReplica, Replica, SyncStats};
+    use crate::protocol::{Version, Versioned};
+    use crate::store::Store;
+
+    const FULL: TokenRange = TokenRange {
+        start: 0,
+        end: u64::MAX,
+    };
+
+    fn versioned(value: &str, timestamp: u64) -> Versioned {
+        Versioned {
+            value: value.as_bytes().to_vec(),
+            version: Version {
+                timestamp,
+                coordinator: "127.0.0.1:4000".parse().unwrap(),
+            },
+        }
+    }
+
+    fn store(keys: u32) -> Mutex<Store> {
+        let mut store = Store::new();
+        for i in 0..keys {
+            store.put(format!("key-{}", i), versioned("v1", 1));
+        }
+        Mutex::new(store)
+    }
+
+    fn contents(store: &Mutex<Store>) -> Vec<(String, Versioned)> {
+        let store = store.lock().unwrap();
+        let mut entries: Vec<_> = store.entries().map(|(k, v)| (k.clone(), v.clone())).collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    #[test]
+    fn replicas_in_sync_only_compare_roots() {
+        let ring = HashRing::<SocketAddr>::new();
+        let (a, b) = (store(500), store(500));
+        let local = LocalReplica::new(&a, &ring, &[]);
+        let stats = sync(&local, &LocalReplica::new(&b, &ring, &[]), FULL).unwrap();
+        assert_eq!(
+            stats,
+            SyncStats {
+                levels: 1,
+                pulled: 0,
+                pushed: 0
+            }
+        );
+    }
+
+    #[test]
+    fn diverged_replicas_converge() {
+        let ring = HashRing::<SocketAddr>::new();
+        let (a, b) = (store(2000), store(2000));
+
+        // a missed some writes, b lost some keys, and both
+        // took writes the other didn't see
+        {
+            let mut a = a.lock().unwrap();
+            let mut b = b.lock().unwrap();
+            for i in 0..10 {
+                b.put(format!("key-{}", i), versioned("v2", 2));
+            }
+            *b = {
+                let mut kept = Store::new();
+                for (k, v) in b.entries().filter(|(k, _)| !k.ends_with("99")) {
+                    kept.put(k.clone(), v.clone());
+                }
+                kept
+            };
+            a.put("key-new-a".to_string(), versioned("a", 3));
+            b.put("key-new-b".to_string(), versioned("b", 3));
+        }
+
+        let local = LocalReplica::new(&a, &ring, &[]);
+        let remote = LocalReplica::new(&b, &ring, &[]);
+        let stats = sync(&local, &remote, FULL).unwrap();
+
+        // only the keys that differ were copied
+        assert_eq!(stats.pulled, 10 + 1);
+        assert_eq!(stats.pushed, 20 + 1);
+        assert_eq!(contents(&a), contents(&b));
+        assert_eq!(contents(&a).len(), 2002);
+
+        assert_eq!(sync(&local, &remote, FULL).unwrap().levels, 1);
+    }
+
+    #[test]
+    fn trees_are_built_together_and_kept() {
+        let ring = HashRing::<SocketAddr>::new();
+        let a = store(200);
+        let ranges = [
+            TokenRange {
+                start: 0,
+                end: u64::MAX / 2,
+            },
+            TokenRange {
+                start: u64::MAX / 2 + 1,
+                end: u64::MAX,
+            },
+        ];
+        let local = LocalReplica::new(&a, &ring, &ranges);
+        let root = local.hashes(ranges[0], 0, &[0]).unwrap();
+        assert_eq!(local.trees.borrow().len(), 2);
+
+        // until a write lands in one
+        local.write("key-0".to_string(), versioned("v2", 2)).unwrap();
+        assert_eq!(local.trees.borrow().len(), 1);
+        let range = ranges.iter().find(|r| r.contains(ring.hash_key(&"key-0"))).unwrap();
+        assert!(!local.trees.borrow().contains_key(range));
+        assert_eq!(local.hashes(ranges[0], 0, &[0]).unwrap() == root, *range != ranges[0]);
+    }
+
+    #[test]
+    fn only_syncs_the_range() {
+        let ring = HashRing::<SocketAddr>::new();
+        let (a, b) = (store(200), Mutex::new(Store::new()));
+        let half = TokenRange {
+            start: 0,
+            end: u64::MAX / 2,
+        };
+        let local = LocalReplica::new(&a, &ring, &[]);
+        sync(&local, &LocalReplica::new(&b, &ring, &[]), half).unwrap();
+
+        let copied = contents(&b);
+        assert!(!copied.is_empty() && copied.len() < 200);
+        assert!(copied.iter().all(|(k, _)| half.contains(ring.hash_key(k))));
+    }
+
+}
@@ -0,0 +1,215 @@
+//! Runs a four node cluster as separate processes on localhost
+
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use kv::Client;
+use ringhash::HashRing;
+
+const VNODES: u32 = 64;
+
+struct Node {
+    addr: SocketAddr,
+    peers: Vec<SocketAddr>,
+    anti_entropy: Duration,
+    child: Option<Child>,
+}
+
+impl Node {
+    fn start(addr: SocketAddr, peers: Vec<SocketAddr>, anti_entropy: Duration) -> Node {
+        let mut node = Node {
+            addr,
+            peers,
+            anti_entropy,
+            child: None,
+        };
+        node.restart();
+        node
+    }
+
+    fn restart(&mut self) {
+        let peers: Vec<String> = self.peers.iter().map(|p| p.to_string()).collect();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_kv"))
+            .args(["--bind", &self.addr.to_string(), "--peers", &peers.join(",")])
+            .args(["--n", "3", "--r", "2", "--w", "2", "--vnodes", &VNODES.to_string()])
+            .args(["--anti-entropy", &self.anti_entropy.as_millis().to_string()])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!(line.trim(), format!("listening on {}", self.addr));
+        self.child = Some(child);
+    }
+
+    fn kill(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    fn client(&self) -> Client {
+        Client::new(self.addr)
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+fn cluster() -> Vec<Node> {
+    // rarely enough not to interfere
+    cluster_syncing_every(Duration::from_secs(60))
+}
+
+fn cluster_syncing_every(anti_entropy: Duration) -> Vec<Node> {
+    // let the OS pick free ports
+    let addrs: Vec<SocketAddr> = (0..4)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap())
+        .collect();
+    addrs
+        .iter()
+        .map(|addr| {
+            let peers = addrs.iter().filter(|a| *a != addr).copied().collect();
+            Node::start(*addr, peers, anti_entropy)
+        })
+        .collect()
+}
+
+// indexes of the key's three replicas, and of the one other node
+fn placement(nodes: &[Node], key: &str) -> (Vec<usize>, usize) {
+    let mut ring = HashRing::with_replicas(VNODES);
+    for node in nodes {
+        ring.add(node.addr);
+    }
+    let index = |addr: &SocketAddr| nodes.iter().position(|n| n.addr == *addr).unwrap();
+    let replicas: Vec<usize> = ring.get_n(&key, 3).into_iter().map(index).collect();
+    let other = (0..nodes.len()).find(|i| !replicas.contains(i)).unwrap();
+    (replicas, other)
+}
+
+fn stored_on(node: &Node, key: &str) -> Option<Vec<u8>> {
+    node.client().read_local(key).unwrap().map(|v| v.value)
+}
+
+fn wait_for<F: FnMut() -> bool>(mut f: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if f() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+#[test]
+fn writes_go_to_the_preference_list() {
+    let nodes = cluster();
+    let key = "user:1";
+    let (replicas, other) = placement(&nodes, key);
+
+    // any node can coordinate
+    nodes[other].client().put(key, b"ada").unwrap();
+    for node in nodes.iter() {
+        assert_eq!(node.client().get(key).unwrap(), Some(b"ada".to_vec()));
+    }
+
+    for i in replicas {
+        assert_eq!(stored_on(&nodes[i], key), Some(b"ada".to_vec()));
+    }
+    assert_eq!(stored_on(&nodes[other], key), None);
+    assert_eq!(nodes[0].client().get("user:2").unwrap(), None);
+}
+
+#[test]
+fn sloppy_quorum_and_hinted_handoff() {
+    let mut nodes = cluster();
+    let key = "user:1";
+    let (replicas, other) = placement(&nodes, key);
+    let (up, down) = (replicas[0], replicas[1]);
+
+    // with only one replica left, the write needs the
+    // other node to stand in for one that's down
+    nodes[replicas[1]].kill();
+    nodes[replicas[2]].kill();
+    nodes[up].client().put(key, b"grace").unwrap();
+    assert_eq!(nodes[other].client().get(key).unwrap(), Some(b"grace".to_vec()));
+    assert_eq!(stored_on(&nodes[other], key), Some(b"grace".to_vec()));
+
+    // the hint finds its way back once the replica is
+    nodes[down].restart();
+    assert!(wait_for(|| stored_on(&nodes[down], key) == Some(b"grace".to_vec())));
+}
+
+#[test]
+fn quorum_failures_are_reported() {
+    let mut nodes = cluster();
+    let key = "user:1";
+    let (replicas, _) = placement(&nodes, key);
+    nodes[0].client().put(key, b"alan").unwrap();
+
+    // with three nodes down only one can answer
+    let survivor = replicas[0];
+    for (i, node) in nodes.iter_mut().enumerate() {
+        if i != survivor {
+            node.kill();
+        }
+    }
+    match nodes[survivor].client().get(key) {
+        Err(kv::Error::Unavailable { needed: 2, answered: 1 }) => {}
+        res => panic!("expected the read to fail, got {:?}", res),
+    }
+    assert!(nodes[survivor].client().put(key, b"barbara").is_err());
+}
+
+#[test]
+fn reads_repair_stale_replicas() {
+    let mut nodes = cluster();
+    let key = "user:1";
+    let (replicas, other) = placement(&nodes, key);
+    nodes[other].client().put(key, b"edsger").unwrap();
+
+    // a restarted replica has lost everything
+    let lost = replicas[2];
+    nodes[lost].kill();
+    nodes[lost].restart();
+    assert_eq!(stored_on(&nodes[lost], key), None);
+
+    assert_eq!(nodes[other].client().get(key).unwrap(), Some(b"edsger".to_vec()));
+    assert!(wait_for(|| stored_on(&nodes[lost], key) == Some(b"edsger".to_vec())));
+}
+
+#[test]
+fn anti_entropy_restores_a_wiped_replica() {
+    let mut nodes = cluster_syncing_every(Duration::from_millis(300));
+    let keys: Vec<String> = (0..100).map(|i| format!("user:{}", i)).collect();
+    for key in keys.iter() {
+        nodes[0].client().put(key, key.as_bytes()).unwrap();
+    }
+
+    // lose everything on one node, without reading anything back
+    nodes[1].kill();
+    nodes[1].restart();
+    let owned: Vec<&String> = keys
+        .iter()
+        .filter(|k| placement(&nodes, k).0.contains(&1))
+        .collect();
+    assert!(!owned.is_empty());
+
+    assert!(wait_for(|| owned
+        .iter()
+        .all(|k| stored_on(&nodes[1], k) == Some(k.as_bytes().to_vec()))));
+    // and nothing it isn't a replica for
+    for key in keys.iter().filter(|k| !owned.contains(k)) {
+        assert_eq!(stored_on(&nodes[1], key), None);
+    }
+}
@@ -0,0 +1,207 @@
+//! Maglev hashing, from Section 3.4 of Eisenbud _et al_,
+//! "Maglev: A Fast and Reliable Software Network Load Balancer".
+//!
+//! Each node has its own permutation of the slots in a fixed size
+//! lookup table, and nodes take turns claiming their next free
+//! preferred slot until the table is full. Lookups are a single index
+//! into the table, and every node owns an almost equal number of slots.
+//! The trade off is that a membership change rebuilds the table and
+//! moves slightly more keys than the minimum.
+
+use std::hash::{BuildHasher, Hash};
+
+use ringhash::hash::BuildXxHash64;
+use ringhash::ConsistentHasher;
+
+/// The table size suggested by the paper, it must be prime
+/// and should be much larger than the number of nodes
+pub const DEFAULT_TABLE_SIZE: usize = 65_537;
+
+pub struct Maglev<N, S = BuildXxHash64> {
+    // kept sorted by hash so the table doesn't
+    // depend on the order nodes were added in
+    nodes: Vec<(u64, N)>,
+    // the index into `nodes` owning each slot
+    table: Vec<usize>,
+    table_size: usize,
+    build_hasher: S,
+}
+
+impl<N: Hash> Default for Maglev<N> {
+    fn default() -> Self {
+        Maglev::new()
+    }
+}
+
+impl<N: Hash> Maglev<N> {
+    pub fn new() -> Maglev<N> {
+        Maglev::with_table_size(DEFAULT_TABLE_SIZE)
+    }
+
+    pub fn with_table_size(table_size: usize) -> Maglev<N> {
+        Maglev::with_table_size_and_hasher(table_size, BuildXxHash64::default())
+    }
+}
+
+fn is_prime(n: usize) -> bool {
+    n >= 2 && (2..).take_while(|i| i * i <= n).all(|i| !n.is_multiple_of(i))
+}
+
+impl<N: Hash, S: BuildHasher> Maglev<N, S> {
+    pub fn with_table_size_and_hasher(table_size: usize, build_hasher: S) -> Maglev<N, S> {
+        assert!(is_prime(table_size), "the lookup table size must be prime");
+        Maglev {
+            nodes: vec![],
+            table: vec![],
+            table_size,
+            build_hasher,
+        }
+    }
+
+    /// The number of slots owned by each node, in hash order
+    pub fn slots(&self) -> Vec<(&N, usize)> {
+        let mut counts = vec![0; self.nodes.len()];
+        for idx in self.table.iter() {
+            counts[*idx] += 1;
+        }
+        self.nodes
+            .iter()
+            .zip(counts)
+            .map(|((_, n), c)| (n, c))
+            .collect()
+    }
+
+    // Algorithm 1 of the paper
+    fn populate(&mut self) {
+        self.table.clear();
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let m = self.table_size as u64;
+        let permutations: Vec<(u64, u64)> = self
+            .nodes
+            .iter()
+            .map(|(id, _)| {
+                let offset = self.build_hasher.hash_one((*id, 0u8)) % m;
+                let skip = self.build_hasher.hash_one((*id, 1u8)) % (m - 1) + 1;
+                (offset, skip)
+            })
+            .collect();
+
+        let mut next = vec![0u64; self.nodes.len()];
+        let mut entry: Vec<Option<usize>> = vec![None; self.table_size];
+        let mut filled = 0;
+        loop {
+            for (i, (offset, skip)) in permutations.iter().enumerate() {
+                let mut c = (offset + next[i] * skip) % m;
+                while entry[c as usize].is_some() {
+                    next[i] += 1;
+                    c = (offset + next[i] * skip) % m;
+                }
+                entry[c as usize] = Some(i);
+                next[i] += 1;
+                filled += 1;
+                if filled == self.table_size {
+                    self.table = entry.into_iter().map(|e| e.unwrap()).collect();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<N: Hash, S: BuildHasher> ConsistentHasher<N> for Maglev<N, S> {
+    fn add(&mut self, node: N) {
+        let id = self.build_hasher.hash_one(&node);
+        if let Err(pos) = self.nodes.binary_search_by_key(&id, |(other, _)| *other) {
+            self.nodes.insert(pos, (id, node));
+            self.populate();
+        }
+    }
+
+    fn remove(&mut self, node: &N) -> Option<N> {
+        let id = self.build_hasher.hash_one(node);
+        let pos = self
+            .nodes
+            .binary_search_by_key(&id, |(other, _)| *other)
+            .ok()?;
+        let (_, removed) = self.nodes.remove(pos);
+        self.populate();
+        Some(removed)
+    }
+
+    fn get<K: Hash>(&self, k: &K) -> Option<&N> {
+        if self.table.is_empty() {
+            return None;
+        }
+        let slot = self.build_hasher.hash_one(k) % self.table_size as u64;
+        Some(&self.nodes[self.table[slot as usize]].1)
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use ringhash::ConsistentHasher;
+
+    use super::Maglev;
+
+    #[test]
+    fn slots_are_balanced() {
+        let mut maglev = Maglev::new();
+        for node in 0..10u32 {
+            maglev.add(node);
+        }
+
+        // every node gets M / N slots, give or take one
+        for (_, count) in maglev.slots() {
+            assert!((count as i64 - 6553).abs() <= 1, "{}", count);
+        }
+    }
+
+    #[test]
+    fn removing_a_node_moves_few_other_keys() {
+        let mut maglev = Maglev::new();
+        for node in 0..10u32 {
+            maglev.add(node);
+        }
+        let before: Vec<u32> = (0..20_000u32).map(|k| *maglev.get(&k).unwrap()).collect();
+
+        assert_eq!(maglev.remove(&4), Some(4));
+        let mut disrupted = 0;
+        for (k, old) in before.iter().enumerate() {
+            let new = *maglev.get(&(k as u32)).unwrap();
+            assert_ne!(new, 4);
+            if *old != 4 && *old != new {
+                disrupted += 1;
+            }
+        }
+        // the paper sees a few percent of the other keys move
+        assert!(disrupted < before.len() / 20, "{} keys moved", disrupted);
+    }
+
+    #[test]
+    fn table_is_independent_of_insertion_order() {
+        let mut a = Maglev::with_table_size(251);
+        let mut b = Maglev::with_table_size(251);
+        for node in 0..5u32 {
+            a.add(node);
+            b.add(4 - node);
+        }
+        for k in 0..1000u32 {
+            assert_eq!(a.get(&k), b.get(&k));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn table_size_must_be_prime() {
+        Maglev::<u32>::with_table_size(1000);
+    }
+
+}
@@ -0,0 +1,292 @@
+//! SWIM style membership, following Das _et al_, "SWIM: Scalable
+//! Weakly-consistent Infection-style Process Group Membership Protocol".
+//!
+//! Every protocol period each member pings one other member. If no ack
+//! arrives in time it asks a few others to ping it on its behalf, and
+//! only if none of them get an ack either is the member suspected.
+//! Suspected members have a while to refute it before being declared
+//! dead. Membership changes are gossiped by piggybacking them onto the
+//! pings and acks, so there is no extra traffic to spread them.
+//!
+//! A `Node` keeps a `HashRing` of the members it believes are up, so
+//! keys are placed on exactly the nodes the cluster can currently reach.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use ringhash::shared::SharedRing;
+use ringhash::HashRing;
+
+mod message;
+mod swim;
+
+use message::Message;
+use swim::{Rng, Swim};
+
+// large enough for the member list sent to a joining node
+const BUFFER_SIZE: usize = 65_507;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How often each member probes another
+    pub protocol_period: Duration,
+    /// How long to wait for a direct ack before asking others to
+    /// probe, which should leave time for them within the period
+    pub ack_timeout: Duration,
+    /// How many members are asked to probe on our behalf
+    pub indirect_probes: usize,
+    /// How long a member is suspected before it's declared dead
+    pub suspicion_timeout: Duration,
+    /// The most updates piggybacked onto a single message
+    pub max_piggyback: usize,
+    /// Virtual nodes given to each member on the ring
+    pub replicas: u32,
+    /// The chance of dropping each outgoing packet, to test
+    /// how the protocol copes with an unreliable network
+    pub loss: f64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            protocol_period: Duration::from_millis(200),
+            ack_timeout: Duration::from_millis(60),
+            indirect_probes: 3,
+            suspicion_timeout: Duration::from_secs(1),
+            max_piggyback: 8,
+            replicas: 100,
+            loss: 0.0,
+        }
+    }
+}
+
+/// A change in what this node believes about another member
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Joined(SocketAddr),
+    Suspected(SocketAddr),
+    /// A suspected member refuted it
+    Recovered(SocketAddr),
+    /// The member failed or left
+    Removed(SocketAddr),
+}
+
+pub struct Node {
+    addr: SocketAddr,
+    socket: UdpSocket,
+    swim: Arc<Mutex<Swim>>,
+    ring: Arc<SharedRing<SocketAddr>>,
+    events: Receiver<Event>,
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Node {
+    /// Binds `addr` and starts taking part in the protocol, joining
+    /// the cluster through whichever of `seeds` answers first
+    pub fn start<A: ToSocketAddrs>(addr: A, seeds: &[SocketAddr], config: Config) -> io::Result<Node> {
+        let socket = UdpSocket::bind(addr)?;
+        let addr = socket.local_addr()?;
+        // wake up often enough to notice timeouts
+        let wake = (config.ack_timeout / 4).max(Duration::from_millis(1));
+        socket.set_read_timeout(Some(wake))?;
+
+        let ring = Arc::new(SharedRing::new(HashRing::with_replicas(config.replicas)));
+        ring.add(addr);
+
+        let loss = config.loss;
+        let swim = Swim::new(addr, seeds.to_vec(), config, Instant::now());
+        let swim = Arc::new(Mutex::new(swim));
+        let running = Arc::new(AtomicBool::new(true));
+        let (tx, events) = mpsc::channel();
+
+        let worker = Worker {
+            socket: socket.try_clone()?,
+            swim: swim.clone(),
+            ring: ring.clone(),
+            events: tx,
+            rng: Rng::new(u64::from(addr.port())),
+            loss,
+        };
+        let r = running.clone();
+        let handle = thread::spawn(move || worker.run(&r));
+
+        Ok(Node {
+            addr,
+            socket,
+            swim,
+            ring,
+            events,
+            running,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Every member this node believes is up, itself included
+    pub fn members(&self) -> Vec<SocketAddr> {
+        self.lock().members()
+    }
+
+    /// A ring of the members in `members`, kept up to date
+    pub fn ring(&self) -> Arc<SharedRing<SocketAddr>> {
+        self.ring.clone()
+    }
+
+    /// Membership changes as this node sees them
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+
+    /// Tells every other member this node is leaving, so they remove
+    /// it straight away rather than waiting to detect the failure
+    pub fn leave(mut self) {
+        let outbox = {
+            let mut swim = self.lock();
+            swim.leave();
+            swim.take_outbox()
+        };
+        self.stop();
+        for (to, msg) in outbox {
+            let _ = self.socket.send_to(&msg.encode(), to);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Swim> {
+        self.swim.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Node {
+    // stops answering, which the others will see as a failure
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Worker {
+    socket: UdpSocket,
+    swim: Arc<Mutex<Swim>>,
+    ring: Arc<SharedRing<SocketAddr>>,
+    events: Sender<Event>,
+    rng: Rng,
+    loss: f64,
+}
+
+impl Worker {
+    fn run(mut self, running: &AtomicBool) {
+        let mut buf = vec![0; BUFFER_SIZE];
+        while running.load(Ordering::SeqCst) {
+            // times out regularly, so either way we get to tick
+            let received = self.socket.recv_from(&mut buf).ok();
+
+            let (outbox, events) = {
+                let mut swim = self.swim.lock().unwrap_or_else(|e| e.into_inner());
+                let now = Instant::now();
+                if let Some((amt, src)) = received {
+                    if let Some(msg) = Message::decode(&buf[..amt]) {
+                        swim.handle(src, msg, now);
+                    }
+                }
+                swim.tick(now);
+                (swim.take_outbox(), swim.take_events())
+            };
+
+            for (to, msg) in outbox {
+                if !self.rng.chance(self.loss) {
+                    let _ = self.socket.send_to(&msg.encode(), to);
+                }
+            }
+            for event in events {
+                match event {
+                    Event::Joined(addr) => self.ring.add(addr),
+                    Event::Removed(addr) => {
+                        self.ring.remove(&addr);
+                    }
+                    // suspects keep their keys until they're removed
+                    Event::Suspected(_) | Event::Recovered(_) => {}
+                }
+                let _ = self.events.send(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{Config, Node};
+
+    fn config() -> Config {
+        Config {
+            protocol_period: Duration::from_millis(50),
+            ack_timeout: Duration::from_millis(15),
+            suspicion_timeout: Duration::from_millis(400),
+            replicas: 10,
+            loss: 0.1,
+            ..Config::default()
+        }
+    }
+
+    fn wait_for<F: Fn() -> bool>(f: F) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if f() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn ring_follows_membership() {
+        let seed = Node::start("127.0.0.1:0", &[], config()).unwrap();
+        let mut nodes: Vec<Node> = (0..3)
+            .map(|_| Node::start("127.0.0.1:0", &[seed.addr()], config()).unwrap())
+            .collect();
+        nodes.push(seed);
+
+        let ring_sizes = |nodes: &[Node], n: usize| {
+            nodes
+                .iter()
+                .all(|node| node.members().len() == n && node.ring().snapshot().len() == n)
+        };
+        assert!(wait_for(|| ring_sizes(&nodes, 4)));
+
+        // every node places keys the same way
+        for k in 0..100u32 {
+            let owner = nodes[0].ring().get(&k);
+            assert!(nodes.iter().all(|n| n.ring().get(&k) == owner));
+        }
+
+        let crashed = nodes.remove(1);
+        let addr = crashed.addr();
+        drop(crashed);
+        assert!(wait_for(|| ring_sizes(&nodes, 3)));
+        assert!(nodes.iter().all(|n| n.ring().snapshot().weight(&addr).is_none()));
+
+        let left = nodes.remove(0);
+        left.leave();
+        assert!(wait_for(|| ring_sizes(&nodes, 2)));
+    }
+
+}
@@ -0,0 +1,119 @@
+use std::env;
+use std::net::SocketAddr;
+use std::process;
+use std::time::Duration;
+
+use membership::{Config, Event, Node};
+
+const USAGE: &str = "usage: membership [--bind ADDR] [--seeds ADDR,..] [--period MS] [--loss P]
+
+Runs one member, joining through the seeds, and prints a line
+for every membership change it sees. --loss drops each packet
+with probability P, between 0 and 1.";
+
+struct Args {
+    bind: String,
+    seeds: Vec<SocketAddr>,
+    config: Config,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut parsed = Args {
+        bind: "127.0.0.1:0".to_string(),
+        seeds: vec![],
+        config: Config::default(),
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        let invalid = || format!("invalid value for {}: {}", flag, value);
+        match flag.as_str() {
+            "--bind" => parsed.bind = value.clone(),
+            "--seeds" => {
+                parsed.seeds = value
+                    .split(',')
+                    .map(|s| s.parse().map_err(|_| invalid()))
+                    .collect::<Result<_, _>>()?
+            }
+            "--period" => {
+                let period = Duration::from_millis(value.parse().map_err(|_| invalid())?);
+                // keep the other timeouts in proportion
+                parsed.config.protocol_period = period;
+                parsed.config.ack_timeout = period * 3 / 10;
+                parsed.config.suspicion_timeout = period * 5;
+            }
+            "--loss" => {
+                // a probability, which NaN isn't either
+                let loss: f64 = value.parse().map_err(|_| invalid())?;
+                if !(0.0..=1.0).contains(&loss) {
+                    return Err(invalid());
+                }
+                parsed.config.loss = loss;
+            }
+            _ => return Err(format!("unknown flag: {}", flag)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = match parse_args(&args) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+
+    let node = match Node::start(&args.bind, &args.seeds, args.config) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("couldn't bind to {}: {}", args.bind, e);
+            process::exit(1);
+        }
+    };
+    println!("listening on {}", node.addr());
+
+    for event in node.events().iter() {
+        match event {
+            Event::Joined(addr) => println!("joined {}", addr),
+            Event::Suspected(addr) => println!("suspected {}", addr),
+            Event::Recovered(addr) => println!("recovered {}", addr),
+            Event::Removed(addr) => println!("removed {}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_flags() {
+        let parsed = parse_args(&args("--seeds 127.0.0.1:7000 --period 100 --loss 0.25")).unwrap();
+        assert_eq!(parsed.seeds, vec!["127.0.0.1:7000".parse().unwrap()]);
+        assert_eq!(parsed.config.protocol_period, Duration::from_millis(100));
+        assert_eq!(parsed.config.loss, 0.25);
+        assert_eq!(parse_args(&args("--loss 1")).unwrap().config.loss, 1.0);
+    }
+
+    #[test]
+    fn rejects_bad_flags() {
+        assert!(parse_args(&args("--period")).is_err());
+        assert!(parse_args(&args("--period soon")).is_err());
+        assert!(parse_args(&args("--seeds nowhere")).is_err());
+        assert!(parse_args(&args("--loss 5")).is_err());
+        assert!(parse_args(&args("--loss -1")).is_err());
+        assert!(parse_args(&args("--loss NaN")).is_err());
+        assert!(parse_args(&args("--verbose true")).is_err());
+    }
+
+}
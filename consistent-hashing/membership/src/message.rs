@@ -0,0 +1,80 @@
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// What one member believes about another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum State {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+/// A membership change, gossiped by piggybacking it onto protocol
+/// messages. The incarnation is only ever bumped by the member itself,
+/// to refute suspicion, so it orders updates about the same member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Update {
+    pub addr: SocketAddr,
+    pub incarnation: u64,
+    pub state: State,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Kind {
+    /// Asks a seed for its full member list
+    Join,
+    /// A full member list, in reply to `Join`
+    Sync(Vec<Update>),
+    Ping { seq: u64 },
+    Ack { seq: u64 },
+    /// Asks the receiver to ping `target` on the sender's behalf
+    /// and forward the ack
+    PingReq { seq: u64, target: SocketAddr },
+    /// Only carries updates, nothing to reply to
+    Gossip,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    // the sender's own incarnation
+    pub incarnation: u64,
+    pub kind: Kind,
+    pub updates: Vec<Update>,
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("messages are serializable")
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Message> {
+        bincode::deserialize(buf).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Kind, Message, State, Update};
+
+    #[test]
+    fn round_trips() {
+        let msg = Message {
+            incarnation: 3,
+            kind: Kind::PingReq {
+                seq: 42,
+                target: "127.0.0.1:7001".parse().unwrap(),
+            },
+            updates: vec![Update {
+                addr: "127.0.0.1:7002".parse().unwrap(),
+                incarnation: 1,
+                state: State::Suspect,
+            }],
+        };
+        let buf = msg.encode();
+        assert_eq!(Message::decode(&buf), Some(msg));
+        assert_eq!(Message::decode(&buf[..buf.len() - 1]), None);
+    }
+
+}
@@ -0,0 +1,657 @@
+//! The SWIM protocol itself, without any I/O. Incoming messages are
+//! passed to `handle`, time moves on through `tick`, and the messages
+//! to send and membership changes to report pile up until taken.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::message::{Kind, Message, State, Update};
+use crate::{Config, Event};
+
+// every update is piggybacked LAMBDA * log2(n) times,
+// enough to reach every member with high probability
+const LAMBDA: u32 = 3;
+
+// how many suspicion timeouts a dead member is remembered for, so
+// that stale gossip about it can't bring it back
+const DEAD_RETENTION: u32 = 10;
+
+// xorshift64*, seeded per node
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub(crate) fn chance(&mut self, p: f64) -> bool {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64 <= p
+    }
+
+    fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            v.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+struct Member {
+    incarnation: u64,
+    state: State,
+    // when the state last changed
+    since: Instant,
+}
+
+struct Probe {
+    target: SocketAddr,
+    seq: u64,
+    sent: Instant,
+    acked: bool,
+    indirect: bool,
+}
+
+// a ping sent on behalf of another member
+struct Relay {
+    requester: SocketAddr,
+    seq: u64,
+    sent: Instant,
+}
+
+pub struct Swim {
+    addr: SocketAddr,
+    incarnation: u64,
+    config: Config,
+    members: HashMap<SocketAddr, Member>,
+    seeds: Vec<SocketAddr>,
+    // updates still to be piggybacked, with how many times each is left
+    gossip: Vec<(Update, u32)>,
+    seq: u64,
+    probe: Option<Probe>,
+    // members left to probe this round, in random order
+    probe_order: Vec<SocketAddr>,
+    next_period: Instant,
+    relays: HashMap<u64, Relay>,
+    rng: Rng,
+    outbox: Vec<(SocketAddr, Message)>,
+    events: Vec<Event>,
+}
+
+impl Swim {
+    pub fn new(addr: SocketAddr, seeds: Vec<SocketAddr>, config: Config, now: Instant) -> Swim {
+        let clock = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let seed = clock ^ u64::from(addr.port()) << 32;
+        Swim::with_rng(addr, seeds, config, now, Rng::new(seed))
+    }
+
+    pub(crate) fn with_rng(
+        addr: SocketAddr,
+        seeds: Vec<SocketAddr>,
+        config: Config,
+        now: Instant,
+        rng: Rng,
+    ) -> Swim {
+        Swim {
+            addr,
+            incarnation: 0,
+            config,
+            members: HashMap::new(),
+            seeds: seeds.into_iter().filter(|s| *s != addr).collect(),
+            gossip: vec![],
+            seq: 0,
+            probe: None,
+            probe_order: vec![],
+            next_period: now,
+            relays: HashMap::new(),
+            rng,
+            outbox: vec![],
+            events: vec![],
+        }
+    }
+
+    /// Every member believed to be up, suspects included, and this one
+    pub fn members(&self) -> Vec<SocketAddr> {
+        let mut members: Vec<SocketAddr> = self
+            .members
+            .iter()
+            .filter(|(_, m)| m.state != State::Dead)
+            .map(|(addr, _)| *addr)
+            .chain(Some(self.addr))
+            .collect();
+        members.sort();
+        members
+    }
+
+    pub fn take_outbox(&mut self) -> Vec<(SocketAddr, Message)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn handle(&mut self, from: SocketAddr, msg: Message, now: Instant) {
+        // hearing from a member at all is news that it's alive
+        self.apply(
+            Update {
+                addr: from,
+                incarnation: msg.incarnation,
+                state: State::Alive,
+            },
+            now,
+        );
+        for u in msg.updates {
+            self.apply(u, now);
+        }
+
+        match msg.kind {
+            Kind::Join => {
+                let members = self.snapshot();
+                self.send(from, Kind::Sync(members));
+            }
+            Kind::Sync(members) => {
+                for u in members {
+                    self.apply(u, now);
+                }
+            }
+            Kind::Ping { seq } => self.send(from, Kind::Ack { seq }),
+            Kind::Ack { seq } => {
+                if let Some(relay) = self.relays.remove(&seq) {
+                    self.send(relay.requester, Kind::Ack { seq: relay.seq });
+                } else if let Some(probe) = self.probe.as_mut().filter(|p| p.seq == seq) {
+                    probe.acked = true;
+                }
+            }
+            Kind::PingReq { seq, target } => {
+                let relayed = self.next_seq();
+                self.relays.insert(
+                    relayed,
+                    Relay {
+                        requester: from,
+                        seq,
+                        sent: now,
+                    },
+                );
+                self.send(target, Kind::Ping { seq: relayed });
+            }
+            Kind::Gossip => {}
+        }
+    }
+
+    pub fn tick(&mut self, now: Instant) {
+        // no direct ack in time, so ask others to try
+        let overdue = self
+            .probe
+            .as_ref()
+            .filter(|p| !p.acked && !p.indirect && now >= p.sent + self.config.ack_timeout)
+            .map(|p| (p.target, p.seq));
+        if let Some((target, seq)) = overdue {
+            for helper in self.random_members(self.config.indirect_probes, target) {
+                self.send(helper, Kind::PingReq { seq, target });
+            }
+            if let Some(p) = self.probe.as_mut() {
+                p.indirect = true;
+            }
+        }
+
+        self.expire(now);
+
+        if now < self.next_period {
+            return;
+        }
+        self.next_period = now + self.config.protocol_period;
+
+        // neither a direct nor an indirect ack within the period
+        if let Some(probe) = self.probe.take().filter(|p| !p.acked) {
+            if let Some(m) = self.members.get(&probe.target) {
+                if m.state == State::Alive {
+                    let suspect = Update {
+                        addr: probe.target,
+                        incarnation: m.incarnation,
+                        state: State::Suspect,
+                    };
+                    self.apply(suspect, now);
+                }
+            }
+        }
+
+        // keep asking the seeds until someone answers
+        if !self.seeds.is_empty() && self.members().len() == 1 {
+            for seed in self.seeds.clone() {
+                self.send(seed, Kind::Join);
+            }
+        }
+
+        if let Some(target) = self.next_target() {
+            let seq = self.next_seq();
+            self.probe = Some(Probe {
+                target,
+                seq,
+                sent: now,
+                acked: false,
+                indirect: false,
+            });
+            self.send(target, Kind::Ping { seq });
+        }
+    }
+
+    /// Announces this member is leaving, to every other member
+    pub fn leave(&mut self) {
+        let update = Update {
+            addr: self.addr,
+            incarnation: self.incarnation,
+            state: State::Dead,
+        };
+        for addr in self.members() {
+            if addr != self.addr {
+                let msg = self.message(Kind::Gossip, vec![update]);
+                self.outbox.push((addr, msg));
+            }
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let suspicion = self.config.suspicion_timeout;
+        let dead: Vec<Update> = self
+            .members
+            .iter()
+            .filter(|(_, m)| m.state == State::Suspect && now >= m.since + suspicion)
+            .map(|(addr, m)| Update {
+                addr: *addr,
+                incarnation: m.incarnation,
+                state: State::Dead,
+            })
+            .collect();
+        for u in dead {
+            self.apply(u, now);
+        }
+
+        let retention = suspicion * DEAD_RETENTION;
+        self.members
+            .retain(|_, m| m.state != State::Dead || now < m.since + retention);
+
+        let period = self.config.protocol_period;
+        self.relays.retain(|_, r| now < r.sent + period);
+    }
+
+    // applies an update if it's newer than what we know,
+    // passing it on and reporting what changed
+    fn apply(&mut self, u: Update, now: Instant) {
+        if u.addr == self.addr {
+            // someone thinks we're down, so refute it
+            if u.state != State::Alive && u.incarnation >= self.incarnation {
+                self.incarnation = u.incarnation + 1;
+                self.queue(Update {
+                    addr: self.addr,
+                    incarnation: self.incarnation,
+                    state: State::Alive,
+                });
+            }
+            return;
+        }
+
+        let previous = self.members.get(&u.addr).map(|m| (m.state, m.incarnation));
+        let newer = match previous {
+            None => true,
+            Some((state, incarnation)) => match (u.state, state) {
+                (_, State::Dead) => u.state == State::Alive && u.incarnation > incarnation,
+                (State::Alive, _) => u.incarnation > incarnation,
+                (State::Suspect, State::Alive) => u.incarnation >= incarnation,
+                (State::Suspect, State::Suspect) => u.incarnation > incarnation,
+                (State::Dead, _) => u.incarnation >= incarnation,
+            },
+        };
+        if !newer {
+            return;
+        }
+
+        self.members.insert(
+            u.addr,
+            Member {
+                incarnation: u.incarnation,
+                state: u.state,
+                since: now,
+            },
+        );
+        self.queue(u);
+
+        let event = match (previous.map(|(state, _)| state), u.state) {
+            (None, State::Dead) => None,
+            (None, _) | (Some(State::Dead), _) => Some(Event::Joined(u.addr)),
+            (Some(State::Alive), State::Suspect) => Some(Event::Suspected(u.addr)),
+            (Some(State::Suspect), State::Alive) => Some(Event::Recovered(u.addr)),
+            (Some(_), State::Dead) => Some(Event::Removed(u.addr)),
+            _ => None,
+        };
+        self.events.extend(event);
+    }
+
+    fn queue(&mut self, u: Update) {
+        self.gossip.retain(|(g, _)| g.addr != u.addr);
+        let n = self.members.len() as f64 + 1.0;
+        let transmits = LAMBDA * (n.log2().ceil() as u32).max(1);
+        self.gossip.push((u, transmits));
+    }
+
+    // the updates to piggyback onto the next message,
+    // least gossiped first
+    fn piggyback(&mut self) -> Vec<Update> {
+        self.gossip.sort_by_key(|(_, left)| std::cmp::Reverse(*left));
+        let updates = self
+            .gossip
+            .iter_mut()
+            .take(self.config.max_piggyback)
+            .map(|(u, left)| {
+                *left -= 1;
+                *u
+            })
+            .collect();
+        self.gossip.retain(|(_, left)| *left > 0);
+        updates
+    }
+
+    fn send(&mut self, to: SocketAddr, kind: Kind) {
+        let mut updates = self.piggyback();
+        // a member we think is dead needs to hear it, to refute it
+        if let Some(m) = self.members.get(&to).filter(|m| m.state == State::Dead) {
+            updates.push(Update {
+                addr: to,
+                incarnation: m.incarnation,
+                state: State::Dead,
+            });
+        }
+        let msg = self.message(kind, updates);
+        self.outbox.push((to, msg));
+    }
+
+    fn message(&self, kind: Kind, updates: Vec<Update>) -> Message {
+        Message {
+            incarnation: self.incarnation,
+            kind,
+            updates,
+        }
+    }
+
+    // everything we know, dead members included
+    fn snapshot(&self) -> Vec<Update> {
+        self.members
+            .iter()
+            .map(|(addr, m)| Update {
+                addr: *addr,
+                incarnation: m.incarnation,
+                state: m.state,
+            })
+            .chain(Some(Update {
+                addr: self.addr,
+                incarnation: self.incarnation,
+                state: State::Alive,
+            }))
+            .collect()
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn is_up(&self, addr: &SocketAddr) -> bool {
+        self.members
+            .get(addr)
+            .is_some_and(|m| m.state != State::Dead)
+    }
+
+    // members are probed round robin in a random order, so each
+    // one is probed within a bounded number of periods
+    fn next_target(&mut self) -> Option<SocketAddr> {
+        loop {
+            if self.probe_order.is_empty() {
+                self.probe_order = self.members().into_iter().filter(|a| *a != self.addr).collect();
+                if self.probe_order.is_empty() {
+                    return None;
+                }
+                let mut order = std::mem::take(&mut self.probe_order);
+                self.rng.shuffle(&mut order);
+                self.probe_order = order;
+            }
+            let target = self.probe_order.pop()?;
+            if self.is_up(&target) {
+                return Some(target);
+            }
+        }
+    }
+
+    fn random_members(&mut self, n: usize, exclude: SocketAddr) -> Vec<SocketAddr> {
+        let mut candidates: Vec<SocketAddr> = self
+            .members()
+            .into_iter()
+            .filter(|a| *a != self.addr && *a != exclude)
+            .collect();
+        self.rng.shuffle(&mut candidates);
+        candidates.truncate(n);
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashSet;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use super::{Rng, Swim};
+    use crate::message::{Kind, Message, State, Update};
+    use crate::{Config, Event};
+
+    const STEP: Duration = Duration::from_millis(5);
+
+    fn config() -> Config {
+        Config {
+            protocol_period: Duration::from_millis(100),
+            ack_timeout: Duration::from_millis(30),
+            suspicion_timeout: Duration::from_millis(500),
+            ..Config::default()
+        }
+    }
+
+    fn addr(i: usize) -> SocketAddr {
+        format!("127.0.0.1:{}", 7000 + i).parse().unwrap()
+    }
+
+    // members on a simulated network, where each packet takes one
+    // step to arrive and may be lost
+    struct Network {
+        nodes: Vec<Swim>,
+        crashed: HashSet<SocketAddr>,
+        in_flight: Vec<(SocketAddr, SocketAddr, Message)>,
+        events: Vec<(SocketAddr, Event)>,
+        loss: f64,
+        rng: Rng,
+        now: Instant,
+    }
+
+    impl Network {
+        // node 0 is everyone's seed
+        fn new(n: usize, loss: f64) -> Network {
+            let now = Instant::now();
+            let nodes = (0..n)
+                .map(|i| Swim::with_rng(addr(i), vec![addr(0)], config(), now, Rng::new(i as u64 + 1)))
+                .collect();
+            Network {
+                nodes,
+                crashed: HashSet::new(),
+                in_flight: vec![],
+                events: vec![],
+                loss,
+                rng: Rng::new(42),
+                now,
+            }
+        }
+
+        fn step(&mut self) {
+            self.now += STEP;
+            for (from, to, msg) in std::mem::take(&mut self.in_flight) {
+                if self.crashed.contains(&to) || self.rng.chance(self.loss) {
+                    continue;
+                }
+                if let Some(node) = self.nodes.iter_mut().find(|n| n.addr == to) {
+                    node.handle(from, msg, self.now);
+                }
+            }
+            for node in self.nodes.iter_mut() {
+                if self.crashed.contains(&node.addr) {
+                    continue;
+                }
+                node.tick(self.now);
+                let from = node.addr;
+                for (to, msg) in node.take_outbox() {
+                    self.in_flight.push((from, to, msg));
+                }
+                for e in node.take_events() {
+                    self.events.push((from, e));
+                }
+            }
+        }
+
+        // steps until every running node sees exactly the running nodes
+        fn converge(&mut self, limit: Duration) -> bool {
+            let deadline = self.now + limit;
+            while self.now < deadline {
+                self.step();
+                let mut expected: Vec<SocketAddr> = self
+                    .nodes
+                    .iter()
+                    .map(|n| n.addr)
+                    .filter(|a| !self.crashed.contains(a))
+                    .collect();
+                expected.sort();
+                let running = self.nodes.iter().filter(|n| !self.crashed.contains(&n.addr));
+                if running.into_iter().all(|n| n.members() == expected) {
+                    return true;
+                }
+            }
+            false
+        }
+
+        fn run(&mut self, d: Duration) {
+            let until = self.now + d;
+            while self.now < until {
+                self.step();
+            }
+        }
+    }
+
+    #[test]
+    fn members_join_through_a_seed() {
+        let mut net = Network::new(5, 0.0);
+        assert!(net.converge(Duration::from_secs(2)));
+
+        // each member reported every other one joining, exactly once
+        for node in net.nodes.iter() {
+            let joined: Vec<_> = net
+                .events
+                .iter()
+                .filter(|(at, e)| *at == node.addr && matches!(e, Event::Joined(_)))
+                .collect();
+            assert_eq!(joined.len(), 4);
+        }
+    }
+
+    #[test]
+    fn crashed_member_is_removed() {
+        let mut net = Network::new(5, 0.0);
+        assert!(net.converge(Duration::from_secs(2)));
+
+        net.crashed.insert(addr(3));
+        assert!(net.converge(Duration::from_secs(5)));
+        for i in [0, 1, 2, 4] {
+            assert!(net.events.contains(&(addr(i), Event::Removed(addr(3)))));
+        }
+    }
+
+    #[test]
+    fn converges_despite_packet_loss() {
+        let mut net = Network::new(6, 0.2);
+        assert!(net.converge(Duration::from_secs(10)));
+
+        net.crashed.insert(addr(5));
+        assert!(net.converge(Duration::from_secs(20)));
+
+        // members falsely suspected on the way refuted it
+        // rather than being removed
+        net.run(Duration::from_secs(5));
+        for node in net.nodes.iter().take(5) {
+            assert_eq!(node.members().len(), 5, "{:?}", node.members());
+        }
+    }
+
+    #[test]
+    fn suspected_member_refutes() {
+        let mut net = Network::new(3, 0.0);
+        assert!(net.converge(Duration::from_secs(2)));
+
+        // someone starts a rumour that node 2 is down
+        let rumour = Message {
+            incarnation: 0,
+            kind: Kind::Gossip,
+            updates: vec![Update {
+                addr: addr(2),
+                incarnation: 0,
+                state: State::Suspect,
+            }],
+        };
+        let now = net.now;
+        net.nodes[0].handle(addr(1), rumour, now);
+        assert!(net.nodes[0].take_events().contains(&Event::Suspected(addr(2))));
+
+        // node 2 hears of it, bumps its incarnation and is cleared
+        net.run(Duration::from_millis(400));
+        assert_eq!(net.nodes[2].incarnation, 1);
+        assert!(net.events.contains(&(addr(0), Event::Recovered(addr(2)))));
+        assert!(net.converge(Duration::from_secs(2)));
+        assert!(!net.events.iter().any(|(_, e)| *e == Event::Removed(addr(2))));
+    }
+
+    #[test]
+    fn leaving_member_is_removed_at_once() {
+        let mut net = Network::new(4, 0.0);
+        assert!(net.converge(Duration::from_secs(2)));
+
+        net.nodes[1].leave();
+        net.run(STEP);
+        net.crashed.insert(addr(1));
+        net.run(STEP * 2);
+        for i in [0, 2, 3] {
+            assert!(!net.nodes[i].members().contains(&addr(1)));
+            assert!(!net.events.contains(&(addr(i), Event::Suspected(addr(1)))));
+        }
+    }
+
+    #[test]
+    fn restarted_member_rejoins() {
+        let mut net = Network::new(3, 0.0);
+        assert!(net.converge(Duration::from_secs(2)));
+        net.crashed.insert(addr(2));
+        assert!(net.converge(Duration::from_secs(5)));
+
+        // same address, starting again from incarnation 0
+        net.nodes[2] = Swim::with_rng(addr(2), vec![addr(0)], config(), net.now, Rng::new(7));
+        net.crashed.clear();
+        assert!(net.converge(Duration::from_secs(2)));
+        assert!(net.nodes[2].incarnation > 0);
+    }
+
+}
@@ -0,0 +1,94 @@
+//! Runs members as separate processes on localhost
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Member {
+    child: Child,
+    addr: String,
+    lines: Receiver<String>,
+    seen: Vec<String>,
+}
+
+impl Member {
+    fn start(seeds: &[&Member]) -> Member {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_membership"));
+        cmd.args(["--period", "50", "--loss", "0.1"]);
+        if !seeds.is_empty() {
+            let seeds: Vec<&str> = seeds.iter().map(|s| s.addr.as_str()).collect();
+            cmd.args(["--seeds", &seeds.join(",")]);
+        }
+        let mut child = cmd.stdout(Stdio::piped()).spawn().unwrap();
+
+        let (tx, lines) = mpsc::channel();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let first = lines.recv_timeout(Duration::from_secs(5)).unwrap();
+        let addr = first.trim_start_matches("listening on ").to_string();
+        Member {
+            child,
+            addr,
+            lines,
+            seen: vec![],
+        }
+    }
+
+    fn saw(&mut self, line: &str) -> bool {
+        self.seen.extend(self.lines.try_iter());
+        self.seen.iter().any(|l| l == line)
+    }
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn wait_for<F: FnMut() -> bool>(mut f: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(15);
+    while Instant::now() < deadline {
+        if f() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn processes_detect_joins_and_failures() {
+    let seed = Member::start(&[]);
+    let b = Member::start(&[&seed]);
+    let c = Member::start(&[&seed]);
+    let mut members = vec![seed, b, c];
+
+    let addrs: Vec<String> = members.iter().map(|m| m.addr.clone()).collect();
+    for (i, member) in members.iter_mut().enumerate() {
+        for (j, addr) in addrs.iter().enumerate() {
+            if i != j {
+                let joined = format!("joined {}", addr);
+                assert!(wait_for(|| member.saw(&joined)), "{} never saw {}", i, joined);
+            }
+        }
+    }
+
+    // kill one without warning
+    let crashed = members.pop().unwrap();
+    let removed = format!("removed {}", crashed.addr);
+    drop(crashed);
+    for member in members.iter_mut() {
+        assert!(wait_for(|| member.saw(&removed)), "{} never removed", member.addr);
+    }
+}
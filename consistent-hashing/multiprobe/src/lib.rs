@@ -0,0 +1,147 @@
+//! Multi-probe consistent hashing, from Appleton & O'Reilly,
+//! "Multi-Probe Consistent Hashing".
+//!
+//! Each node sits on the ring exactly once, but every key is hashed
+//! `probes` times and goes to the node closest (clockwise) to any of
+//! its probes. This evens out load like virtual nodes do, while keeping
+//! memory at one point per node; 21 probes gives a peak-to-mean load
+//! ratio of about 1.05.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+
+use ringhash::hash::BuildXxHash64;
+use ringhash::ConsistentHasher;
+
+pub const DEFAULT_PROBES: u32 = 21;
+
+pub struct MultiProbe<N, S = BuildXxHash64> {
+    // sorted positions of each node, which are also their ids
+    points: Vec<u64>,
+    nodes: HashMap<u64, N>,
+    probes: u32,
+    build_hasher: S,
+}
+
+impl<N: Hash> Default for MultiProbe<N> {
+    fn default() -> Self {
+        MultiProbe::new()
+    }
+}
+
+impl<N: Hash> MultiProbe<N> {
+    pub fn new() -> MultiProbe<N> {
+        MultiProbe::with_probes(DEFAULT_PROBES)
+    }
+
+    pub fn with_probes(probes: u32) -> MultiProbe<N> {
+        MultiProbe::with_probes_and_hasher(probes, BuildXxHash64::default())
+    }
+}
+
+impl<N: Hash, S: BuildHasher> MultiProbe<N, S> {
+    pub fn with_probes_and_hasher(probes: u32, build_hasher: S) -> MultiProbe<N, S> {
+        MultiProbe {
+            points: vec![],
+            nodes: HashMap::new(),
+            probes: probes.max(1),
+            build_hasher,
+        }
+    }
+
+    // the first point at or after `hash`, wrapping around the ring
+    fn successor(&self, hash: u64) -> u64 {
+        let pos = match self.points.binary_search(&hash) {
+            Ok(n) => n,
+            Err(n) => n,
+        };
+        self.points[pos % self.points.len()]
+    }
+}
+
+impl<N: Hash, S: BuildHasher> ConsistentHasher<N> for MultiProbe<N, S> {
+    fn add(&mut self, node: N) {
+        let id = self.build_hasher.hash_one(&node);
+        if let Err(pos) = self.points.binary_search(&id) {
+            self.points.insert(pos, id);
+        }
+        self.nodes.insert(id, node);
+    }
+
+    fn remove(&mut self, node: &N) -> Option<N> {
+        let id = self.build_hasher.hash_one(node);
+        let removed = self.nodes.remove(&id)?;
+        self.points.retain(|p| *p != id);
+        Some(removed)
+    }
+
+    fn get<K: Hash>(&self, k: &K) -> Option<&N> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let key = self.build_hasher.hash_one(k);
+        let (_, closest) = (0..self.probes)
+            .map(|i| {
+                let probe = self.build_hasher.hash_one((key, i));
+                let point = self.successor(probe);
+                (point.wrapping_sub(probe), point)
+            })
+            .min()?;
+        self.nodes.get(&closest)
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use ringhash::ConsistentHasher;
+
+    use super::MultiProbe;
+
+    fn max_to_mean(probes: u32) -> f64 {
+        let mut mp = MultiProbe::with_probes(probes);
+        for node in 0..20u32 {
+            mp.add(node);
+        }
+
+        let mut counts = [0u32; 20];
+        for k in 0..100_000u32 {
+            counts[*mp.get(&k).unwrap() as usize] += 1;
+        }
+        *counts.iter().max().unwrap() as f64 / 5_000.0
+    }
+
+    #[test]
+    fn probes_even_out_load() {
+        let single = max_to_mean(1);
+        let multi = max_to_mean(21);
+        assert!(multi < single);
+        assert!(multi < 1.2, "max/mean was {}", multi);
+    }
+
+    #[test]
+    fn membership_changes_are_minimal() {
+        let mut mp = MultiProbe::new();
+        for node in 0..8u32 {
+            mp.add(node);
+        }
+        let before: Vec<u32> = (0..10_000u32).map(|k| *mp.get(&k).unwrap()).collect();
+
+        mp.add(8);
+        for (k, old) in before.iter().enumerate() {
+            let new = *mp.get(&(k as u32)).unwrap();
+            assert!(*old == new || new == 8);
+        }
+
+        assert_eq!(mp.remove(&8), Some(8));
+        for (k, old) in before.iter().enumerate() {
+            assert_eq!(*old, *mp.get(&(k as u32)).unwrap());
+        }
+    }
+
+}
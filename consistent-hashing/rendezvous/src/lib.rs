@@ -0,0 +1,145 @@
+//! Rendezvous, or Highest Random Weight (HRW), hashing from Thaler &
+//! Ravishankar, "A Name-Based Mapping Scheme for Rendezvous".
+//!
+//! Every node scores every key with a hash of the pair, and the key
+//! goes to the node with the highest score. There's no state besides
+//! the node list, and removing a node only moves the keys it won, at
+//! the cost of an O(n) lookup.
+
+use std::hash::{BuildHasher, Hash};
+
+use ringhash::hash::BuildXxHash64;
+use ringhash::ConsistentHasher;
+
+pub struct Rendezvous<N, S = BuildXxHash64> {
+    // nodes alongside their hash
+    nodes: Vec<(u64, N)>,
+    build_hasher: S,
+}
+
+impl<N: Hash> Default for Rendezvous<N> {
+    fn default() -> Self {
+        Rendezvous::new()
+    }
+}
+
+impl<N: Hash> Rendezvous<N> {
+    pub fn new() -> Rendezvous<N> {
+        Rendezvous::with_hasher(BuildXxHash64::default())
+    }
+}
+
+impl<N: Hash, S: BuildHasher> Rendezvous<N, S> {
+    pub fn with_hasher(build_hasher: S) -> Rendezvous<N, S> {
+        Rendezvous {
+            nodes: vec![],
+            build_hasher,
+        }
+    }
+
+    /// Every node ordered from highest to lowest score for `k`,
+    /// which doubles as a replica preference list
+    pub fn ranked<K: Hash>(&self, k: &K) -> Vec<&N> {
+        let key = self.build_hasher.hash_one(k);
+        let mut scored: Vec<(u64, u64, &N)> = self
+            .nodes
+            .iter()
+            .map(|(id, n)| (self.score(*id, key), *id, n))
+            .collect();
+        scored.sort_by_key(|(score, id, _)| std::cmp::Reverse((*score, *id)));
+        scored.into_iter().map(|(_, _, n)| n).collect()
+    }
+
+    fn score(&self, id: u64, key: u64) -> u64 {
+        self.build_hasher.hash_one((id, key))
+    }
+}
+
+impl<N: Hash, S: BuildHasher> ConsistentHasher<N> for Rendezvous<N, S> {
+    fn add(&mut self, node: N) {
+        let id = self.build_hasher.hash_one(&node);
+        if self.nodes.iter().all(|(other, _)| *other != id) {
+            self.nodes.push((id, node));
+        }
+    }
+
+    fn remove(&mut self, node: &N) -> Option<N> {
+        let id = self.build_hasher.hash_one(node);
+        let pos = self.nodes.iter().position(|(other, _)| *other == id)?;
+        Some(self.nodes.remove(pos).1)
+    }
+
+    fn get<K: Hash>(&self, k: &K) -> Option<&N> {
+        let key = self.build_hasher.hash_one(k);
+        self.nodes
+            .iter()
+            // break (unlikely) ties on score by id so the
+            // winner doesn't depend on the order of `nodes`
+            .max_by_key(|(id, _)| (self.score(*id, key), *id))
+            .map(|(_, n)| n)
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use ringhash::ConsistentHasher;
+
+    use super::Rendezvous;
+
+    #[test]
+    fn nodes_are_balanced() {
+        let mut hrw = Rendezvous::new();
+        for node in 0..10u32 {
+            hrw.add(node);
+        }
+
+        let mut counts = [0u32; 10];
+        for k in 0..100_000u32 {
+            counts[*hrw.get(&k).unwrap() as usize] += 1;
+        }
+        for count in counts.iter() {
+            assert!((*count as i64 - 10_000).abs() < 500, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn membership_changes_are_minimal() {
+        let mut hrw = Rendezvous::new();
+        for node in 0..8u32 {
+            hrw.add(node);
+        }
+        let before: Vec<u32> = (0..10_000u32).map(|k| *hrw.get(&k).unwrap()).collect();
+
+        assert_eq!(hrw.remove(&3), Some(3));
+        for (k, old) in before.iter().enumerate() {
+            let new = *hrw.get(&(k as u32)).unwrap();
+            assert!(*old == 3 || *old == new);
+        }
+
+        hrw.add(3);
+        hrw.add(8);
+        for (k, old) in before.iter().enumerate() {
+            let new = *hrw.get(&(k as u32)).unwrap();
+            assert!(*old == new || new == 8);
+        }
+    }
+
+    #[test]
+    fn ranked_starts_with_owner() {
+        let mut hrw = Rendezvous::new();
+        for node in 0..5u32 {
+            hrw.add(node);
+        }
+        for k in 0..100u32 {
+            let ranked = hrw.ranked(&k);
+            assert_eq!(ranked.len(), 5);
+            assert_eq!(ranked[0], hrw.get(&k).unwrap());
+        }
+    }
+
+}
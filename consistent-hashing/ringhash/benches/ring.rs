@@ -0,0 +1,72 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use ringhash::HashRing;
+
+// physical nodes on every ring, so the total virtual nodes
+// is set by how many tokens each one gets
+const NODES: u32 = 10;
+
+const VNODES: [u32; 4] = [10, 1_000, 10_000, 100_000];
+
+// lookups in one `get` iteration
+const KEYS: u64 = 1_000;
+
+fn ring(vnodes: u32) -> HashRing<String> {
+    let mut ring = HashRing::with_replicas(vnodes / NODES);
+    for i in 0..NODES {
+        ring.add(format!("node-{}", i));
+    }
+    ring
+}
+
+fn add(c: &mut Criterion) {
+    let mut group = c.benchmark_group("add");
+    for vnodes in VNODES {
+        group.bench_with_input(BenchmarkId::from_parameter(vnodes), &vnodes, |b, &vnodes| {
+            b.iter_batched(
+                || ring(vnodes),
+                |mut ring| {
+                    ring.add("joining".to_string());
+                    ring
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn remove(c: &mut Criterion) {
+    let mut group = c.benchmark_group("remove");
+    for vnodes in VNODES {
+        group.bench_with_input(BenchmarkId::from_parameter(vnodes), &vnodes, |b, &vnodes| {
+            b.iter_batched(
+                || ring(vnodes),
+                |mut ring| {
+                    ring.remove(&"node-0".to_string());
+                    ring
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    group.throughput(Throughput::Elements(KEYS));
+    for vnodes in VNODES {
+        let ring = ring(vnodes);
+        group.bench_with_input(BenchmarkId::from_parameter(vnodes), &ring, |b, ring| {
+            b.iter(|| {
+                for k in 0..KEYS {
+                    criterion::black_box(ring.get(&k));
+                }
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, add, remove, get);
+criterion_main!(benches);
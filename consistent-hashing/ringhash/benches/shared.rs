@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ringhash::shared::SharedRing;
+use ringhash::HashRing;
+
+// lookups per thread in one benchmark iteration
+const LOOKUPS: u64 = 1_000;
+
+fn ring() -> HashRing<String> {
+    let mut ring = HashRing::with_replicas(100);
+    for i in 0..50 {
+        ring.add(format!("node-{}", i));
+    }
+    ring
+}
+
+// pause between membership changes, far more churn than a real cluster
+const UPDATE_INTERVAL: Duration = Duration::from_micros(50);
+
+// time for `threads` readers to each do `iters * LOOKUPS` lookups
+// while another thread keeps adding and removing a node
+fn contended<L, U>(iters: u64, threads: u64, lookup: L, update: U) -> Duration
+where
+    L: Fn(u64) + Sync,
+    U: Fn(u64) + Sync,
+{
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        s.spawn(|| {
+            let mut i = 0;
+            while !done.load(Ordering::Relaxed) {
+                update(i);
+                i += 1;
+                thread::sleep(UPDATE_INTERVAL);
+            }
+        });
+
+        let start = Instant::now();
+        let readers: Vec<_> = (0..threads)
+            .map(|t| {
+                let lookup = &lookup;
+                s.spawn(move || {
+                    for k in 0..iters * LOOKUPS {
+                        lookup(k * threads + t);
+                    }
+                })
+            })
+            .collect();
+        for r in readers {
+            r.join().unwrap();
+        }
+        let elapsed = start.elapsed();
+
+        done.store(true, Ordering::Relaxed);
+        elapsed
+    })
+}
+
+fn lookups_under_updates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookups_under_updates");
+    for threads in [1u64, 2, 4, 8] {
+        group.throughput(Throughput::Elements(LOOKUPS * threads));
+
+        group.bench_with_input(BenchmarkId::new("mutex", threads), &threads, |b, &threads| {
+            let ring = Mutex::new(ring());
+            b.iter_custom(|iters| {
+                contended(
+                    iters,
+                    threads,
+                    |k| {
+                        criterion::black_box(ring.lock().unwrap().get(&k).cloned());
+                    },
+                    |i| {
+                        let mut ring = ring.lock().unwrap();
+                        if i % 2 == 0 {
+                            ring.add("flapping".to_string());
+                        } else {
+                            ring.remove(&"flapping".to_string());
+                        }
+                    },
+                )
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("snapshot", threads), &threads, |b, &threads| {
+            let ring = Arc::new(SharedRing::new(ring()));
+            b.iter_custom(|iters| {
+                contended(
+                    iters,
+                    threads,
+                    |k| {
+                        criterion::black_box(ring.get(&k));
+                    },
+                    |i| {
+                        if i % 2 == 0 {
+                            ring.add("flapping".to_string());
+                        } else {
+                            ring.remove(&"flapping".to_string());
+                        }
+                    },
+                )
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookups_under_updates);
+criterion_main!(benches);
@@ -14,7 +14,8 @@ struct Record {
 
 fn main() {
 
-    let mut ring = HashRing::new();
+    // every physical node gets 100 tokens on the ring
+    let mut ring = HashRing::with_replicas(100);
 
     let nodes = vec![
         VirtualNode{ 
@@ -35,7 +36,7 @@ fn main() {
         ring.add(node);
     }
 
-    println!("Ring has {} elements", ring.len());
+    println!("Ring has {} nodes and {} tokens", ring.len(), ring.token_count());
 
     let records = vec![
         Record { id : 1 },
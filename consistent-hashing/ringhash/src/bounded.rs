@@ -0,0 +1,241 @@
+//! Consistent hashing with bounded loads, from Mirrokni, Thorup &
+//! Zadimoghaddam, "Consistent Hashing with Bounded Loads".
+//!
+//! Every node has a capacity of `(1 + ε)` times its share of the
+//! average load. A key whose owner is at capacity is forwarded to the
+//! next node clockwise that isn't, so a hot key spills over onto its
+//! neighbours instead of overloading a single node.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+
+use crate::hash::BuildXxHash64;
+use crate::HashRing;
+
+pub struct BoundedLoadRing<N, S = BuildXxHash64> {
+    ring: HashRing<N, S>,
+    epsilon: f64,
+    // current load of each physical node, by id
+    loads: HashMap<u64, usize>,
+    total_load: usize,
+    total_weight: u64,
+}
+
+impl<N: Hash, S: BuildHasher> BoundedLoadRing<N, S> {
+    /// Wraps `ring` so that no node takes on more than `1 + epsilon`
+    /// times its share of the load. `epsilon` must be positive.
+    pub fn new(ring: HashRing<N, S>, epsilon: f64) -> BoundedLoadRing<N, S> {
+        assert!(epsilon > 0.0, "epsilon must be positive");
+        let total_weight = ring.physical.values().map(|p| p.weight as u64).sum();
+        BoundedLoadRing {
+            ring,
+            epsilon,
+            loads: HashMap::new(),
+            total_load: 0,
+            total_weight,
+        }
+    }
+
+    pub fn ring(&self) -> &HashRing<N, S> {
+        &self.ring
+    }
+
+    pub fn add(&mut self, node: N) {
+        self.add_weighted(node, 1);
+    }
+
+    pub fn add_weighted(&mut self, node: N, weight: u32) {
+        if let Some(old) = self.ring.weight(&node) {
+            self.total_weight -= old as u64;
+        }
+        self.ring.add_weighted(node, weight);
+        self.total_weight += weight.max(1) as u64;
+    }
+
+    /// Removes the node, and with it any load assigned to it
+    pub fn remove<K: Hash>(&mut self, k: &K) -> Option<N> {
+        let weight = self.ring.weight(k)?;
+        let id = self.ring.calculate_hash(k);
+        self.total_weight -= weight as u64;
+        self.total_load -= self.loads.remove(&id).unwrap_or(0);
+        self.ring.remove(k)
+    }
+
+    /// The total load currently assigned across all nodes
+    pub fn total_load(&self) -> usize {
+        self.total_load
+    }
+
+    pub fn load<K: Hash>(&self, node: &K) -> usize {
+        let id = self.ring.calculate_hash(node);
+        self.loads.get(&id).copied().unwrap_or(0)
+    }
+
+    /// How much load `node` may hold once one more unit is assigned,
+    /// i.e. `⌈(1 + ε) × (total + 1) × weight / total_weight⌉`
+    pub fn capacity<K: Hash>(&self, node: &K) -> usize {
+        match self.ring.weight(node) {
+            Some(weight) => self.capacity_for(weight),
+            None => 0,
+        }
+    }
+
+    fn capacity_for(&self, weight: u32) -> usize {
+        let share = (self.total_load + 1) as f64 * weight as f64 / self.total_weight as f64;
+        ((1.0 + self.epsilon) * share).ceil() as usize
+    }
+
+    /// The node `k` would be assigned to: its owner on the ring, or the
+    /// first node clockwise from it with spare capacity
+    pub fn get<K: Hash>(&self, k: &K) -> Option<&N> {
+        self.ring.preference_list(k).find(|node| {
+            let id = self.ring.calculate_hash(*node);
+            let weight = self.ring.physical[&id].weight;
+            self.loads.get(&id).copied().unwrap_or(0) < self.capacity_for(weight)
+        })
+    }
+
+    /// Assigns one unit of load for `k`, returning the node it went to
+    pub fn assign<K: Hash>(&mut self, k: &K) -> Option<&N> {
+        let id = self.ring.calculate_hash(self.get(k)?);
+        *self.loads.entry(id).or_insert(0) += 1;
+        self.total_load += 1;
+        self.ring.physical.get(&id).map(|p| &p.node)
+    }
+
+    /// Releases one unit of load previously assigned to `node`.
+    /// Returns false if the node had nothing assigned.
+    pub fn release<K: Hash>(&mut self, node: &K) -> bool {
+        let id = self.ring.calculate_hash(node);
+        match self.loads.get_mut(&id) {
+            Some(load) if *load > 0 => {
+                *load -= 1;
+                self.total_load -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use super::BoundedLoadRing;
+    use crate::HashRing;
+
+    // xorshift64, so the key set is the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_f64(&mut self) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    // requests for `n_keys` keys, where the popularity of the
+    // key with rank r is proportional to 1 / r^s
+    fn zipf_requests(n_keys: usize, n_requests: usize, s: f64) -> Vec<usize> {
+        let mut cdf = Vec::with_capacity(n_keys);
+        let mut sum = 0.0;
+        for r in 1..=n_keys {
+            sum += 1.0 / (r as f64).powf(s);
+            cdf.push(sum);
+        }
+
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        (0..n_requests)
+            .map(|_| {
+                let u = rng.next_f64() * sum;
+                cdf.iter().position(|c| *c >= u).unwrap_or(n_keys - 1)
+            })
+            .collect()
+    }
+
+    fn ring(nodes: u32) -> HashRing<String> {
+        let mut ring = HashRing::with_replicas(50);
+        for i in 0..nodes {
+            ring.add(format!("node-{}", i));
+        }
+        ring
+    }
+
+    #[test]
+    fn unloaded_ring_matches_plain_ring() {
+        let plain = ring(5);
+        let bounded = BoundedLoadRing::new(ring(5), 0.25);
+        for k in 0..1000u32 {
+            assert_eq!(plain.get(&k), bounded.get(&k));
+        }
+    }
+
+    #[test]
+    fn skewed_keys_stay_within_capacity() {
+        let requests = zipf_requests(1000, 20_000, 1.1);
+
+        let plain = ring(10);
+        let mut plain_loads: HashMap<&String, usize> = HashMap::new();
+        for k in requests.iter() {
+            *plain_loads.entry(plain.get(k).unwrap()).or_insert(0) += 1;
+        }
+        let plain_max = *plain_loads.values().max().unwrap();
+
+        let mut bounded = BoundedLoadRing::new(ring(10), 0.25);
+        for k in requests.iter() {
+            assert!(bounded.assign(k).is_some());
+        }
+        assert_eq!(bounded.total_load(), requests.len());
+
+        // ⌈1.25 × 20,000 / 10⌉
+        let bound = 2500;
+        let bounded_max = (0..10)
+            .map(|i| bounded.load(&format!("node-{}", i)))
+            .max()
+            .unwrap();
+        assert!(bounded_max <= bound, "max load {}", bounded_max);
+        assert!(plain_max > bound, "plain max load {}", plain_max);
+    }
+
+    #[test]
+    fn full_owner_forwards_clockwise() {
+        let mut bounded = BoundedLoadRing::new(ring(4), 0.5);
+        let owners: Vec<String> = bounded
+            .ring()
+            .get_n(b"hot key", 2)
+            .into_iter()
+            .cloned()
+            .collect();
+
+        // with nothing else assigned the owner fills up after
+        // ⌈1.5 × (load + 1) / 4⌉ requests, i.e. on the second one
+        assert_eq!(bounded.assign(b"hot key").unwrap(), &owners[0]);
+        assert_eq!(bounded.get(b"hot key").unwrap(), &owners[1]);
+
+        assert!(bounded.release(&owners[0]));
+        assert!(!bounded.release(&owners[0]));
+        assert_eq!(bounded.get(b"hot key").unwrap(), &owners[0]);
+    }
+
+    #[test]
+    fn weights_scale_capacity() {
+        let mut bounded = BoundedLoadRing::new(HashRing::with_replicas(50), 0.1);
+        bounded.add("small".to_string());
+        bounded.add_weighted("big".to_string(), 3);
+
+        for k in 0..1000u32 {
+            bounded.assign(&k);
+        }
+        assert_eq!(bounded.capacity(&"big".to_string()), 826);
+        assert_eq!(bounded.capacity(&"small".to_string()), 276);
+        assert!(bounded.load(&"small".to_string()) <= 276);
+
+        assert!(bounded.remove(&"big".to_string()).is_some());
+        assert_eq!(bounded.total_load(), bounded.load(&"small".to_string()));
+    }
+
+}
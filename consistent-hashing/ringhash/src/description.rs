@@ -0,0 +1,260 @@
+//! A versioned description of ring membership that can be shipped
+//! between processes, either as compact binary or as JSON.
+//!
+//! Every membership change bumps the ring's epoch, so a process can
+//! tell whether a description it receives is newer than its own ring.
+//! Tokens are carried explicitly, so the rebuilt ring is identical to
+//! the one described, as long as both sides use the same hasher `S`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::topology::Topology;
+use crate::{HashRing, PhysicalNode, VirtualNode};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RingDescription<N> {
+    pub epoch: u64,
+    pub replicas: u32,
+    pub nodes: Vec<NodeDescription<N>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeDescription<N> {
+    pub node: N,
+    pub weight: u32,
+    pub topology: Topology,
+    // positions of the node's virtual nodes
+    pub tokens: Vec<u64>,
+}
+
+#[derive(Debug)]
+pub enum RingError {
+    /// The description is no newer than the ring it was applied to
+    StaleEpoch { current: u64, received: u64 },
+    /// Two nodes in the description hash to the same id
+    DuplicateNode,
+    Decode(String),
+}
+
+impl Error for RingError {}
+
+impl fmt::Display for RingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RingError::StaleEpoch { current, received } => write!(
+                f,
+                "stale ring description: epoch {} is not newer than {}",
+                received, current
+            ),
+            RingError::DuplicateNode => write!(f, "ring description has a duplicate node"),
+            RingError::Decode(e) => write!(f, "couldn't decode ring description: {}", e),
+        }
+    }
+}
+
+impl<N: Serialize + DeserializeOwned> RingDescription<N> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // serializing plain structs into a Vec can't fail
+        bincode::serialize(self).expect("ring description is serializable")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<RingDescription<N>, RingError> {
+        bincode::deserialize(bytes).map_err(|e| RingError::Decode(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("ring description is serializable")
+    }
+
+    pub fn from_json(json: &str) -> Result<RingDescription<N>, RingError> {
+        serde_json::from_str(json).map_err(|e| RingError::Decode(e.to_string()))
+    }
+}
+
+impl<N: Hash + Clone, S: BuildHasher> HashRing<N, S> {
+    /// The ring's current membership, nodes in ring order
+    /// of their first token
+    pub fn describe(&self) -> RingDescription<N> {
+        let mut order = vec![];
+        let mut tokens: HashMap<u64, Vec<u64>> = HashMap::new();
+        for vn in self.nodes.iter() {
+            tokens
+                .entry(vn.id)
+                .or_insert_with(|| {
+                    order.push(vn.id);
+                    vec![]
+                })
+                .push(vn.position);
+        }
+
+        let nodes = order
+            .into_iter()
+            .map(|id| {
+                let p = &self.physical[&id];
+                NodeDescription {
+                    node: p.node.clone(),
+                    weight: p.weight,
+                    topology: p.topology.clone(),
+                    tokens: tokens.remove(&id).unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        RingDescription {
+            epoch: self.epoch,
+            replicas: self.replicas,
+            nodes,
+        }
+    }
+}
+
+impl<N: Hash, S: BuildHasher> HashRing<N, S> {
+    /// Rebuilds the ring a description was taken from
+    pub fn from_description(
+        desc: RingDescription<N>,
+        build_hasher: S,
+    ) -> Result<HashRing<N, S>, RingError> {
+        let mut ring = HashRing::with_replicas_and_hasher(desc.replicas, build_hasher);
+        for n in desc.nodes {
+            let id = ring.calculate_hash(&n.node);
+            if ring.physical.contains_key(&id) {
+                return Err(RingError::DuplicateNode);
+            }
+            for position in n.tokens {
+                ring.nodes.push(VirtualNode::new(position, id));
+            }
+            ring.physical.insert(
+                id,
+                PhysicalNode {
+                    node: n.node,
+                    weight: n.weight,
+                    topology: n.topology,
+                },
+            );
+        }
+        ring.nodes.sort();
+        ring.epoch = desc.epoch;
+        Ok(ring)
+    }
+
+    /// Replaces this ring's membership with a newer description,
+    /// rejecting any whose epoch isn't greater than the ring's
+    pub fn apply(&mut self, desc: RingDescription<N>) -> Result<(), RingError>
+    where
+        S: Clone,
+    {
+        if desc.epoch <= self.epoch {
+            return Err(RingError::StaleEpoch {
+                current: self.epoch,
+                received: desc.epoch,
+            });
+        }
+        *self = HashRing::from_description(desc, self.build_hasher.clone())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{RingDescription, RingError};
+    use crate::topology::Topology;
+    use crate::HashRing;
+
+    fn ring() -> HashRing<String> {
+        let mut ring = HashRing::with_replicas(16);
+        ring.add("cache-a".to_string());
+        ring.add_weighted("cache-b".to_string(), 2);
+        ring.add_with_topology("cache-c".to_string(), 1, Topology::new("zone-a", "rack-1"));
+        ring
+    }
+
+    fn assert_identical(a: &HashRing<String>, b: &HashRing<String>) {
+        assert_eq!(a.describe(), b.describe());
+        for k in 0..1000u32 {
+            assert_eq!(a.get_n(&k, 3), b.get_n(&k, 3));
+        }
+    }
+
+    #[test]
+    fn membership_changes_bump_the_epoch() {
+        let mut ring = ring();
+        assert_eq!(ring.epoch(), 3);
+
+        ring.remove(&"cache-a".to_string());
+        assert_eq!(ring.epoch(), 4);
+
+        // nothing to remove, nothing changed
+        ring.remove(&"cache-z".to_string());
+        assert_eq!(ring.epoch(), 4);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let ring = ring();
+        let bytes = ring.describe().to_bytes();
+
+        let desc: RingDescription<String> = RingDescription::from_bytes(&bytes).unwrap();
+        assert_eq!(desc.epoch, 3);
+        assert_eq!(desc.nodes.len(), 3);
+        assert_eq!(desc.nodes.iter().map(|n| n.tokens.len()).sum::<usize>(), 64);
+
+        let rebuilt = HashRing::from_description(desc, Default::default()).unwrap();
+        assert_identical(&ring, &rebuilt);
+        assert_eq!(
+            rebuilt.topology(&"cache-c".to_string()),
+            Some(&Topology::new("zone-a", "rack-1"))
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let ring = ring();
+        let json = ring.describe().to_json();
+        assert!(json.contains("\"cache-b\""));
+        assert!(json.contains("\"epoch\": 3"));
+
+        let desc = RingDescription::from_json(&json).unwrap();
+        let rebuilt = HashRing::from_description(desc, Default::default()).unwrap();
+        assert_identical(&ring, &rebuilt);
+    }
+
+    #[test]
+    fn stale_epochs_are_rejected() {
+        let mut local = ring();
+        let mut remote = ring();
+
+        // same epoch isn't newer
+        match local.apply(remote.describe()) {
+            Err(RingError::StaleEpoch { current: 3, received: 3 }) => {}
+            res => panic!("expected a stale epoch, got {:?}", res),
+        }
+
+        remote.add("cache-d".to_string());
+        let newer = remote.describe();
+        local.apply(newer.clone()).unwrap();
+        assert_identical(&local, &remote);
+
+        assert!(local.apply(newer).is_err());
+    }
+
+    #[test]
+    fn bad_descriptions_are_rejected() {
+        assert!(RingDescription::<String>::from_bytes(&[1, 2, 3]).is_err());
+        assert!(RingDescription::<String>::from_json("{\"epoch\": 1}").is_err());
+
+        let mut desc = ring().describe();
+        desc.nodes.push(desc.nodes[0].clone());
+        match HashRing::<String>::from_description(desc, Default::default()) {
+            Err(RingError::DuplicateNode) => {}
+            _ => panic!("expected a duplicate node"),
+        }
+    }
+
+}
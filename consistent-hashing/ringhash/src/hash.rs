@@ -0,0 +1,487 @@
+//! Hash functions whose output is fixed by their specification, so two
+//! processes (or two builds with different toolchains) agree on where a
+//! key lands on the ring. `std`'s `DefaultHasher` makes no such promise.
+//!
+//! Every hasher here writes integers as little-endian bytes, and `usize`
+//! as a `u64`, so placement also agrees across architectures.
+
+use std::hash::{BuildHasherDefault, Hasher};
+
+pub type BuildFnv1a = BuildHasherDefault<Fnv1a>;
+pub type BuildXxHash64 = BuildHasherDefault<XxHash64>;
+pub type BuildMurmur3 = BuildHasherDefault<Murmur3>;
+pub type BuildSipHash24 = BuildHasherDefault<SipHash24>;
+
+// the integer `write_*` defaults use native-endian bytes
+macro_rules! portable_writes {
+    () => {
+        fn write_u16(&mut self, i: u16) {
+            self.write(&i.to_le_bytes())
+        }
+
+        fn write_u32(&mut self, i: u32) {
+            self.write(&i.to_le_bytes())
+        }
+
+        fn write_u64(&mut self, i: u64) {
+            self.write(&i.to_le_bytes())
+        }
+
+        fn write_u128(&mut self, i: u128) {
+            self.write(&i.to_le_bytes())
+        }
+
+        fn write_usize(&mut self, i: usize) {
+            self.write(&(i as u64).to_le_bytes())
+        }
+    };
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(buf)
+}
+
+/// 64-bit FNV-1a, <http://www.isthe.com/chongo/tech/comp/fnv/>
+#[derive(Debug, Clone, Copy)]
+pub struct Fnv1a(u64);
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    portable_writes!();
+}
+
+/// XXH64, <https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md>
+#[derive(Debug, Clone)]
+pub struct XxHash64 {
+    seed: u64,
+    acc: [u64; 4],
+    buf: [u8; 32],
+    buf_len: usize,
+    total_len: u64,
+}
+
+const XX_PRIME_1: u64 = 0x9e37_79b1_85eb_ca87;
+const XX_PRIME_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const XX_PRIME_3: u64 = 0x1656_67b1_9e37_79f9;
+const XX_PRIME_4: u64 = 0x85eb_ca77_c2b2_ae63;
+const XX_PRIME_5: u64 = 0x27d4_eb2f_1656_67c5;
+
+fn xx_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(XX_PRIME_2))
+        .rotate_left(31)
+        .wrapping_mul(XX_PRIME_1)
+}
+
+fn xx_merge(acc: u64, val: u64) -> u64 {
+    (acc ^ xx_round(0, val))
+        .wrapping_mul(XX_PRIME_1)
+        .wrapping_add(XX_PRIME_4)
+}
+
+impl XxHash64 {
+    pub fn with_seed(seed: u64) -> XxHash64 {
+        XxHash64 {
+            seed,
+            acc: [
+                seed.wrapping_add(XX_PRIME_1).wrapping_add(XX_PRIME_2),
+                seed.wrapping_add(XX_PRIME_2),
+                seed,
+                seed.wrapping_sub(XX_PRIME_1),
+            ],
+            buf: [0; 32],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn consume_stripe(acc: &mut [u64; 4], stripe: &[u8]) {
+        for (i, lane) in acc.iter_mut().enumerate() {
+            *lane = xx_round(*lane, read_u64(&stripe[i * 8..]));
+        }
+    }
+}
+
+impl Default for XxHash64 {
+    fn default() -> Self {
+        XxHash64::with_seed(0)
+    }
+}
+
+impl Hasher for XxHash64 {
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+
+        // top up a partially filled stripe first
+        if self.buf_len > 0 {
+            let take = (32 - self.buf_len).min(bytes.len());
+            self.buf[self.buf_len..self.buf_len + take].copy_from_slice(&bytes[..take]);
+            self.buf_len += take;
+            bytes = &bytes[take..];
+            if self.buf_len < 32 {
+                return;
+            }
+            let stripe = self.buf;
+            XxHash64::consume_stripe(&mut self.acc, &stripe);
+            self.buf_len = 0;
+        }
+
+        while bytes.len() >= 32 {
+            XxHash64::consume_stripe(&mut self.acc, &bytes[..32]);
+            bytes = &bytes[32..];
+        }
+
+        self.buf[..bytes.len()].copy_from_slice(bytes);
+        self.buf_len = bytes.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let [v1, v2, v3, v4] = self.acc;
+        let mut h = if self.total_len >= 32 {
+            let h = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            self.acc.iter().fold(h, |h, v| xx_merge(h, *v))
+        } else {
+            self.seed.wrapping_add(XX_PRIME_5)
+        };
+        h = h.wrapping_add(self.total_len);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            h ^= xx_round(0, read_u64(rest));
+            h = h
+                .rotate_left(27)
+                .wrapping_mul(XX_PRIME_1)
+                .wrapping_add(XX_PRIME_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h ^= (read_u32(rest) as u64).wrapping_mul(XX_PRIME_1);
+            h = h
+                .rotate_left(23)
+                .wrapping_mul(XX_PRIME_2)
+                .wrapping_add(XX_PRIME_3);
+            rest = &rest[4..];
+        }
+        for b in rest {
+            h ^= (*b as u64).wrapping_mul(XX_PRIME_5);
+            h = h.rotate_left(11).wrapping_mul(XX_PRIME_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(XX_PRIME_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(XX_PRIME_3);
+        h ^ (h >> 32)
+    }
+
+    portable_writes!();
+}
+
+/// The low 64 bits of MurmurHash3 x64_128,
+/// <https://github.com/aappleby/smhasher/blob/master/src/MurmurHash3.cpp>
+#[derive(Debug, Clone)]
+pub struct Murmur3 {
+    h1: u64,
+    h2: u64,
+    buf: [u8; 16],
+    buf_len: usize,
+    total_len: u64,
+}
+
+const MURMUR_C1: u64 = 0x87c3_7b91_1142_53d5;
+const MURMUR_C2: u64 = 0x4cf5_ad43_2745_937f;
+
+fn murmur_k1(k1: u64) -> u64 {
+    k1.wrapping_mul(MURMUR_C1)
+        .rotate_left(31)
+        .wrapping_mul(MURMUR_C2)
+}
+
+fn murmur_k2(k2: u64) -> u64 {
+    k2.wrapping_mul(MURMUR_C2)
+        .rotate_left(33)
+        .wrapping_mul(MURMUR_C1)
+}
+
+fn murmur_fmix(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^ (k >> 33)
+}
+
+impl Murmur3 {
+    pub fn with_seed(seed: u32) -> Murmur3 {
+        Murmur3 {
+            h1: seed as u64,
+            h2: seed as u64,
+            buf: [0; 16],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn consume_block(&mut self, block: &[u8]) {
+        self.h1 ^= murmur_k1(read_u64(block));
+        self.h1 = self
+            .h1
+            .rotate_left(27)
+            .wrapping_add(self.h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+
+        self.h2 ^= murmur_k2(read_u64(&block[8..]));
+        self.h2 = self
+            .h2
+            .rotate_left(31)
+            .wrapping_add(self.h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+}
+
+impl Default for Murmur3 {
+    fn default() -> Self {
+        Murmur3::with_seed(0)
+    }
+}
+
+impl Hasher for Murmur3 {
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+
+        if self.buf_len > 0 {
+            let take = (16 - self.buf_len).min(bytes.len());
+            self.buf[self.buf_len..self.buf_len + take].copy_from_slice(&bytes[..take]);
+            self.buf_len += take;
+            bytes = &bytes[take..];
+            if self.buf_len < 16 {
+                return;
+            }
+            let block = self.buf;
+            self.consume_block(&block);
+            self.buf_len = 0;
+        }
+
+        while bytes.len() >= 16 {
+            self.consume_block(&bytes[..16]);
+            bytes = &bytes[16..];
+        }
+
+        self.buf[..bytes.len()].copy_from_slice(bytes);
+        self.buf_len = bytes.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let (mut h1, mut h2) = (self.h1, self.h2);
+
+        let tail = &self.buf[..self.buf_len];
+        let (mut k1, mut k2) = (0u64, 0u64);
+        for (i, b) in tail.iter().enumerate() {
+            if i < 8 {
+                k1 |= (*b as u64) << (8 * i);
+            } else {
+                k2 |= (*b as u64) << (8 * (i - 8));
+            }
+        }
+        if tail.len() > 8 {
+            h2 ^= murmur_k2(k2);
+        }
+        if !tail.is_empty() {
+            h1 ^= murmur_k1(k1);
+        }
+
+        h1 ^= self.total_len;
+        h2 ^= self.total_len;
+        h1 = h1.wrapping_add(h2);
+        h2 = h2.wrapping_add(h1);
+        h1 = murmur_fmix(h1);
+        h2 = murmur_fmix(h2);
+        h1.wrapping_add(h2)
+    }
+
+    portable_writes!();
+}
+
+/// SipHash-2-4, <https://www.aumasson.jp/siphash/siphash.pdf>.
+/// `Default` uses fixed all-zero keys rather than random ones.
+#[derive(Debug, Clone)]
+pub struct SipHash24 {
+    v: [u64; 4],
+    tail: u64,
+    tail_len: usize,
+    total_len: u64,
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+impl SipHash24 {
+    pub fn new_with_keys(k0: u64, k1: u64) -> SipHash24 {
+        SipHash24 {
+            v: [
+                k0 ^ 0x736f_6d65_7073_6575,
+                k1 ^ 0x646f_7261_6e64_6f6d,
+                k0 ^ 0x6c79_6765_6e65_7261,
+                k1 ^ 0x7465_6462_7974_6573,
+            ],
+            tail: 0,
+            tail_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn compress(v: &mut [u64; 4], m: u64) {
+        v[3] ^= m;
+        sip_round(v);
+        sip_round(v);
+        v[0] ^= m;
+    }
+}
+
+impl Default for SipHash24 {
+    fn default() -> Self {
+        SipHash24::new_with_keys(0, 0)
+    }
+}
+
+impl Hasher for SipHash24 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+        for b in bytes {
+            self.tail |= (*b as u64) << (8 * self.tail_len);
+            self.tail_len += 1;
+            if self.tail_len == 8 {
+                SipHash24::compress(&mut self.v, self.tail);
+                self.tail = 0;
+                self.tail_len = 0;
+            }
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut v = self.v;
+        let b = ((self.total_len & 0xff) << 56) | self.tail;
+        SipHash24::compress(&mut v, b);
+        v[2] ^= 0xff;
+        for _ in 0..4 {
+            sip_round(&mut v);
+        }
+        v[0] ^ v[1] ^ v[2] ^ v[3]
+    }
+
+    portable_writes!();
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::hash::Hasher;
+
+    use super::*;
+
+    fn digest<H: Hasher>(mut h: H, bytes: &[u8]) -> u64 {
+        h.write(bytes);
+        h.finish()
+    }
+
+    // feeds the input one byte at a time to exercise the buffering
+    fn digest_bytewise<H: Hasher>(mut h: H, bytes: &[u8]) -> u64 {
+        for b in bytes {
+            h.write(&[*b]);
+        }
+        h.finish()
+    }
+
+    const LONG: &[u8] = b"The quick brown fox jumps over the lazy dog, twice: The quick brown fox jumps over the lazy dog";
+
+    #[test]
+    fn fnv1a_golden_values() {
+        assert_eq!(digest(Fnv1a::default(), b""), 0xcbf29ce484222325);
+        assert_eq!(digest(Fnv1a::default(), b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(digest(Fnv1a::default(), b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn xxhash64_golden_values() {
+        assert_eq!(digest(XxHash64::default(), b""), 0xef46db3751d8e999);
+        assert_eq!(digest(XxHash64::default(), b"a"), 0xd24ec4f1a98c6e5b);
+        assert_eq!(digest(XxHash64::default(), b"abc"), 0x44bc2cf5ad770999);
+        assert_eq!(digest(XxHash64::default(), LONG), 0xa6d08bfeabb88a54);
+        assert_eq!(digest_bytewise(XxHash64::default(), LONG), 0xa6d08bfeabb88a54);
+        assert_eq!(digest(XxHash64::with_seed(42), b"abc"), 0x13c1d910702770e6);
+    }
+
+    #[test]
+    fn murmur3_golden_values() {
+        assert_eq!(digest(Murmur3::default(), b""), 0);
+        assert_eq!(digest(Murmur3::default(), b"hello"), 0xcbd8a7b341bd9b02);
+        assert_eq!(digest(Murmur3::default(), LONG), 0x6e1317341ed5b479);
+        assert_eq!(digest_bytewise(Murmur3::default(), LONG), 0x6e1317341ed5b479);
+    }
+
+    #[test]
+    fn siphash24_golden_values() {
+        // test vectors from the appendix of the SipHash paper
+        let k0 = u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7]);
+        let k1 = u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]);
+        let msg: Vec<u8> = (0..15).collect();
+
+        assert_eq!(digest(SipHash24::new_with_keys(k0, k1), b""), 0x726fdb47dd0e0e31);
+        assert_eq!(digest(SipHash24::new_with_keys(k0, k1), &msg), 0xa129ca6149be45e5);
+        assert_eq!(digest(SipHash24::default(), LONG), 0x9409c96e20cefd8d);
+    }
+
+    #[test]
+    fn integers_are_hashed_little_endian() {
+        let mut a = XxHash64::default();
+        a.write_u64(0x0102_0304_0506_0708);
+        a.write_usize(7);
+        let mut b = XxHash64::default();
+        b.write(&[8, 7, 6, 5, 4, 3, 2, 1]);
+        b.write(&[7, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(a.finish(), b.finish());
+    }
+
+}
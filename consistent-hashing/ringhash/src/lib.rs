@@ -1,102 +1,323 @@
+//! Here, we follow the implementation details outlined
+//! in Amazon's Dynamo system, Section 4.2 of their paper
+
 use std::cmp::Ordering;
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+
+pub mod bounded;
+pub mod description;
+pub mod hash;
+pub mod rebalance;
+pub mod shared;
+pub mod topology;
+
+use hash::BuildXxHash64;
+use topology::Topology;
+
+/// A strategy for placing keys on a set of nodes. Services written
+/// against this trait can swap placement algorithms by type parameter.
+/// Nodes are identified by their hash, so `remove` takes anything
+/// that hashes the same as the node that was added.
+pub trait ConsistentHasher<N> {
+    fn add(&mut self, node: N);
+
+    fn remove(&mut self, node: &N) -> Option<N>;
+
+    /// The node responsible for `k`, or `None` if there are no nodes
+    fn get<K: Hash>(&self, k: &K) -> Option<&N>;
+
+    /// The number of nodes keys are placed on
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 
-/// Here, we follow the implementation details outlined 
-/// in Amazon's Dynamo system, Section 4.2 of their paper
+/// The number of tokens a weight-1 node gets on a ring built with `new`
+const DEFAULT_REPLICAS: u32 = 1;
 
-struct VirtualNode<T> {
+/// A single token on the ring. Every physical node owns
+/// one or more of these, identified by the node's hash.
+#[derive(Clone)]
+struct VirtualNode {
     position: u64,
-    node: T,
+    id: u64,
 }
 
-impl<T> VirtualNode<T> {
-    fn new(position: u64, node: T) -> VirtualNode<T> {
-        VirtualNode { position, node }
-    } 
+impl VirtualNode {
+    fn new(position: u64, id: u64) -> VirtualNode {
+        VirtualNode { position, id }
+    }
 }
 
-impl<T> PartialEq for VirtualNode<T> {
-    fn eq(&self, other: &VirtualNode<T>) -> bool {
-        self.position == other.position
+impl PartialEq for VirtualNode {
+    fn eq(&self, other: &VirtualNode) -> bool {
+        self.position == other.position && self.id == other.id
     }
 }
 
-impl<T> Eq for VirtualNode<T> {}
+impl Eq for VirtualNode {}
 
-impl<T> PartialOrd for VirtualNode<T> {
-    fn partial_cmp(&self, other: &VirtualNode<T>) -> Option<Ordering> {
-        self.position.partial_cmp(&other.position)
+impl PartialOrd for VirtualNode {
+    fn partial_cmp(&self, other: &VirtualNode) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl<T> Ord for VirtualNode<T> {
-    fn cmp(&self, other: &VirtualNode<T>) -> Ordering {
-        self.position.cmp(&other.position)
+impl Ord for VirtualNode {
+    // ties on position are broken by id so the order
+    // doesn't depend on the order nodes were added in
+    fn cmp(&self, other: &VirtualNode) -> Ordering {
+        self.position
+            .cmp(&other.position)
+            .then(self.id.cmp(&other.id))
     }
 }
 
-pub struct HashRing<N> {
-    nodes: Vec<VirtualNode<N>>,
+#[derive(Clone)]
+struct PhysicalNode<N> {
+    node: N,
+    weight: u32,
+    topology: Topology,
+}
+
+/// Nodes and keys are placed with the hashing strategy `S`, which defaults
+/// to xxHash64 so that placement is the same in every process and build.
+/// See the [`hash`] module for the other stable built-ins.
+#[derive(Clone)]
+pub struct HashRing<N, S = BuildXxHash64> {
+    // tokens handed to a node of weight 1
+    replicas: u32,
+    nodes: Vec<VirtualNode>,
+    physical: HashMap<u64, PhysicalNode<N>>,
+    build_hasher: S,
+    // bumped on every membership change
+    epoch: u64,
 }
 
-fn calculate_hash<K: Hash>(k: &K) -> u64 {
-    let mut s = DefaultHasher::new();
-    k.hash(&mut s);
-    s.finish()
+impl<N: Hash, S: BuildHasher + Default> Default for HashRing<N, S> {
+    fn default() -> Self {
+        HashRing::with_replicas_and_hasher(DEFAULT_REPLICAS, S::default())
+    }
 }
 
 impl<N: Hash> HashRing<N> {
 
+    pub fn new() -> HashRing<N> {
+        HashRing::with_replicas(DEFAULT_REPLICAS)
+    }
+
+    /// Every node of weight 1 will be placed on the ring `replicas`
+    /// times, which evens out the size of the ranges each one owns
+    pub fn with_replicas(replicas: u32) -> HashRing<N> {
+        HashRing::with_replicas_and_hasher(replicas, BuildXxHash64::default())
+    }
+
+}
+
+impl<N: Hash, S: BuildHasher> HashRing<N, S> {
+
+    pub fn with_hasher(build_hasher: S) -> HashRing<N, S> {
+        HashRing::with_replicas_and_hasher(DEFAULT_REPLICAS, build_hasher)
+    }
+
+    pub fn with_replicas_and_hasher(replicas: u32, build_hasher: S) -> HashRing<N, S> {
+        HashRing {
+            replicas: replicas.max(1),
+            nodes: vec![],
+            physical: HashMap::new(),
+            build_hasher,
+            epoch: 0,
+        }
+    }
+
+    fn calculate_hash<K: Hash + ?Sized>(&self, k: &K) -> u64 {
+        self.build_hasher.hash_one(k)
+    }
+
+    /// Where `k` lands on the ring, e.g. to check which
+    /// [`rebalance::TokenRange`] it falls in
+    pub fn hash_key<K: Hash>(&self, k: &K) -> u64 {
+        self.calculate_hash(k)
+    }
+
+    /// The number of physical nodes on the ring
     pub fn len(&self) -> usize {
+        self.physical.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.physical.is_empty()
+    }
+
+    /// The number of virtual nodes (tokens) on the ring
+    pub fn token_count(&self) -> usize {
         self.nodes.len()
     }
 
-    pub fn new() -> HashRing<N> {
-        HashRing { nodes: vec![] }
+    /// The version of the ring's membership, which goes
+    /// up by one every time a node is added or removed
+    pub fn epoch(&self) -> u64 {
+        self.epoch
     }
 
     /// Each node in the system is assigned a random value within
-    /// the space which represents its "position" on the ring 
+    /// the space which represents its "position" on the ring
     pub fn add(&mut self, node: N) {
-        let node_hash = calculate_hash(&node);
-        let node = VirtualNode { position: node_hash, node };
-        self.nodes.push(node);
+        self.add_weighted(node, 1);
+    }
+
+    /// Adds `node` with `weight * replicas` virtual nodes, so a node
+    /// with twice the capacity can be given twice the keyspace.
+    /// Re-adding a node that is already present replaces its tokens
+    /// but keeps its topology.
+    pub fn add_weighted(&mut self, node: N, weight: u32) {
+        let weight = weight.max(1);
+        let id = self.calculate_hash(&node);
+        self.remove_tokens(id);
+        let topology = self
+            .physical
+            .remove(&id)
+            .map(|p| p.topology)
+            .unwrap_or_default();
+
+        let count = self.replicas.saturating_mul(weight);
+        for replica in 0..count {
+            let position = self.calculate_hash(&(id, replica));
+            self.nodes.push(VirtualNode::new(position, id));
+        }
         self.nodes.sort();
+        self.physical.insert(
+            id,
+            PhysicalNode {
+                node,
+                weight,
+                topology,
+            },
+        );
+        self.epoch += 1;
+    }
+
+    /// The weight `node` was added with, if it is on the ring
+    pub fn weight<K: Hash>(&self, k: &K) -> Option<u32> {
+        self.physical.get(&self.calculate_hash(k)).map(|p| p.weight)
     }
 
-    pub fn get<K: Hash>(&mut self, k: &K) -> Option<&N> {
+    pub fn get<K: Hash>(&self, k: &K) -> Option<&N> {
         if self.nodes.is_empty() {
             return None;
         }
 
-        let hash = calculate_hash(&k);
+        let pos = self.position(self.calculate_hash(k));
+        let id = self.nodes[pos].id;
+        self.physical.get(&id).map(|p| &p.node)
+    }
+
+    /// The first `n` distinct physical nodes found walking clockwise
+    /// from `k`, i.e. Dynamo's preference list for the key
+    pub fn get_n<K: Hash>(&self, k: &K, n: usize) -> Vec<&N> {
+        self.preference_list(k).take(n).collect()
+    }
+
+    /// Lazily walks the ring clockwise from `k`, yielding each physical
+    /// node the first time one of its virtual nodes is reached
+    pub fn preference_list<K: Hash>(&self, k: &K) -> PreferenceList<'_, N> {
+        let start = if self.nodes.is_empty() {
+            0
+        } else {
+            self.position(self.calculate_hash(k))
+        };
+        PreferenceList {
+            tokens: &self.nodes,
+            physical: &self.physical,
+            start,
+            offset: 0,
+            seen: HashSet::new(),
+        }
+    }
+
+    // index of the first token at or after `hash`, wrapping
+    // around to the start of the ring
+    fn position(&self, hash: u64) -> usize {
         let pos = match self.nodes.binary_search_by(|node| node.position.cmp(&hash)) {
             Ok(n) => n,
             Err(n) => n,
         };
         if pos == self.nodes.len() {
-            return Some(&self.nodes[0].node)
+            return 0;
         }
-        Some(&self.nodes[pos].node)
+        pos
     }
 
+    /// Removes the physical node and every one of its virtual nodes
     pub fn remove<K: Hash>(&mut self, k: &K) -> Option<N> {
-        let hash = calculate_hash(&k);
-        match self.nodes.binary_search_by(|node| node.position.cmp(&hash)) {
-            Ok(n) => Some(self.nodes.remove(n).node),
-            Err(_) => None,
-        }
+        let id = self.calculate_hash(k);
+        let removed = self.physical.remove(&id)?;
+        self.remove_tokens(id);
+        self.epoch += 1;
+        Some(removed.node)
+    }
+
+    fn remove_tokens(&mut self, id: u64) {
+        self.nodes.retain(|vn| vn.id != id);
+    }
+
+}
+
+impl<N: Hash, S: BuildHasher> ConsistentHasher<N> for HashRing<N, S> {
+    fn add(&mut self, node: N) {
+        HashRing::add(self, node)
     }
 
+    fn remove(&mut self, node: &N) -> Option<N> {
+        HashRing::remove(self, node)
+    }
+
+    fn get<K: Hash>(&self, k: &K) -> Option<&N> {
+        HashRing::get(self, k)
+    }
+
+    fn len(&self) -> usize {
+        HashRing::len(self)
+    }
+}
+
+pub struct PreferenceList<'a, N> {
+    tokens: &'a [VirtualNode],
+    physical: &'a HashMap<u64, PhysicalNode<N>>,
+    start: usize,
+    offset: usize,
+    // physical nodes already returned
+    seen: HashSet<u64>,
+}
+
+impl<'a, N> Iterator for PreferenceList<'a, N> {
+    type Item = &'a N;
+
+    fn next(&mut self) -> Option<&'a N> {
+        while self.offset < self.tokens.len() && self.seen.len() < self.physical.len() {
+            let vn = &self.tokens[(self.start + self.offset) % self.tokens.len()];
+            self.offset += 1;
+            if self.seen.insert(vn.id) {
+                return self.physical.get(&vn.id).map(|p| &p.node);
+            }
+        }
+        None
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use std::collections::HashMap;
     use std::net::{IpAddr, Ipv4Addr};
 
-    use super::HashRing;
+    use std::hash::BuildHasher;
+
+    use super::hash::{BuildFnv1a, BuildMurmur3, BuildSipHash24, BuildXxHash64};
+    use super::{ConsistentHasher, HashRing};
 
     #[derive(Hash, Debug)]
     struct RemoteCache {
@@ -138,7 +359,7 @@ mod tests {
 
         let present_cache = RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)) };
         let nonpresent_cache = RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 24)) };
-        
+
         let present_ref = ring.remove(&present_cache);
         assert!(present_ref.is_some());
 
@@ -165,4 +386,166 @@ mod tests {
 
     }
 
+    #[test]
+    fn add_weighted_nodes() {
+        let mut ring: HashRing<RemoteCache> = HashRing::with_replicas(100);
+
+        ring.add(RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)) });
+        ring.add_weighted(RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)) }, 3);
+
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.token_count(), 400);
+
+        let big = RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)) };
+        assert_eq!(ring.weight(&big), Some(3));
+
+        // re-adding replaces the tokens rather than duplicating them
+        ring.add(RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)) });
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.token_count(), 200);
+    }
+
+    #[test]
+    fn remove_node_removes_all_tokens() {
+        let mut ring: HashRing<RemoteCache> = HashRing::with_replicas(50);
+
+        for i in 1..=3 {
+            ring.add(RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, i)) });
+        }
+        assert_eq!(ring.token_count(), 150);
+
+        let removed = RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)) };
+        assert!(ring.remove(&removed).is_some());
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.token_count(), 100);
+
+        for i in 0..1000u32 {
+            let owner = ring.get(&i).unwrap();
+            assert_ne!(owner.addr, removed.addr);
+        }
+    }
+
+    #[test]
+    fn virtual_nodes_balance_keys() {
+        let mut ring: HashRing<RemoteCache> = HashRing::with_replicas(200);
+
+        for i in 1..=3 {
+            ring.add(RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, i)) });
+        }
+        ring.add_weighted(RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 4)) }, 2);
+
+        let mut counts: HashMap<IpAddr, usize> = HashMap::new();
+        let n_keys = 50_000u32;
+        for i in 0..n_keys {
+            let owner = ring.get(&i).unwrap();
+            *counts.entry(owner.addr).or_insert(0) += 1;
+        }
+
+        // each weight-1 node expects 1/5 of the keys, the weight-2 node 2/5
+        for i in 1..=3 {
+            let share = counts[&IpAddr::V4(Ipv4Addr::new(127, 0, 0, i))] as f64 / n_keys as f64;
+            assert!((share - 0.2).abs() < 0.05, "share was {}", share);
+        }
+        let share = counts[&IpAddr::V4(Ipv4Addr::new(127, 0, 0, 4))] as f64 / n_keys as f64;
+        assert!((share - 0.4).abs() < 0.07, "share was {}", share);
+    }
+
+    fn placements<S: BuildHasher>(mut ring: HashRing<&'static str, S>) -> Vec<&'static str> {
+        for node in &["cache-a", "cache-b", "cache-c", "cache-d"] {
+            ring.add(node);
+        }
+        ["alpha", "bravo", "charlie", "delta", "echo", "foxtrot"]
+            .iter()
+            .map(|k| *ring.get(k).unwrap())
+            .collect()
+    }
+
+    // these must never change: another process, built with any
+    // toolchain, has to put the same keys on the same nodes
+    #[test]
+    fn placement_is_stable_across_builds() {
+        assert_eq!(
+            placements(HashRing::with_replicas_and_hasher(10, BuildFnv1a::default())),
+            vec!["cache-c", "cache-b", "cache-c", "cache-c", "cache-c", "cache-c"]
+        );
+        assert_eq!(
+            placements(HashRing::with_replicas_and_hasher(10, BuildXxHash64::default())),
+            vec!["cache-c", "cache-c", "cache-d", "cache-d", "cache-b", "cache-a"]
+        );
+        assert_eq!(
+            placements(HashRing::with_replicas_and_hasher(10, BuildMurmur3::default())),
+            vec!["cache-b", "cache-c", "cache-b", "cache-d", "cache-b", "cache-a"]
+        );
+        assert_eq!(
+            placements(HashRing::with_replicas_and_hasher(10, BuildSipHash24::default())),
+            vec!["cache-c", "cache-c", "cache-d", "cache-a", "cache-c", "cache-d"]
+        );
+    }
+
+    #[test]
+    fn get_replicas() {
+        let mut ring: HashRing<RemoteCache> = HashRing::with_replicas(20);
+
+        for i in 1..=5 {
+            ring.add(RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, i)) });
+        }
+
+        for i in 0..100u32 {
+            let replicas: Vec<IpAddr> = ring.get_n(&i, 3).iter().map(|c| c.addr).collect();
+            assert_eq!(replicas.len(), 3);
+            assert_ne!(replicas[0], replicas[1]);
+            assert_ne!(replicas[0], replicas[2]);
+            assert_ne!(replicas[1], replicas[2]);
+
+            // the coordinator is the node `get` would have returned
+            assert_eq!(replicas[0], ring.get(&i).unwrap().addr);
+        }
+
+        // asking for more replicas than nodes returns every node once
+        assert_eq!(ring.get_n(b"test key", 10).len(), 5);
+        assert_eq!(ring.preference_list(b"test key").count(), 5);
+    }
+
+    #[test]
+    fn preference_list_walks_clockwise() {
+        let mut ring: HashRing<RemoteCache> = HashRing::with_replicas(20);
+
+        for i in 1..=4 {
+            ring.add(RemoteCache { addr: std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, i)) });
+        }
+
+        let eager: Vec<IpAddr> = ring.get_n(b"test key", 4).iter().map(|c| c.addr).collect();
+        let lazy: Vec<IpAddr> = ring.preference_list(b"test key").map(|c| c.addr).collect();
+        assert_eq!(eager, lazy);
+
+        // removing the coordinator promotes the rest of the list in order
+        let coordinator = RemoteCache { addr: eager[0] };
+        ring.remove(&coordinator);
+        let after: Vec<IpAddr> = ring.get_n(b"test key", 3).iter().map(|c| c.addr).collect();
+        assert_eq!(after, eager[1..].to_vec());
+    }
+
+    #[test]
+    fn preference_list_of_empty_ring() {
+        let ring: HashRing<RemoteCache> = HashRing::new();
+        assert!(ring.get_n(b"test key", 3).is_empty());
+    }
+
+    fn spread<H: ConsistentHasher<u32>>(hasher: &mut H) -> usize {
+        for node in 0..4 {
+            hasher.add(node);
+        }
+        let owners: std::collections::HashSet<u32> =
+            (0..1000u32).map(|k| *hasher.get(&k).unwrap()).collect();
+        hasher.remove(&0);
+        owners.len()
+    }
+
+    #[test]
+    fn ring_as_consistent_hasher() {
+        let mut ring: HashRing<u32> = HashRing::with_replicas(10);
+        assert_eq!(spread(&mut ring), 4);
+        assert_eq!(ConsistentHasher::len(&ring), 3);
+    }
+
 }
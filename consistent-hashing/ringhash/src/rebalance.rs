@@ -0,0 +1,249 @@
+//! Working out which parts of the keyspace change owner when nodes
+//! join or leave the ring, so that only the affected data is streamed
+//! between nodes.
+
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{HashRing, PreferenceList};
+
+/// An inclusive range of hashes on the ring. Ranges never wrap past
+/// `u64::MAX`; a region that does is split in two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TokenRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl TokenRange {
+    pub fn contains(&self, hash: u64) -> bool {
+        self.start <= hash && hash <= self.end
+    }
+
+    /// The number of hashes in the range
+    pub fn size(&self) -> u128 {
+        (self.end - self.start) as u128 + 1
+    }
+}
+
+/// A range whose keys must move from `from` to `to`
+#[derive(Debug)]
+pub struct Transfer<'a, N> {
+    pub range: TokenRange,
+    pub from: &'a N,
+    pub to: &'a N,
+}
+
+/// Every range of the keyspace owned by a different physical node in
+/// `after` than in `before`, in ring order. Adjacent ranges moving
+/// between the same pair of nodes are merged. If either ring is empty
+/// there is no data to move, so the plan is empty.
+pub fn plan<'a, N: Hash, S: BuildHasher>(
+    before: &'a HashRing<N, S>,
+    after: &'a HashRing<N, S>,
+) -> Vec<Transfer<'a, N>> {
+    let mut transfers: Vec<Transfer<'a, N>> = vec![];
+    if before.nodes.is_empty() || after.nodes.is_empty() {
+        return transfers;
+    }
+
+    // ownership can only change at a token of either ring
+    let mut boundaries: Vec<u64> = before
+        .nodes
+        .iter()
+        .chain(after.nodes.iter())
+        .map(|vn| vn.position)
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut ranges = vec![];
+    let mut start = 0;
+    for end in boundaries.iter() {
+        ranges.push(TokenRange { start, end: *end });
+        if *end == u64::MAX {
+            break;
+        }
+        start = end + 1;
+    }
+    if boundaries.last() != Some(&u64::MAX) {
+        ranges.push(TokenRange { start, end: u64::MAX });
+    }
+
+    // owners of the last transfer, to merge adjacent ranges
+    let mut last_ids = None;
+    for range in ranges {
+        // every hash in a range belongs to the token at its end
+        let from = before.nodes[before.position(range.end)].id;
+        let to = after.nodes[after.position(range.end)].id;
+        if from == to {
+            last_ids = None;
+            continue;
+        }
+
+        if last_ids == Some((from, to)) {
+            if let Some(last) = transfers.last_mut() {
+                last.range.end = range.end;
+                continue;
+            }
+        }
+
+        transfers.push(Transfer {
+            range,
+            from: &before.physical[&from].node,
+            to: &after.physical[&to].node,
+        });
+        last_ids = Some((from, to));
+    }
+
+    transfers
+}
+
+/// The range of hashes each token owns, along with the first `n`
+/// distinct nodes found walking clockwise from it, i.e. the replicas
+/// of every key in the range. Ranges are in ring order and cover the
+/// whole keyspace.
+pub fn replica_sets<N: Hash, S: BuildHasher>(
+    ring: &HashRing<N, S>,
+    n: usize,
+) -> Vec<(TokenRange, Vec<&N>)> {
+    let replicas = |start| {
+        PreferenceList {
+            tokens: &ring.nodes,
+            physical: &ring.physical,
+            start,
+            offset: 0,
+            seen: HashSet::new(),
+        }
+        .take(n)
+        .collect::<Vec<&N>>()
+    };
+
+    let mut sets = vec![];
+    let mut start = 0;
+    for (i, vn) in ring.nodes.iter().enumerate() {
+        // tokens colliding on the same position own nothing
+        if vn.position < start {
+            continue;
+        }
+        sets.push((TokenRange { start, end: vn.position }, replicas(i)));
+        if vn.position == u64::MAX {
+            return sets;
+        }
+        start = vn.position + 1;
+    }
+    // hashes after the last token wrap around to the first
+    if !ring.nodes.is_empty() {
+        sets.push((TokenRange { start, end: u64::MAX }, replicas(0)));
+    }
+    sets
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const RING_SIZE: f64 = u64::MAX as f64 + 1.0;
+
+    fn ring(nodes: u32) -> HashRing<String> {
+        let mut ring = HashRing::with_replicas(100);
+        for i in 0..nodes {
+            ring.add(format!("node-{}", i));
+        }
+        ring
+    }
+
+    fn moved_fraction(transfers: &[Transfer<'_, String>]) -> f64 {
+        transfers.iter().map(|t| t.range.size() as f64).sum::<f64>() / RING_SIZE
+    }
+
+    #[test]
+    fn unchanged_ring_moves_nothing() {
+        let before = ring(5);
+        let after = before.clone();
+        assert!(plan(&before, &after).is_empty());
+    }
+
+    #[test]
+    fn node_join_only_moves_keys_to_new_node() {
+        let before = ring(10);
+        let mut after = before.clone();
+        after.add("node-new".to_string());
+
+        let transfers = plan(&before, &after);
+        assert!(transfers.iter().all(|t| t.to == "node-new"));
+
+        // the new node should take about 1/11th of the keyspace
+        let moved = moved_fraction(&transfers);
+        assert!((moved - 1.0 / 11.0).abs() < 0.03, "moved {}", moved);
+    }
+
+    #[test]
+    fn node_leave_only_moves_keys_from_old_node() {
+        let before = ring(10);
+        let mut after = before.clone();
+        after.remove(&"node-3".to_string());
+
+        let transfers = plan(&before, &after);
+        assert!(transfers.iter().all(|t| t.from == "node-3"));
+
+        let moved = moved_fraction(&transfers);
+        assert!((moved - 1.0 / 10.0).abs() < 0.03, "moved {}", moved);
+    }
+
+    #[test]
+    fn plan_covers_exactly_the_moved_keys() {
+        let before = ring(8);
+        let mut after = before.clone();
+        after.remove(&"node-1".to_string());
+        after.add("node-8".to_string());
+        after.add_weighted("node-9".to_string(), 2);
+
+        let transfers = plan(&before, &after);
+        for w in transfers.windows(2) {
+            assert!(w[0].range.end < w[1].range.start);
+        }
+
+        let mut moved = 0;
+        for i in 0..20_000u32 {
+            let hash = before.hash_key(&i);
+            let old = before.get_n(&i, 1)[0].clone();
+            let new = after.get_n(&i, 1)[0].clone();
+            let transfer = transfers.iter().find(|t| t.range.contains(hash));
+
+            match transfer {
+                Some(t) => {
+                    assert_eq!(*t.from, old);
+                    assert_eq!(*t.to, new);
+                    moved += 1;
+                }
+                None => assert_eq!(old, new),
+            }
+        }
+        assert!(moved > 0);
+    }
+
+    #[test]
+    fn replica_sets_cover_the_keyspace() {
+        let ring = ring(6);
+        let sets = replica_sets(&ring, 3);
+        assert_eq!(sets.len(), ring.token_count() + 1);
+        assert_eq!(sets[0].0.start, 0);
+        assert_eq!(sets.last().unwrap().0.end, u64::MAX);
+        for w in sets.windows(2) {
+            assert_eq!(w[0].0.end + 1, w[1].0.start);
+        }
+
+        for i in 0..5_000u32 {
+            let hash = ring.hash_key(&i);
+            let (_, replicas) = sets.iter().find(|(r, _)| r.contains(hash)).unwrap();
+            assert_eq!(*replicas, ring.get_n(&i, 3));
+        }
+
+        assert!(replica_sets(&HashRing::<String>::new(), 3).is_empty());
+    }
+
+}
@@ -0,0 +1,142 @@
+//! A ring that can be shared between threads without locking lookups.
+//!
+//! Readers load the current ring, an immutable snapshot behind an
+//! `Arc`, without blocking. Membership changes copy the ring, apply the
+//! change to the copy and atomically publish it as the new snapshot, so
+//! a lookup sees either the old ring or the new one, never a mix.
+
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+
+use crate::hash::BuildXxHash64;
+use crate::HashRing;
+
+pub struct SharedRing<N, S = BuildXxHash64> {
+    current: ArcSwap<HashRing<N, S>>,
+    // writers take turns so that concurrent
+    // updates can't overwrite each other
+    writer: Mutex<()>,
+}
+
+impl<N: Hash + Clone, S: BuildHasher + Clone> SharedRing<N, S> {
+    pub fn new(ring: HashRing<N, S>) -> SharedRing<N, S> {
+        SharedRing {
+            current: ArcSwap::from_pointee(ring),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// The current ring. It won't change underneath the caller,
+    /// so several lookups against it are consistent with each other.
+    pub fn snapshot(&self) -> Arc<HashRing<N, S>> {
+        self.current.load_full()
+    }
+
+    pub fn get<K: Hash>(&self, k: &K) -> Option<N> {
+        self.current.load().get(k).cloned()
+    }
+
+    pub fn get_n<K: Hash>(&self, k: &K, n: usize) -> Vec<N> {
+        self.current
+            .load()
+            .get_n(k, n)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Applies `change` to a copy of the current ring and publishes it
+    pub fn update<F, T>(&self, change: F) -> T
+    where
+        F: FnOnce(&mut HashRing<N, S>) -> T,
+    {
+        let _guard = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let mut next = HashRing::clone(&self.current.load());
+        let res = change(&mut next);
+        self.current.store(Arc::new(next));
+        res
+    }
+
+    pub fn add(&self, node: N) {
+        self.update(|ring| ring.add(node))
+    }
+
+    pub fn add_weighted(&self, node: N, weight: u32) {
+        self.update(|ring| ring.add_weighted(node, weight))
+    }
+
+    pub fn remove<K: Hash>(&self, k: &K) -> Option<N> {
+        self.update(|ring| ring.remove(k))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::SharedRing;
+    use crate::HashRing;
+
+    #[test]
+    fn snapshots_are_immutable() {
+        let shared = SharedRing::new(HashRing::with_replicas(10));
+        shared.add("a".to_string());
+        shared.add("b".to_string());
+
+        let before = shared.snapshot();
+        assert_eq!(shared.remove(&"a".to_string()), Some("a".to_string()));
+
+        assert_eq!(before.len(), 2);
+        assert_eq!(shared.snapshot().len(), 1);
+        for k in 0..100u32 {
+            assert_eq!(shared.get(&k), Some("b".to_string()));
+        }
+    }
+
+    #[test]
+    fn concurrent_lookups_and_updates() {
+        let shared = Arc::new(SharedRing::new(HashRing::with_replicas(10)));
+        for i in 0..4 {
+            shared.add(format!("stable-{}", i));
+        }
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for k in 0..5_000u32 {
+                        // the stable nodes are never removed, so
+                        // every snapshot has an owner for every key
+                        assert!(shared.get(&k).is_some());
+                    }
+                })
+            })
+            .collect();
+
+        let writers: Vec<_> = (0..2)
+            .map(|w| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for i in 0..50 {
+                        let node = format!("flapping-{}-{}", w, i);
+                        shared.add(node.clone());
+                        assert_eq!(shared.remove(&node), Some(node));
+                    }
+                    shared.add(format!("joined-{}", w));
+                })
+            })
+            .collect();
+
+        for t in readers.into_iter().chain(writers) {
+            t.join().unwrap();
+        }
+
+        // no update was lost
+        assert_eq!(shared.snapshot().len(), 6);
+    }
+
+}
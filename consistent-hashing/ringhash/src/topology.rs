@@ -0,0 +1,190 @@
+//! Failure domain aware replica placement. Nodes can be labelled with
+//! the zone and rack they live in, and replicas are spread across as
+//! many distinct zones (and then racks) as the ring allows.
+
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+
+use serde::{Deserialize, Serialize};
+
+use crate::HashRing;
+
+/// Where a node lives. Nodes added without one share the empty
+/// zone and rack, so they're treated as one failure domain.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Topology {
+    pub zone: String,
+    pub rack: String,
+}
+
+impl Topology {
+    pub fn new(zone: impl ToString, rack: impl ToString) -> Topology {
+        Topology {
+            zone: zone.to_string(),
+            rack: rack.to_string(),
+        }
+    }
+}
+
+impl<N: Hash, S: BuildHasher> HashRing<N, S> {
+    /// Like `add_weighted`, labelling the node with its failure domain
+    pub fn add_with_topology(&mut self, node: N, weight: u32, topology: Topology) {
+        let id = self.calculate_hash(&node);
+        self.add_weighted(node, weight);
+        if let Some(p) = self.physical.get_mut(&id) {
+            p.topology = topology;
+        }
+    }
+
+    pub fn topology<K: Hash>(&self, k: &K) -> Option<&Topology> {
+        self.physical
+            .get(&self.calculate_hash(k))
+            .map(|p| &p.topology)
+    }
+
+    /// Up to `n` replicas for `k` spread over as many failure domains as
+    /// possible. Walking clockwise from `k`, nodes in a zone not chosen
+    /// yet are taken first, then nodes in a rack not chosen yet, then
+    /// anything left in ring order. The first replica is always the
+    /// node `get` returns.
+    pub fn get_n_spread<K: Hash>(&self, k: &K, n: usize) -> Vec<&N> {
+        let candidates: Vec<(&N, &Topology)> = self
+            .preference_list(k)
+            .map(|node| (node, &self.physical[&self.calculate_hash(node)].topology))
+            .collect();
+
+        let mut chosen = vec![false; candidates.len()];
+        let mut replicas = Vec::with_capacity(n.min(candidates.len()));
+        let mut zones: HashSet<&str> = HashSet::new();
+        let mut racks: HashSet<(&str, &str)> = HashSet::new();
+
+        // first nodes in new zones, then in new racks, then anything
+        for pass in 0..3 {
+            for (i, (node, topology)) in candidates.iter().enumerate() {
+                if replicas.len() == n {
+                    return replicas;
+                }
+                let zone = topology.zone.as_str();
+                let rack = topology.rack.as_str();
+                let accept = match pass {
+                    0 => !zones.contains(zone),
+                    1 => !racks.contains(&(zone, rack)),
+                    _ => true,
+                };
+                if chosen[i] || !accept {
+                    continue;
+                }
+                chosen[i] = true;
+                zones.insert(zone);
+                racks.insert((zone, rack));
+                replicas.push(*node);
+            }
+        }
+        replicas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashSet;
+
+    use super::Topology;
+    use crate::HashRing;
+
+    // (name, zone, rack)
+    fn ring(nodes: &[(&'static str, &str, &str)]) -> HashRing<&'static str> {
+        let mut ring = HashRing::with_replicas(20);
+        for (name, zone, rack) in nodes {
+            ring.add_with_topology(*name, 1, Topology::new(zone, rack));
+        }
+        ring
+    }
+
+    fn zones<'a>(ring: &'a HashRing<&'static str>, replicas: &[&&'static str]) -> HashSet<&'a str> {
+        replicas
+            .iter()
+            .map(|n| ring.topology(*n).unwrap().zone.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn replicas_span_distinct_zones() {
+        let ring = ring(&[
+            ("a1", "us-east-1a", "r1"),
+            ("a2", "us-east-1a", "r2"),
+            ("a3", "us-east-1a", "r3"),
+            ("b1", "us-east-1b", "r1"),
+            ("b2", "us-east-1b", "r2"),
+            ("c1", "us-east-1c", "r1"),
+        ]);
+
+        for k in 0..500u32 {
+            let replicas = ring.get_n_spread(&k, 3);
+            assert_eq!(replicas.len(), 3);
+            assert_eq!(zones(&ring, &replicas).len(), 3);
+            assert_eq!(replicas[0], ring.get(&k).unwrap());
+        }
+    }
+
+    #[test]
+    fn falls_back_to_distinct_racks_then_ring_order() {
+        let ring = ring(&[
+            ("a1", "zone-a", "r1"),
+            ("a2", "zone-a", "r2"),
+            ("a3", "zone-a", "r2"),
+            ("b1", "zone-b", "r1"),
+        ]);
+
+        for k in 0..500u32 {
+            let replicas = ring.get_n_spread(&k, 3);
+            assert_eq!(zones(&ring, &replicas).len(), 2);
+
+            // both zones, then the zone-a rack not used yet
+            let racks: HashSet<(String, String)> = replicas
+                .iter()
+                .map(|n| {
+                    let t = ring.topology(*n).unwrap();
+                    (t.zone.clone(), t.rack.clone())
+                })
+                .collect();
+            assert_eq!(racks.len(), 3);
+
+            // and everything, once racks run out
+            let all = ring.get_n_spread(&k, 10);
+            assert_eq!(all.len(), 4);
+            assert_eq!(all[..3], replicas[..]);
+
+            // placement only depends on the ring
+            assert_eq!(ring.get_n_spread(&k, 3), replicas);
+        }
+    }
+
+    #[test]
+    fn unlabelled_nodes_fall_back_to_preference_list() {
+        let mut ring = HashRing::with_replicas(20);
+        for name in &["a", "b", "c", "d"] {
+            ring.add(*name);
+        }
+        for k in 0..100u32 {
+            assert_eq!(ring.get_n_spread(&k, 3), ring.get_n(&k, 3));
+        }
+    }
+
+    #[test]
+    fn reweighting_a_node_keeps_its_topology() {
+        let mut ring = ring(&[
+            ("a1", "zone-a", "r1"),
+            ("a2", "zone-a", "r2"),
+            ("b1", "zone-b", "r1"),
+        ]);
+        ring.add_weighted("b1", 3);
+
+        assert_eq!(ring.weight(&"b1"), Some(3));
+        assert_eq!(ring.topology(&"b1"), Some(&Topology::new("zone-b", "r1")));
+        for k in 0..500u32 {
+            assert_eq!(zones(&ring, &ring.get_n_spread(&k, 2)).len(), 2);
+        }
+    }
+
+}
@@ -0,0 +1,182 @@
+//! Properties every ring should have, checked on randomly generated
+//! memberships and keys rather than a handful of hand picked ones.
+
+use std::hash::{BuildHasher, Hasher};
+
+use proptest::prelude::*;
+use ringhash::HashRing;
+
+// the ring the node ids `nodes` make, each with `replicas` tokens
+fn build_ring<S: BuildHasher + Default>(nodes: &[u32], replicas: u32) -> HashRing<u32, S> {
+    let mut ring = HashRing::with_replicas_and_hasher(replicas, S::default());
+    for node in nodes {
+        ring.add(*node);
+    }
+    ring
+}
+
+fn default_ring(nodes: &[u32], replicas: u32) -> HashRing<u32> {
+    build_ring(nodes, replicas)
+}
+
+/// Places a single `u64` at its own value, so tests can pick exactly
+/// where on the ring a key lands, e.g. past the last token. Anything
+/// else (like the `(id, replica)` pairs tokens are made from) is mixed.
+#[derive(Default)]
+struct Positional(u64);
+
+impl Hasher for Positional {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.write_u64(*b as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = self.0.wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(29) ^ n;
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Default, Clone)]
+struct BuildPositional;
+
+impl BuildHasher for BuildPositional {
+    type Hasher = Positional;
+
+    fn build_hasher(&self) -> Positional {
+        Positional::default()
+    }
+}
+
+// who should own `hash`: the node of the first token at or after it
+// (ties going to the lower node id), wrapping around to the first token
+fn expected_owner<S: BuildHasher>(ring: &HashRing<u32, S>, hash: u64) -> Option<u32> {
+    let mut tokens: Vec<(u64, u64, u32)> = ring
+        .describe()
+        .nodes
+        .iter()
+        .flat_map(|n| n.tokens.iter().map(move |t| (*t, ring.hash_key(&n.node), n.node)))
+        .collect();
+    tokens.sort_unstable();
+    tokens
+        .iter()
+        .find(|(position, _, _)| *position >= hash)
+        .or_else(|| tokens.first())
+        .map(|(_, _, node)| *node)
+}
+
+fn nodes() -> impl Strategy<Value = Vec<u32>> {
+    prop::collection::hash_set(any::<u32>(), 1..12).prop_map(|s| s.into_iter().collect())
+}
+
+proptest! {
+
+    #[test]
+    fn adding_a_node_only_moves_keys_to_it(
+        nodes in nodes(),
+        added in any::<u32>(),
+        replicas in 1u32..50,
+        keys in prop::collection::vec(any::<u64>(), 200),
+    ) {
+        prop_assume!(!nodes.contains(&added));
+        let before = default_ring(&nodes, replicas);
+        let mut after = before.clone();
+        after.add(added);
+
+        for k in keys.iter() {
+            let (old, new) = (before.get(k).unwrap(), after.get(k).unwrap());
+            prop_assert!(new == old || *new == added, "{} moved from {} to {}", k, old, new);
+        }
+    }
+
+    #[test]
+    fn removing_a_node_only_moves_its_keys(
+        nodes in nodes(),
+        replicas in 1u32..50,
+        which in any::<prop::sample::Index>(),
+        keys in prop::collection::vec(any::<u64>(), 200),
+    ) {
+        prop_assume!(nodes.len() > 1);
+        let removed = nodes[which.index(nodes.len())];
+        let before = default_ring(&nodes, replicas);
+        let mut after = before.clone();
+        prop_assert_eq!(after.remove(&removed), Some(removed));
+
+        for k in keys.iter() {
+            let old = *before.get(k).unwrap();
+            let new = *after.get(k).unwrap();
+            prop_assert!(new != removed);
+            prop_assert!(old == new || old == removed, "{} moved from {} to {}", k, old, new);
+        }
+    }
+
+    #[test]
+    fn placement_is_deterministic(
+        nodes in nodes(),
+        replicas in 1u32..50,
+        keys in prop::collection::vec(any::<u64>(), 100),
+        seed in any::<u64>(),
+    ) {
+        // the same members added in a different order
+        let mut shuffled = nodes.clone();
+        shuffled.sort_unstable_by_key(|n| (*n as u64).wrapping_mul(seed | 1).rotate_left(17));
+
+        let a = default_ring(&nodes, replicas);
+        let b = default_ring(&shuffled, replicas);
+        for k in keys.iter() {
+            prop_assert_eq!(a.get(k), b.get(k));
+            prop_assert_eq!(a.get_n(k, 3), b.get_n(k, 3));
+        }
+    }
+
+    #[test]
+    fn lookups_match_a_linear_scan(
+        nodes in nodes(),
+        replicas in 1u32..20,
+        keys in prop::collection::vec(any::<u64>(), 100),
+    ) {
+        let ring = default_ring(&nodes, replicas);
+        for k in keys.iter() {
+            prop_assert_eq!(ring.get(k).copied(), expected_owner(&ring, ring.hash_key(k)));
+        }
+    }
+
+    #[test]
+    fn lookups_wrap_around_the_ring(
+        nodes in nodes(),
+        replicas in 1u32..20,
+        offsets in prop::collection::vec(any::<u64>(), 50),
+    ) {
+        let ring: HashRing<u32, BuildPositional> = build_ring(&nodes, replicas);
+        let mut tokens: Vec<u64> = ring.describe().nodes.iter().flat_map(|n| n.tokens.clone()).collect();
+        tokens.sort_unstable();
+        let (first, last) = (tokens[0], tokens[tokens.len() - 1]);
+
+        // keys are placed at their own value with this hasher
+        prop_assert_eq!(ring.hash_key(&last), last);
+
+        // past the last token, and before (or on) the first, belong to
+        // the first token's node, as do the extremes of the key space
+        let wrapped = expected_owner(&ring, first);
+        prop_assert_eq!(ring.get(&u64::MAX).copied(), expected_owner(&ring, u64::MAX));
+        prop_assert_eq!(ring.get(&0u64).copied(), expected_owner(&ring, 0));
+        for offset in offsets.iter() {
+            if last < u64::MAX {
+                let past = last + 1 + offset % (u64::MAX - last);
+                prop_assert_eq!(ring.get(&past).copied(), wrapped);
+            }
+            let before = offset % first.saturating_add(1);
+            prop_assert_eq!(ring.get(&before).copied(), wrapped);
+        }
+
+        // a key exactly on a token belongs to that token's node
+        for token in tokens.iter() {
+            prop_assert_eq!(ring.get(token).copied(), expected_owner(&ring, *token));
+        }
+    }
+
+}
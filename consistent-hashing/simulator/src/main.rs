@@ -0,0 +1,174 @@
+use std::env;
+use std::process;
+
+use jumphash::JumpHash;
+use maglev::Maglev;
+use multiprobe::MultiProbe;
+use rendezvous::Rendezvous;
+use ringhash::HashRing;
+
+mod simulation;
+use simulation::{simulate, AnchorNodes, Stats};
+
+const ALGORITHMS: &[&str] = &["ring", "jump", "rendezvous", "maglev", "multiprobe", "anchor"];
+
+const USAGE: &str = "usage: simulator [--keys N] [--nodes N,..] [--vnodes N,..] [--algorithms NAME,..]
+
+Hashes N synthetic keys onto each node count with each algorithm and
+prints one CSV row per run, measuring how many keys move when a node
+joins and when one leaves, so needs at least 2 nodes. --vnodes only
+applies to the ring.
+Algorithms: ring, jump, rendezvous, maglev, multiprobe, anchor";
+
+#[derive(Debug, PartialEq)]
+struct Config {
+    keys: u64,
+    nodes: Vec<u32>,
+    vnodes: Vec<u32>,
+    algorithms: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            keys: 1_000_000,
+            nodes: vec![10, 100],
+            vnodes: vec![1, 10, 100, 1000],
+            algorithms: ALGORITHMS.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+}
+
+fn parse_list(value: &str) -> Result<Vec<u32>, String> {
+    value
+        .split(',')
+        .map(|v| v.parse().map_err(|_| format!("not a number: {}", v)))
+        .collect()
+}
+
+fn parse_args(args: &[String]) -> Result<Config, String> {
+    let mut config = Config::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--keys" => {
+                config.keys = value
+                    .parse()
+                    .map_err(|_| format!("not a number: {}", value))?
+            }
+            "--nodes" => config.nodes = parse_list(value)?,
+            "--vnodes" => config.vnodes = parse_list(value)?,
+            "--algorithms" => {
+                config.algorithms = value.split(',').map(|a| a.to_string()).collect();
+                if let Some(a) = config.algorithms.iter().find(|a| !ALGORITHMS.contains(&a.as_str())) {
+                    return Err(format!("unknown algorithm: {}", a));
+                }
+            }
+            _ => return Err(format!("unknown flag: {}", flag)),
+        }
+    }
+
+    if config.keys == 0 || config.nodes.contains(&0) || config.vnodes.contains(&0) {
+        return Err("keys, nodes and vnodes must be positive".to_string());
+    }
+    // one node leaves, and there has to be one left to take its keys
+    if config.nodes.contains(&1) {
+        return Err("nodes must be at least 2".to_string());
+    }
+    Ok(config)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match parse_args(&args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+
+    println!("{}", Stats::CSV_HEADER);
+    for algorithm in config.algorithms.iter() {
+        for nodes in config.nodes.iter().copied() {
+            let keys = config.keys;
+            let row = |stats: Stats, vnodes| stats.to_csv(algorithm, nodes, vnodes, keys);
+
+            match algorithm.as_str() {
+                "ring" => {
+                    for vnodes in config.vnodes.iter().copied() {
+                        let stats = simulate(HashRing::with_replicas(vnodes), nodes, keys);
+                        println!("{}", row(stats, Some(vnodes)));
+                    }
+                }
+                "jump" => println!("{}", row(simulate(JumpHash::new(), nodes, keys), None)),
+                "rendezvous" => println!("{}", row(simulate(Rendezvous::new(), nodes, keys), None)),
+                "maglev" => println!("{}", row(simulate(Maglev::new(), nodes, keys), None)),
+                "multiprobe" => println!("{}", row(simulate(MultiProbe::new(), nodes, keys), None)),
+                // leave headroom in the anchor set, as a deployment would
+                "anchor" => {
+                    let stats = simulate(AnchorNodes::new(nodes * 2), nodes, keys);
+                    println!("{}", row(stats, None));
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_flags() {
+        let config = parse_args(&args("--keys 500 --nodes 3,5 --algorithms ring,anchor")).unwrap();
+        assert_eq!(config.keys, 500);
+        assert_eq!(config.nodes, vec![3, 5]);
+        assert_eq!(config.vnodes, Config::default().vnodes);
+        assert_eq!(config.algorithms, vec!["ring", "anchor"]);
+
+        assert_eq!(parse_args(&[]).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn rejects_bad_flags() {
+        assert!(parse_args(&args("--keys")).is_err());
+        assert!(parse_args(&args("--keys many")).is_err());
+        assert!(parse_args(&args("--nodes 0")).is_err());
+        assert!(parse_args(&args("--nodes 1,10")).is_err());
+        assert!(parse_args(&args("--algorithms ring,chord")).is_err());
+        assert!(parse_args(&args("--verbose true")).is_err());
+    }
+
+    #[test]
+    fn minimal_disruption_algorithms_move_one_nth() {
+        let keys = 20_000;
+        for stats in [
+            simulate(HashRing::with_replicas(200), 10, keys),
+            simulate(Rendezvous::new(), 10, keys),
+            simulate(AnchorNodes::new(20), 10, keys),
+        ] {
+            assert!((stats.moved_join - 1.0 / 11.0).abs() < 0.02, "{:?}", stats);
+            assert!((stats.moved_leave - 1.0 / 10.0).abs() < 0.02, "{:?}", stats);
+            assert!(stats.max_mean < 1.2, "{:?}", stats);
+        }
+    }
+
+    #[test]
+    fn csv_rows_match_header() {
+        let stats = simulate(JumpHash::new(), 4, 1000);
+        let columns = Stats::CSV_HEADER.split(',').count();
+        assert_eq!(stats.to_csv("jump", 4, None, 1000).split(',').count(), columns);
+        assert_eq!(stats.to_csv("ring", 4, Some(10), 1000).split(',').count(), columns);
+    }
+
+}
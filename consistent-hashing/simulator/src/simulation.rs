@@ -0,0 +1,125 @@
+use std::hash::Hash;
+
+use anchorhash::AnchorHash;
+use ringhash::ConsistentHasher;
+
+/// How evenly an algorithm spread the keys, and how
+/// many of them moved when a node joined or left
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub stddev: f64,
+    pub max_mean: f64,
+    pub moved_join: f64,
+    pub moved_leave: f64,
+}
+
+impl Stats {
+    pub const CSV_HEADER: &'static str =
+        "algorithm,nodes,vnodes,keys,load_stddev,max_mean_ratio,moved_join_pct,moved_leave_pct";
+
+    pub fn to_csv(&self, algorithm: &str, nodes: u32, vnodes: Option<u32>, keys: u64) -> String {
+        let vnodes = vnodes.map(|v| v.to_string()).unwrap_or_default();
+        format!(
+            "{},{},{},{},{:.3},{:.4},{:.3},{:.3}",
+            algorithm,
+            nodes,
+            vnodes,
+            keys,
+            self.stddev,
+            self.max_mean,
+            self.moved_join * 100.0,
+            self.moved_leave * 100.0
+        )
+    }
+}
+
+fn owners<H: ConsistentHasher<u32>>(placement: &H, keys: u64) -> Vec<u32> {
+    (0..keys).map(|k| *placement.get(&k).unwrap()).collect()
+}
+
+fn moved(before: &[u32], after: &[u32]) -> f64 {
+    let n = before.iter().zip(after).filter(|(b, a)| b != a).count();
+    n as f64 / before.len() as f64
+}
+
+/// Places `keys` synthetic keys on nodes `0..nodes`, then measures how
+/// many move when node `nodes` joins and when node `nodes / 2` leaves.
+/// There have to be at least 2 nodes, so one's left to take its keys.
+pub fn simulate<H: ConsistentHasher<u32>>(mut placement: H, nodes: u32, keys: u64) -> Stats {
+    for node in 0..nodes {
+        placement.add(node);
+    }
+    let before = owners(&placement, keys);
+
+    let mut loads = vec![0u64; nodes as usize];
+    for owner in before.iter() {
+        loads[*owner as usize] += 1;
+    }
+    let mean = keys as f64 / nodes as f64;
+    let variance = loads
+        .iter()
+        .map(|l| (*l as f64 - mean).powi(2))
+        .sum::<f64>()
+        / nodes as f64;
+    let max = *loads.iter().max().unwrap_or(&0);
+
+    placement.add(nodes);
+    let moved_join = moved(&before, &owners(&placement, keys));
+    placement.remove(&nodes);
+
+    placement.remove(&(nodes / 2));
+    let moved_leave = moved(&before, &owners(&placement, keys));
+
+    Stats {
+        stddev: variance.sqrt(),
+        max_mean: max as f64 / mean,
+        moved_join,
+        moved_leave,
+    }
+}
+
+/// AnchorHash hands out bucket numbers rather than nodes, so here
+/// node `i` is simply bucket `i`. AnchorHash always needs one working
+/// bucket, so node 0 is on from the start.
+pub struct AnchorNodes {
+    anchor: AnchorHash,
+    buckets: Vec<u32>,
+}
+
+impl AnchorNodes {
+    pub fn new(capacity: u32) -> AnchorNodes {
+        AnchorNodes {
+            anchor: AnchorHash::new(capacity, 1),
+            buckets: (0..capacity).collect(),
+        }
+    }
+}
+
+impl ConsistentHasher<u32> for AnchorNodes {
+    // buckets come back last in first out, so nodes must be
+    // added in order and re-added in the reverse of their removal
+    fn add(&mut self, node: u32) {
+        if self.anchor.is_working(node) {
+            return;
+        }
+        // a different bucket would quietly skew every number after it
+        let added = self.anchor.add_bucket();
+        assert_eq!(added, Some(node), "AnchorHash added the wrong bucket");
+    }
+
+    fn remove(&mut self, node: &u32) -> Option<u32> {
+        if self.anchor.remove_bucket(*node) {
+            Some(*node)
+        } else {
+            None
+        }
+    }
+
+    fn get<K: Hash>(&self, k: &K) -> Option<&u32> {
+        self.buckets.get(self.anchor.get_bucket(k) as usize)
+    }
+
+    fn len(&self) -> usize {
+        self.anchor.len()
+    }
+}
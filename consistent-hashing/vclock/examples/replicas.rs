@@ -0,0 +1,71 @@
+use ringhash::HashRing;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use vclock::{Siblings, VectorClock};
+
+// each node's copy of the versions of every key it replicates
+type Replicas = HashMap<SocketAddr, Siblings<SocketAddr, Vec<u8>>>;
+
+fn show(replicas: &Replicas, nodes: &[SocketAddr]) {
+    for node in nodes {
+        let values: Vec<String> = replicas[node]
+            .values()
+            .map(|v| String::from_utf8_lossy(v).into_owned())
+            .collect();
+        println!("  {} holds {:?} ({} bytes encoded)", node, values, replicas[node].to_bytes().len());
+    }
+}
+
+fn main() {
+
+    let mut ring = HashRing::with_replicas(100);
+    for port in 4000..4005 {
+        ring.add(SocketAddr::from(([127, 0, 0, 1], port)));
+    }
+
+    // the key's preference list, as Dynamo would use it
+    let key = "cart:42";
+    let nodes: Vec<SocketAddr> = ring.get_n(&key, 3).into_iter().copied().collect();
+    println!("{} is replicated on {:?}", key, nodes);
+
+    let mut replicas: Replicas = nodes.iter().map(|n| (*n, Siblings::new())).collect();
+
+    // a write coordinated by the first replica reaches all of them
+    let (first, second) = (nodes[0], nodes[1]);
+    let mut copy = replicas[&first].clone();
+    copy.update(first, &VectorClock::new(), b"apples".to_vec());
+    for node in nodes.iter() {
+        replicas.get_mut(node).unwrap().merge(copy.clone());
+    }
+    println!("after the first write");
+    show(&replicas, &nodes);
+
+    // two clients read it, then write through different
+    // coordinators while those two can't reach each other
+    let context = replicas[&first].context();
+    replicas.get_mut(&first).unwrap().update(first, &context, b"apples, pears".to_vec());
+    replicas.get_mut(&second).unwrap().update(second, &context, b"apples, plums".to_vec());
+    println!("after concurrent writes on {} and {}", first, second);
+    show(&replicas, &nodes);
+
+    // anti-entropy spreads both versions, which are kept as siblings
+    let mut all = Siblings::new();
+    for node in nodes.iter() {
+        all.merge(replicas[node].clone());
+    }
+    for node in nodes.iter() {
+        replicas.get_mut(node).unwrap().merge(all.clone());
+    }
+    println!("after the replicas sync");
+    show(&replicas, &nodes);
+
+    // a client reads both and writes back the reconciled value
+    let context = replicas[&second].context();
+    replicas.get_mut(&second).unwrap().update(second, &context, b"apples, pears, plums".to_vec());
+    let resolved = replicas[&second].clone();
+    for node in nodes.iter() {
+        replicas.get_mut(node).unwrap().merge(resolved.clone());
+    }
+    println!("after the siblings are resolved");
+    show(&replicas, &nodes);
+}
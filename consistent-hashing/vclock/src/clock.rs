@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How two versions are related
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Causality {
+    Equal,
+    /// The first happened before, and was seen by, the second
+    Before,
+    After,
+    /// Neither saw the other, so both have to be kept
+    Concurrent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub(crate) counter: u64,
+    // when the actor last updated it, in seconds since the
+    // epoch, so that the oldest entries can be pruned
+    pub(crate) timestamp: u64,
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A counter per actor (usually the node coordinating a write), as in
+/// Section 4.4 of DeCandia _et al_. One clock descends another if every
+/// one of its counters is at least as large.
+#[derive(Debug, Clone)]
+pub struct VectorClock<A: Ord> {
+    pub(crate) entries: BTreeMap<A, Entry>,
+}
+
+impl<A: Ord> Default for VectorClock<A> {
+    fn default() -> Self {
+        VectorClock {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+// timestamps are only bookkeeping for pruning,
+// so they don't make two clocks different
+impl<A: Ord> PartialEq for VectorClock<A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries.len() == other.entries.len()
+            && self
+                .entries
+                .iter()
+                .zip(other.entries.iter())
+                .all(|((a, x), (b, y))| a == b && x.counter == y.counter)
+    }
+}
+
+impl<A: Ord> Eq for VectorClock<A> {}
+
+impl<A: Ord + Clone> VectorClock<A> {
+    pub fn new() -> VectorClock<A> {
+        VectorClock::default()
+    }
+
+    /// The number of actors with an entry
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// `actor`'s counter, 0 if it has none
+    pub fn get(&self, actor: &A) -> u64 {
+        self.entries.get(actor).map_or(0, |e| e.counter)
+    }
+
+    /// Each actor with its counter, ordered by actor
+    pub fn iter(&self) -> impl Iterator<Item = (&A, u64)> {
+        self.entries.iter().map(|(a, e)| (a, e.counter))
+    }
+
+    /// Records an update by `actor`, returning its new counter
+    pub fn increment(&mut self, actor: A) -> u64 {
+        self.increment_at(actor, now())
+    }
+
+    pub fn increment_at(&mut self, actor: A, timestamp: u64) -> u64 {
+        let entry = self.entries.entry(actor).or_insert(Entry {
+            counter: 0,
+            timestamp,
+        });
+        entry.counter += 1;
+        entry.timestamp = timestamp;
+        entry.counter
+    }
+
+    // raises `actor`'s counter to at least `counter`
+    pub(crate) fn witness(&mut self, actor: A, counter: u64, timestamp: u64) {
+        let entry = self.entries.entry(actor).or_insert(Entry {
+            counter: 0,
+            timestamp,
+        });
+        if counter > entry.counter {
+            entry.counter = counter;
+        }
+        entry.timestamp = entry.timestamp.max(timestamp);
+    }
+
+    /// Whether this clock has seen everything `other` has
+    pub fn descends(&self, other: &VectorClock<A>) -> bool {
+        other.iter().all(|(actor, counter)| self.get(actor) >= counter)
+    }
+
+    pub fn compare(&self, other: &VectorClock<A>) -> Causality {
+        match (self.descends(other), other.descends(self)) {
+            (true, true) => Causality::Equal,
+            (true, false) => Causality::After,
+            (false, true) => Causality::Before,
+            (false, false) => Causality::Concurrent,
+        }
+    }
+
+    /// The least clock descending both, i.e. the largest of each counter
+    pub fn merge(&mut self, other: &VectorClock<A>) {
+        for (actor, entry) in other.entries.iter() {
+            self.witness(actor.clone(), entry.counter, entry.timestamp);
+        }
+    }
+
+    /// Drops the least recently updated entries until at most
+    /// `max_entries` are left, returning how many were dropped.
+    ///
+    /// As with Dynamo's clock truncation, this keeps clocks small when
+    /// many coordinators have written a key, at the risk of versions
+    /// that descend each other later looking concurrent.
+    pub fn prune(&mut self, max_entries: usize) -> usize {
+        let excess = self.entries.len().saturating_sub(max_entries);
+        if excess == 0 {
+            return 0;
+        }
+        let mut oldest: Vec<(u64, A)> = self
+            .entries
+            .iter()
+            .map(|(actor, e)| (e.timestamp, actor.clone()))
+            .collect();
+        oldest.sort_by_key(|(timestamp, _)| *timestamp);
+        for (_, actor) in oldest.into_iter().take(excess) {
+            self.entries.remove(&actor);
+        }
+        excess
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Causality, VectorClock};
+
+    fn clock(entries: &[(&'static str, u64)]) -> VectorClock<&'static str> {
+        let mut clock = VectorClock::new();
+        for (actor, counter) in entries {
+            for _ in 0..*counter {
+                clock.increment_at(*actor, 0);
+            }
+        }
+        clock
+    }
+
+    #[test]
+    fn compares_causality() {
+        let a = clock(&[("sx", 1)]);
+        let b = clock(&[("sx", 2)]);
+        let c = clock(&[("sx", 2), ("sy", 1)]);
+        let d = clock(&[("sx", 2), ("sz", 1)]);
+
+        assert_eq!(a.compare(&a), Causality::Equal);
+        assert_eq!(a.compare(&b), Causality::Before);
+        assert_eq!(c.compare(&b), Causality::After);
+        assert_eq!(c.compare(&d), Causality::Concurrent);
+        assert_eq!(VectorClock::new().compare(&a), Causality::Before);
+    }
+
+    #[test]
+    fn merge_descends_both() {
+        // Dynamo's example: D3 and D4 reconciled by Sx
+        let mut d5 = clock(&[("sx", 2), ("sy", 1)]);
+        let d4 = clock(&[("sx", 2), ("sz", 1)]);
+        d5.merge(&d4);
+        d5.increment_at("sx", 0);
+
+        assert_eq!(d5, clock(&[("sx", 3), ("sy", 1), ("sz", 1)]));
+        assert!(d5.descends(&d4));
+        assert_eq!(d5.iter().collect::<Vec<_>>(), vec![(&"sx", 3), (&"sy", 1), (&"sz", 1)]);
+    }
+
+    #[test]
+    fn prunes_least_recently_updated() {
+        let mut c = VectorClock::new();
+        c.increment_at("a", 30);
+        c.increment_at("b", 10);
+        c.increment_at("c", 20);
+        c.increment_at("b", 40);
+
+        assert_eq!(c.prune(3), 0);
+        assert_eq!(c.prune(2), 1);
+        assert_eq!(c.get(&"c"), 0);
+        assert_eq!(c.get(&"b"), 2);
+
+        // timestamps don't affect equality
+        let mut d = VectorClock::new();
+        d.increment_at("a", 0);
+        d.increment_at("b", 0);
+        d.increment_at("b", 0);
+        assert_eq!(c, d);
+    }
+
+}
@@ -0,0 +1,240 @@
+//! Dotted version vectors, following Preguiça _et al_, "Dotted Version
+//! Vectors: Logical Clocks for Optimistic Replication".
+//!
+//! With plain vector clocks, a coordinator writing on behalf of two
+//! clients that both read the same version gives the two writes clocks
+//! that look like one descends the other, and a write is lost. A dotted
+//! version vector separates the single event that created a value (its
+//! _dot_) from the causal past the client had seen (its _context_), so
+//! such writes are correctly kept as concurrent siblings.
+
+use crate::clock::{now, Causality, VectorClock};
+
+/// One update: the `counter`th event of `actor`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Dot<A> {
+    pub actor: A,
+    pub counter: u64,
+}
+
+impl<A: Ord + Clone> VectorClock<A> {
+    /// Whether this clock has seen the update `dot`
+    pub fn contains(&self, dot: &Dot<A>) -> bool {
+        self.get(&dot.actor) >= dot.counter
+    }
+}
+
+/// The version of a single value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DottedVersionVector<A: Ord> {
+    pub dot: Dot<A>,
+    /// What the writer had seen when it wrote the value
+    pub context: VectorClock<A>,
+    // when the dot was created, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+impl<A: Ord + Clone> DottedVersionVector<A> {
+    /// Whether this version has seen the update `dot`
+    pub fn covers(&self, dot: &Dot<A>) -> bool {
+        self.dot == *dot || self.context.contains(dot)
+    }
+
+    pub fn compare(&self, other: &DottedVersionVector<A>) -> Causality {
+        if self.dot == other.dot {
+            Causality::Equal
+        } else if self.covers(&other.dot) {
+            Causality::After
+        } else if other.covers(&self.dot) {
+            Causality::Before
+        } else {
+            Causality::Concurrent
+        }
+    }
+
+    /// The version as a plain vector clock: its context plus its dot
+    pub fn clock(&self) -> VectorClock<A> {
+        let mut clock = self.context.clone();
+        clock.witness(self.dot.actor.clone(), self.dot.counter, self.timestamp);
+        clock
+    }
+}
+
+/// Every concurrent version of a key that a replica holds. Reads hand
+/// the client all the values plus the `context` covering them, and a
+/// write carrying that context replaces exactly the values it was
+/// based on, leaving anything written concurrently as a sibling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Siblings<A: Ord, T> {
+    pub(crate) versions: Vec<(DottedVersionVector<A>, T)>,
+}
+
+impl<A: Ord, T> Default for Siblings<A, T> {
+    fn default() -> Self {
+        Siblings { versions: vec![] }
+    }
+}
+
+impl<A: Ord + Clone, T> Siblings<A, T> {
+    pub fn new() -> Siblings<A, T> {
+        Siblings::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.versions.iter().map(|(_, v)| v)
+    }
+
+    pub fn versions(&self) -> &[(DottedVersionVector<A>, T)] {
+        &self.versions
+    }
+
+    /// Everything these siblings have seen, to be passed back
+    /// with the next write to supersede them
+    pub fn context(&self) -> VectorClock<A> {
+        let mut context = VectorClock::new();
+        for (version, _) in self.versions.iter() {
+            context.merge(&version.clock());
+        }
+        context
+    }
+
+    /// Stores `value`, written by a client that had seen `context`,
+    /// with `actor` (the coordinating replica) creating the new dot
+    pub fn update(&mut self, actor: A, context: &VectorClock<A>, value: T) -> Dot<A> {
+        self.update_at(actor, context, value, now())
+    }
+
+    pub fn update_at(&mut self, actor: A, context: &VectorClock<A>, value: T, timestamp: u64) -> Dot<A> {
+        // the actor's counter has to move past anything it's
+        // done before, whether or not the client saw it
+        let counter = self.context().get(&actor).max(context.get(&actor)) + 1;
+        let dot = Dot { actor, counter };
+
+        self.versions.retain(|(version, _)| !context.contains(&version.dot));
+        self.versions.push((
+            DottedVersionVector {
+                dot: dot.clone(),
+                context: context.clone(),
+                timestamp,
+            },
+            value,
+        ));
+        dot
+    }
+
+    /// Combines the versions held by two replicas, dropping
+    /// any that a version on either side has superseded
+    pub fn merge(&mut self, other: Siblings<A, T>) {
+        for (version, value) in other.versions {
+            if !self.versions.iter().any(|(v, _)| v.covers(&version.dot)) {
+                self.versions.push((version, value));
+            }
+        }
+        let all: Vec<DottedVersionVector<A>> = self.versions.iter().map(|(v, _)| v.clone()).collect();
+        self.versions.retain(|(version, _)| {
+            !all
+                .iter()
+                .any(|other| other.dot != version.dot && other.covers(&version.dot))
+        });
+    }
+
+    /// Prunes every sibling's context, as `VectorClock::prune`
+    pub fn prune(&mut self, max_entries: usize) {
+        for (version, _) in self.versions.iter_mut() {
+            version.context.prune(max_entries);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Dot, Siblings};
+    use crate::clock::{Causality, VectorClock};
+
+    fn values(s: &Siblings<&'static str, &'static str>) -> Vec<&'static str> {
+        let mut values: Vec<&str> = s.values().copied().collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn blind_writes_are_siblings() {
+        let mut s = Siblings::new();
+        let none = VectorClock::new();
+        s.update_at("a", &none, "v1", 0);
+        s.update_at("a", &none, "v2", 0);
+        assert_eq!(values(&s), vec!["v1", "v2"]);
+
+        let (x, y) = (&s.versions()[0].0, &s.versions()[1].0);
+        assert_eq!(x.compare(y), Causality::Concurrent);
+    }
+
+    #[test]
+    fn write_with_context_replaces_what_it_read() {
+        let mut s = Siblings::new();
+        s.update_at("a", &VectorClock::new(), "v1", 0);
+        let read = s.context();
+
+        // two clients both read v1, and write through the same
+        // coordinator; vector clocks alone would lose one write
+        s.update_at("a", &read, "v2", 0);
+        let dot = s.update_at("a", &read, "v3", 0);
+        assert_eq!(values(&s), vec!["v2", "v3"]);
+        assert_eq!(dot, Dot { actor: "a", counter: 3 });
+
+        // a client that read both resolves them
+        let both = s.context();
+        s.update_at("b", &both, "v4", 0);
+        assert_eq!(values(&s), vec!["v4"]);
+        assert_eq!(s.context().get(&"a"), 3);
+        assert_eq!(s.context().get(&"b"), 1);
+    }
+
+    #[test]
+    fn replicas_merge_to_the_same_siblings() {
+        let mut base = Siblings::new();
+        base.update_at("a", &VectorClock::new(), "v1", 0);
+        let read = base.context();
+
+        // concurrent writes on two replicas
+        let mut r1 = base.clone();
+        r1.update_at("a", &read, "from-a", 0);
+        let mut r2 = base.clone();
+        r2.update_at("b", &read, "from-b", 0);
+
+        let mut m1 = r1.clone();
+        m1.merge(r2.clone());
+        let mut m2 = r2.clone();
+        m2.merge(r1.clone());
+        assert_eq!(values(&m1), vec!["from-a", "from-b"]);
+        assert_eq!(values(&m2), values(&m1));
+
+        // merging is idempotent, and what's superseded stays gone
+        m1.merge(m2.clone());
+        m1.merge(base);
+        assert_eq!(values(&m1), vec!["from-a", "from-b"]);
+    }
+
+    #[test]
+    fn dvv_clock_includes_its_dot() {
+        let mut s = Siblings::new();
+        let mut context = VectorClock::new();
+        context.increment_at("b", 0);
+        s.update_at("a", &context, "v", 0);
+
+        let version = &s.versions()[0].0;
+        assert!(version.covers(&Dot { actor: "b", counter: 1 }));
+        assert!(!version.covers(&Dot { actor: "a", counter: 2 }));
+        assert_eq!(version.clock().iter().count(), 2);
+    }
+
+}
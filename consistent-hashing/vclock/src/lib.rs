@@ -0,0 +1,15 @@
+//! Versions for values replicated by `ringhash`, following Section 4.4
+//! of DeCandia _et al_, "Dynamo: Amazon's Highly Available Key-value
+//! Store". Writes coordinated by different nodes are tracked with a
+//! counter per node, so replicas can tell a stale copy of a value from
+//! one written concurrently, which has to be kept as a sibling until a
+//! client resolves it. Dotted version vectors, from Preguiça _et al_,
+//! keep writes coordinated by the same node apart as well.
+
+pub mod clock;
+pub mod dvv;
+pub mod wire;
+
+pub use clock::{Causality, VectorClock};
+pub use dvv::{Dot, DottedVersionVector, Siblings};
+pub use wire::{Actor, DecodeError};
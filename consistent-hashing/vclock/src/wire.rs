@@ -0,0 +1,349 @@
+//! A compact binary encoding, so versions can be stored and shipped
+//! alongside every value without costing much more than the value.
+//!
+//! Counters, timestamps and lengths are LEB128 varints, so a clock with
+//! a few small counters takes a few bytes per actor plus the actors
+//! themselves. A clock is its number of entries followed by each
+//! `(actor, counter, timestamp)` in actor order; a dotted version vector
+//! is its dot, its timestamp and its context; siblings are their number
+//! followed by each version and its length prefixed value.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::clock::{Entry, VectorClock};
+use crate::dvv::{Dot, DottedVersionVector, Siblings};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DecodeError;
+
+impl std::error::Error for DecodeError {}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        "invalid version encoding".fmt(fmt)
+    }
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn get_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        let bits = (byte & 0x7f) as u64;
+        // the 10th byte can only hold the top bit
+        if shift == 63 && bits > 1 {
+            return None;
+        }
+        n |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+fn get_bytes<'a>(buf: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if buf.len() < len {
+        return None;
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(bytes)
+}
+
+fn get_len(buf: &mut &[u8]) -> Option<usize> {
+    let len = get_varint(buf)?;
+    // every entry takes at least a byte, so longer
+    // lengths can only come from a corrupt buffer
+    if len > buf.len() as u64 {
+        return None;
+    }
+    Some(len as usize)
+}
+
+/// Something that can update a clock, and be written in one
+pub trait Actor: Ord + Clone {
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Reads an actor off the front of `buf`, advancing it
+    fn decode(buf: &mut &[u8]) -> Option<Self>;
+}
+
+impl Actor for u32 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_varint(buf, *self as u64);
+    }
+
+    fn decode(buf: &mut &[u8]) -> Option<Self> {
+        get_varint(buf).and_then(|n| u32::try_from(n).ok())
+    }
+}
+
+impl Actor for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_varint(buf, *self);
+    }
+
+    fn decode(buf: &mut &[u8]) -> Option<Self> {
+        get_varint(buf)
+    }
+}
+
+impl Actor for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_varint(buf, self.len() as u64);
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(buf: &mut &[u8]) -> Option<Self> {
+        let len = get_len(buf)?;
+        let bytes = get_bytes(buf, len)?;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl Actor for SocketAddr {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self.ip() {
+            IpAddr::V4(ip) => {
+                buf.push(4);
+                buf.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                buf.push(6);
+                buf.extend_from_slice(&ip.octets());
+            }
+        }
+        buf.extend_from_slice(&self.port().to_be_bytes());
+    }
+
+    fn decode(buf: &mut &[u8]) -> Option<Self> {
+        let ip = match get_bytes(buf, 1)?[0] {
+            4 => {
+                let mut octets = [0; 4];
+                octets.copy_from_slice(get_bytes(buf, 4)?);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            6 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(get_bytes(buf, 16)?);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return None,
+        };
+        let port = get_bytes(buf, 2)?;
+        Some(SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])))
+    }
+}
+
+fn encode_clock<A: Actor>(clock: &VectorClock<A>, buf: &mut Vec<u8>) {
+    put_varint(buf, clock.entries.len() as u64);
+    for (actor, entry) in clock.entries.iter() {
+        actor.encode(buf);
+        put_varint(buf, entry.counter);
+        put_varint(buf, entry.timestamp);
+    }
+}
+
+fn decode_clock<A: Actor>(buf: &mut &[u8]) -> Option<VectorClock<A>> {
+    let mut clock = VectorClock::new();
+    let mut last: Option<A> = None;
+    for _ in 0..get_len(buf)? {
+        let actor = A::decode(buf)?;
+        let counter = get_varint(buf)?;
+        let timestamp = get_varint(buf)?;
+        // actors are written in order, so anything else
+        // (including repeats) isn't a clock we wrote
+        if counter == 0 || last.as_ref().is_some_and(|l| *l >= actor) {
+            return None;
+        }
+        last = Some(actor.clone());
+        clock.entries.insert(actor, Entry { counter, timestamp });
+    }
+    Some(clock)
+}
+
+fn encode_dvv<A: Actor>(dvv: &DottedVersionVector<A>, buf: &mut Vec<u8>) {
+    dvv.dot.actor.encode(buf);
+    put_varint(buf, dvv.dot.counter);
+    put_varint(buf, dvv.timestamp);
+    encode_clock(&dvv.context, buf);
+}
+
+fn decode_dvv<A: Actor>(buf: &mut &[u8]) -> Option<DottedVersionVector<A>> {
+    let actor = A::decode(buf)?;
+    let counter = get_varint(buf)?;
+    let timestamp = get_varint(buf)?;
+    let context = decode_clock(buf)?;
+    Some(DottedVersionVector {
+        dot: Dot { actor, counter },
+        context,
+        timestamp,
+    })
+}
+
+// decodes all of `bytes`, which mustn't have anything left over
+fn decode_all<T, F>(mut bytes: &[u8], decode: F) -> Result<T, DecodeError>
+where
+    F: FnOnce(&mut &[u8]) -> Option<T>,
+{
+    match decode(&mut bytes) {
+        Some(t) if bytes.is_empty() => Ok(t),
+        _ => Err(DecodeError),
+    }
+}
+
+impl<A: Actor> VectorClock<A> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        encode_clock(self, &mut buf);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<VectorClock<A>, DecodeError> {
+        decode_all(bytes, decode_clock)
+    }
+}
+
+impl<A: Actor> DottedVersionVector<A> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        encode_dvv(self, &mut buf);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<DottedVersionVector<A>, DecodeError> {
+        decode_all(bytes, decode_dvv)
+    }
+}
+
+impl<A: Actor> Siblings<A, Vec<u8>> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        put_varint(&mut buf, self.versions.len() as u64);
+        for (version, value) in self.versions.iter() {
+            encode_dvv(version, &mut buf);
+            put_varint(&mut buf, value.len() as u64);
+            buf.extend_from_slice(value);
+        }
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Siblings<A, Vec<u8>>, DecodeError> {
+        decode_all(bytes, |buf| {
+            let mut versions = vec![];
+            for _ in 0..get_len(buf)? {
+                let version = decode_dvv(buf)?;
+                let len = get_len(buf)?;
+                versions.push((version, get_bytes(buf, len)?.to_vec()));
+            }
+            Some(Siblings { versions })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::SocketAddr;
+
+    use super::{get_varint, put_varint, DecodeError};
+    use crate::clock::VectorClock;
+    use crate::dvv::{DottedVersionVector, Siblings};
+
+    #[test]
+    fn varints_round_trip() {
+        for n in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = vec![];
+            put_varint(&mut buf, n);
+            let mut bytes = buf.as_slice();
+            assert_eq!(get_varint(&mut bytes), Some(n));
+            assert!(bytes.is_empty());
+        }
+        let mut small = vec![];
+        put_varint(&mut small, 127);
+        assert_eq!(small.len(), 1);
+
+        // too long, and truncated
+        assert_eq!(get_varint(&mut [0xff; 11].as_slice()), None);
+        assert_eq!(get_varint(&mut [0x80].as_slice()), None);
+    }
+
+    #[test]
+    fn clocks_round_trip() {
+        let a: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let b: SocketAddr = "[::1]:4001".parse().unwrap();
+        let mut clock = VectorClock::new();
+        clock.increment_at(a, 1_700_000_000);
+        clock.increment_at(b, 1_700_000_001);
+        clock.increment_at(a, 1_700_000_002);
+
+        let decoded = VectorClock::<SocketAddr>::from_bytes(&clock.to_bytes()).unwrap();
+        assert_eq!(decoded, clock);
+        // timestamps survive too, since pruning needs them
+        assert_eq!(decoded.entries, clock.entries);
+
+        let mut named = VectorClock::new();
+        named.increment_at("node-a".to_string(), 0);
+        assert_eq!(VectorClock::from_bytes(&named.to_bytes()), Ok(named));
+    }
+
+    #[test]
+    fn siblings_round_trip() {
+        let mut s = Siblings::new();
+        let none = VectorClock::new();
+        s.update_at(1u32, &none, b"one".to_vec(), 10);
+        s.update_at(2u32, &none, b"two".to_vec(), 20);
+        let context = s.context();
+        s.update_at(1u32, &context, b"three".to_vec(), 30);
+        s.update_at(2u32, &none, vec![], 40);
+
+        let decoded = Siblings::<u32, Vec<u8>>::from_bytes(&s.to_bytes()).unwrap();
+        assert_eq!(decoded, s);
+
+        let (version, _) = &s.versions()[0];
+        assert_eq!(DottedVersionVector::from_bytes(&version.to_bytes()).as_ref(), Ok(version));
+    }
+
+    #[test]
+    fn encoding_is_compact() {
+        // three coordinators with small counters and recent timestamps
+        let mut clock = VectorClock::new();
+        for actor in 0..3u32 {
+            for _ in 0..5 {
+                clock.increment_at(actor, 1_700_000_000);
+            }
+        }
+        // a count, then an actor, counter and 5 byte timestamp each
+        assert_eq!(clock.to_bytes().len(), 1 + 3 * (1 + 1 + 5));
+    }
+
+    #[test]
+    fn rejects_bad_encodings() {
+        let mut clock = VectorClock::new();
+        clock.increment_at(7u32, 0);
+        clock.increment_at(9u32, 0);
+        let bytes = clock.to_bytes();
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert_eq!(VectorClock::<u32>::from_bytes(truncated), Err(DecodeError));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(VectorClock::<u32>::from_bytes(&trailing), Err(DecodeError));
+
+        // actors out of order, and a huge length
+        assert_eq!(VectorClock::<u32>::from_bytes(&[2, 9, 1, 0, 7, 1, 0]), Err(DecodeError));
+        assert_eq!(Siblings::<u32, Vec<u8>>::from_bytes(&[0xff, 0xff, 0x7f]), Err(DecodeError));
+    }
+
+}
@@ -1,11 +1,27 @@
 use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
 use tokio::sync::broadcast::{self, Receiver};
+use tokio::sync::mpsc;
+use tokio::task;
+use uuid::Uuid;
+
+use crate::log::{self, Log, LogConfig, Record};
+use crate::error::MessageStoreError;
+use crate::group::Groups;
+use crate::protocol::{self, Assignment, Message, Start};
+use crate::topic::{Topic, INBOX_PREFIX};
+use crate::trie::SubjectTrie;
 
-use crate::{error::MessageStoreError, protocol::Message, topic::Topic};
+pub const CHAN_CAPACITY: usize = 1024;
 
-const CHAN_CAPACITY: usize = 1024;
+// records read from a log at a time when replaying it
+const REPLAY_BATCH: usize = 256;
 
 #[derive(Debug)]
 pub struct MessageStoreDropGuard {
@@ -17,16 +33,161 @@ pub struct MessageStore {
     state: Arc<Mutex<State>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct State {
-    topics: HashMap<Topic, broadcast::Sender<Message>>,
+    topics: HashMap<Topic, Channel>,
+    // where durable topics keep their logs, if they're enabled
+    data_dir: Option<PathBuf>,
+    log_config: LogConfig,
+    // a channel for each wildcard subscribed to, which every
+    // topic matching it also sends its messages to
+    wildcards: SubjectTrie<broadcast::Sender<Message>>,
+}
+
+#[derive(Debug)]
+struct Channel {
+    tx: broadcast::Sender<Message>,
+    // every message published, for durable topics
+    log: Option<Arc<Mutex<Log>>>,
+    // the next offset, for topics without a log to count them
+    next: u64,
+    groups: Arc<Mutex<Groups>>,
+}
+
+impl Channel {
+    fn new(log: Option<Log>) -> Self {
+        let (tx, _) = broadcast::channel(CHAN_CAPACITY);
+        Channel {
+            tx,
+            log: log.map(|l| Arc::new(Mutex::new(l))),
+            next: 0,
+            groups: Arc::new(Mutex::new(Groups::default())),
+        }
+    }
+
+    // to every subscriber, including those to matching
+    // wildcards, and one member of each group
+    fn send(
+        tx: &broadcast::Sender<Message>,
+        groups: &Mutex<Groups>,
+        wildcards: &[broadcast::Sender<Message>],
+        msg: Message,
+    ) {
+        groups.lock().unwrap_or_else(|e| e.into_inner()).deliver(&msg);
+        // no subscribers isn't an error
+        for wildcard in wildcards {
+            let _ = wildcard.send(msg.clone());
+        }
+        let _ = tx.send(msg);
+    }
+}
+
+// how a message is kept in a durable topic's log, i.e.
+// `id | #header-bytes | headers | payload`, the log
+// keeping its offset and timestamp itself
+fn to_record(msg: &Message) -> Bytes {
+    let mut headers = BytesMut::new();
+    protocol::write_headers(&msg.headers, &mut headers);
+
+    let mut buf = BytesMut::with_capacity(20 + headers.len() + msg.bytes.len());
+    buf.put_slice(msg.id.as_bytes());
+    buf.put_u32(headers.len() as u32);
+    buf.put_slice(&headers);
+    buf.put_slice(&msg.bytes);
+    buf.freeze()
+}
+
+fn from_record(record: Record) -> io::Result<Message> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed message in log");
+    let mut buf = record.payload;
+    if buf.len() < 20 {
+        return Err(invalid());
+    }
+    let id = Uuid::from_slice(&buf.split_to(16)).map_err(|_| invalid())?;
+    let header_len = buf.get_u32() as usize;
+    if header_len > buf.len() {
+        return Err(invalid());
+    }
+    let headers = protocol::parse_headers(&buf.split_to(header_len)).map_err(|_| invalid())?;
+    Ok(Message {
+        id,
+        timestamp: record.timestamp,
+        offset: record.offset,
+        headers,
+        ..Message::new(buf)
+    })
+}
+
+/// A subscription to a durable topic that starts in its log. Batches
+/// are read until it catches up with where live messages start, and
+/// again whenever the live receiver lags and misses some.
+pub struct Replay {
+    log: Arc<Mutex<Log>>,
+    next: u64,
+    // the first offset the live receiver gets
+    end: u64,
+    // a live message that came after a gap, held back until it's filled
+    held: Option<Message>,
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self {
-            topics: HashMap::default(),
+impl Replay {
+    /// The next messages to deliver, empty once the replay is done.
+    /// They're read off the async workers, and without holding the
+    /// log, so publishers to the topic aren't held up meanwhile.
+    pub async fn next_batch(&mut self) -> io::Result<Vec<Message>> {
+        if self.next >= self.end {
+            return Ok(self.take_held().into_iter().collect());
+        }
+        let max = REPLAY_BATCH.min((self.end - self.next) as usize);
+        let snapshot = self.log.lock().unwrap_or_else(|e| e.into_inner()).snapshot(self.next);
+        let records = task::spawn_blocking(move || snapshot.read(max))
+            .await
+            .map_err(io::Error::other)??;
+        self.next = records.last().map_or(self.end, |r| r.offset + 1);
+        records.into_iter().map(from_record).collect()
+    }
+
+    /// Takes a message from the live receiver once the replay's done,
+    /// returning it if it's the next one to deliver. One that's already
+    /// been replayed is dropped, and one after a gap, i.e. the receiver
+    /// lagged, is held back while `next_batch` reads the gap from the log.
+    pub fn live(&mut self, msg: Message) -> Option<Message> {
+        if msg.offset < self.next {
+            return None;
+        }
+        self.end = msg.offset;
+        self.held = Some(msg);
+        if self.next < self.end {
+            return None;
         }
+        self.take_held()
+    }
+
+    fn take_held(&mut self) -> Option<Message> {
+        let msg = self.held.take()?;
+        self.next = msg.offset + 1;
+        self.end = self.next;
+        Some(msg)
+    }
+}
+
+// topics are published to, so can't be wildcards
+fn valid_topic(topic: &Topic) -> bool {
+    topic.is_valid() && !topic.is_wildcard()
+}
+
+// topics and consumers are stored in files named after
+// them, so their names have to be usable as one
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+// each durable topic's log is a directory named after it
+fn topic_dir(data_dir: &Path, topic: &Topic) -> Option<PathBuf> {
+    if valid_name(&topic.0) {
+        Some(data_dir.join(&topic.0))
+    } else {
+        None
     }
 }
 
@@ -37,6 +198,36 @@ impl MessageStoreDropGuard {
         }
     }
 
+    /// A store whose durable topics keep their logs under `data_dir`.
+    /// Any durable topics already there are reopened.
+    pub fn open(data_dir: impl Into<PathBuf>, log_config: LogConfig) -> crate::Result<Self> {
+        let data_dir = data_dir.into();
+        fs::create_dir_all(&data_dir)?;
+
+        let mut state = State {
+            topics: HashMap::new(),
+            data_dir: Some(data_dir.clone()),
+            log_config,
+            wildcards: SubjectTrie::default(),
+        };
+        for entry in fs::read_dir(&data_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                let log = Log::open(entry.path(), log_config)?;
+                state.topics.insert(Topic::new(name), Channel::new(Some(log)));
+            }
+        }
+
+        Ok(MessageStoreDropGuard {
+            store: MessageStore {
+                state: Arc::new(Mutex::new(state)),
+            },
+        })
+    }
+
     pub fn store(&self) -> MessageStore {
         // return a clone of the Arc around the state
         // i.e. increment the Ref Count
@@ -45,54 +236,681 @@ impl MessageStoreDropGuard {
 }
 
 impl MessageStore {
+    /// Adds a topic whose messages are only delivered to whoever's
+    /// subscribed. Making a topic that already exists keeps it as it
+    /// is, along with its subscribers and, if it's durable, its log.
     pub fn add_topic(&self, name: impl ToString) -> crate::Result<Topic> {
         let topic = Topic::new(name);
+        if !valid_topic(&topic) {
+            return Err(Box::new(MessageStoreError::InvalidName(topic.0)));
+        }
         match self.state.try_lock() {
             Ok(mut s) => {
-                let (tx, _) = broadcast::channel(CHAN_CAPACITY);
-                s.topics.insert(topic.clone(), tx);
+                s.topics
+                    .entry(topic.clone())
+                    .or_insert_with(|| Channel::new(None));
                 Ok(topic)
             }
-            Err(_) => Err(Box::new(MessageStoreError::AddTopic)),
+            Err(_) => Err(Box::new(MessageStoreError::Busy)),
         }
     }
 
-    pub fn remove_topic(&self, name: impl ToString) -> crate::Result<Topic> {
+    /// Adds a topic with a new, unique name to receive replies on. It's up
+    /// to whoever asked for it to remove it once they're done with it.
+    pub fn add_inbox(&self) -> crate::Result<Topic> {
+        self.add_topic(format!("{}.{}", INBOX_PREFIX, Uuid::new_v4().to_simple()))
+    }
+
+    /// Adds a topic whose messages are written to a log before they're
+    /// delivered. Making a durable topic that already exists keeps it,
+    /// but a topic that's already been made without a log can't be made
+    /// durable, as replacing it would drop its subscribers.
+    pub fn add_durable_topic(&self, name: impl ToString) -> crate::Result<Topic> {
         let topic = Topic::new(name);
         match self.state.try_lock() {
             Ok(mut s) => {
-                s.topics.remove(&topic);
+                match s.topics.get(&topic) {
+                    Some(Channel { log: Some(_), .. }) => return Ok(topic),
+                    Some(_) => return Err(Box::new(MessageStoreError::AlreadyExists(topic.0))),
+                    None => {}
+                }
+                let data_dir = match &s.data_dir {
+                    Some(dir) => dir,
+                    None => return Err(Box::new(MessageStoreError::NoDataDir)),
+                };
+                let dir = match topic_dir(data_dir, &topic) {
+                    Some(dir) if valid_topic(&topic) => dir,
+                    _ => return Err(Box::new(MessageStoreError::InvalidName(topic.0))),
+                };
+                let log = Log::open(dir, s.log_config)?;
+                s.topics.insert(topic.clone(), Channel::new(Some(log)));
                 Ok(topic)
             }
-            Err(_) => Err(Box::new(MessageStoreError::RemoveTopic)),
+            Err(_) => Err(Box::new(MessageStoreError::Busy)),
         }
     }
 
+    /// Removes an inbox once its request is done with it. No one else
+    /// can, so rather than fail when the store's busy it waits for it,
+    /// which is brief, as inboxes have no log to delete.
+    pub fn remove_inbox(&self, inbox: &Topic) {
+        let mut s = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        s.topics.remove(inbox);
+    }
+
+    /// Removes a topic, and a durable one's log. The log's deleted after
+    /// letting go of the store, but it still blocks, so from async code
+    /// use `remove_topic_async`.
+    pub fn remove_topic(&self, name: impl ToString) -> crate::Result<Topic> {
+        let topic = Topic::new(name);
+        let dir = match self.state.try_lock() {
+            Ok(mut s) => match s.topics.remove(&topic) {
+                Some(Channel { log: Some(_), .. }) => {
+                    s.data_dir.as_ref().and_then(|d| topic_dir(d, &topic))
+                }
+                Some(_) => None,
+                None => return Err(Box::new(MessageStoreError::UnknownTopic(topic.0))),
+            },
+            Err(_) => return Err(Box::new(MessageStoreError::Busy)),
+        };
+        if let Some(dir) = dir {
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(topic)
+    }
+
+    /// `remove_topic`, but a durable topic's is done off the async
+    /// workers, since deleting its log blocks on the disk
+    pub async fn remove_topic_async(&self, topic_name: String) -> crate::Result<Topic> {
+        if !self.is_durable(&Topic::new(&topic_name)) {
+            return self.remove_topic(topic_name);
+        }
+        let store = self.clone();
+        task::spawn_blocking(move || store.remove_topic(topic_name)).await?
+    }
+
+    /// Subscribes to a topic, or to every topic matching a wildcard,
+    /// including those that haven't been made yet
     pub fn subscribe(&self, topic_name: impl ToString) -> crate::Result<Receiver<Message>> {
         let topic = Topic::new(topic_name);
         match self.state.try_lock() {
+            Ok(mut s) if topic.is_wildcard() && topic.is_valid() => {
+                // wildcards no one's subscribed to any more are dropped
+                // now, rather than checking every time they're matched
+                s.wildcards.retain(|tx| tx.receiver_count() > 0);
+                let tx = s
+                    .wildcards
+                    .get_or_insert_with(&topic.0, || broadcast::channel(CHAN_CAPACITY).0);
+                Ok(tx.subscribe())
+            }
             Ok(s) => match s.topics.get(&topic) {
                 Some(chan) => {
-                    let rx = chan.subscribe();
+                    let rx = chan.tx.subscribe();
                     Ok(rx)
                 }
-                None => Err(Box::new(MessageStoreError::Subscribe)),
+                None => Err(Box::new(MessageStoreError::UnknownTopic(topic.0))),
             },
-            Err(_) => Err(Box::new(MessageStoreError::Subscribe)),
+            Err(_) => Err(Box::new(MessageStoreError::Busy)),
         }
     }
 
-    pub fn publish(&self, topic_name: String, msg: Message) -> crate::Result<Topic> {
+    /// Joins the consumer group `group` of a topic, which shares out the
+    /// messages published to it between its members. Members that go
+    /// away are dropped from the group the next time it's used.
+    pub fn join_group(
+        &self,
+        topic_name: impl ToString,
+        group: &str,
+        assignment: Assignment,
+    ) -> crate::Result<mpsc::Receiver<Message>> {
         let topic = Topic::new(topic_name);
-        match self.state.try_lock() {
+        let groups = match self.state.try_lock() {
             Ok(s) => match s.topics.get(&topic) {
-                Some(ch) => {
-                    ch.send(msg).unwrap();
-                    Ok(topic) // TODO: and number of subs
-                }
-                None => Err(Box::new(MessageStoreError::Publish)),
+                Some(chan) => chan.groups.clone(),
+                None => return Err(Box::new(MessageStoreError::UnknownTopic(topic.0))),
             },
-            Err(_) => Err(Box::new(MessageStoreError::Publish)),
+            Err(_) => return Err(Box::new(MessageStoreError::Busy)),
+        };
+        let mut groups = groups.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(groups.join(group, assignment)?)
+    }
+
+    /// Subscribes to a durable topic, first replaying its log from
+    /// `start`, or from just after the last offset `consumer` committed
+    /// if it has. The receiver gets every message published after the
+    /// replay ends, and passing them through `Replay::live` fills in any
+    /// it misses by lagging, so none are missed or delivered twice.
+    pub fn subscribe_from(
+        &self,
+        topic_name: impl ToString,
+        start: Start,
+        consumer: Option<&str>,
+    ) -> crate::Result<(Replay, Receiver<Message>)> {
+        if let Some(consumer) = consumer.filter(|c| !valid_name(c)) {
+            return Err(Box::new(MessageStoreError::InvalidName(consumer.to_string())));
+        }
+        let topic = Topic::new(topic_name);
+        let (log, tx) = match self.state.try_lock() {
+            Ok(s) => match s.topics.get(&topic) {
+                Some(Channel { tx, log: Some(log), .. }) => (log.clone(), tx.clone()),
+                Some(_) => return Err(Box::new(MessageStoreError::NotDurable(topic.0))),
+                None => return Err(Box::new(MessageStoreError::UnknownTopic(topic.0))),
+            },
+            Err(_) => return Err(Box::new(MessageStoreError::Busy)),
+        };
+
+        // publishers append and send while holding the log, so
+        // subscribing while holding it splits the messages cleanly
+        let guard = log.lock().unwrap_or_else(|e| e.into_inner());
+        let rx = tx.subscribe();
+        let end = guard.next_offset();
+        let committed = match consumer {
+            Some(c) => guard.committed(c)?,
+            None => None,
+        };
+        let next = match (committed, start) {
+            (Some(offset), _) => offset + 1,
+            (None, Start::Earliest) => guard.earliest(),
+            (None, Start::Latest) => end,
+            (None, Start::Offset(offset)) => offset,
+            (None, Start::Timestamp(ts)) => guard.offset_for_timestamp(ts)?,
+        };
+        let next = next.clamp(guard.earliest(), end);
+        drop(guard);
+
+        let replay = Replay {
+            log,
+            next,
+            end,
+            held: None,
+        };
+        Ok((replay, rx))
+    }
+
+    /// Records that `consumer` has processed `topic_name` up to and
+    /// including `offset`, so it resumes after it when it subscribes
+    pub fn commit(
+        &self,
+        topic_name: impl ToString,
+        consumer: &str,
+        offset: u64,
+    ) -> crate::Result<Topic> {
+        if !valid_name(consumer) {
+            return Err(Box::new(MessageStoreError::InvalidName(consumer.to_string())));
+        }
+        let topic = Topic::new(topic_name);
+        let log = match self.state.try_lock() {
+            Ok(s) => match s.topics.get(&topic) {
+                Some(Channel { log: Some(log), .. }) => log.clone(),
+                Some(_) => return Err(Box::new(MessageStoreError::NotDurable(topic.0))),
+                None => return Err(Box::new(MessageStoreError::UnknownTopic(topic.0))),
+            },
+            Err(_) => return Err(Box::new(MessageStoreError::Busy)),
+        };
+
+        let log = log.lock().unwrap_or_else(|e| e.into_inner());
+        if offset >= log.next_offset() {
+            return Err(Box::new(MessageStoreError::InvalidOffset(offset)));
+        }
+        log.commit(consumer, offset)?;
+        Ok(topic)
+    }
+
+    /// `publish`, but a durable topic's is done off the async workers,
+    /// since appending to its log (and syncing it, under `--fsync
+    /// always`) blocks on the disk
+    pub async fn publish_async(
+        &self,
+        topic_name: String,
+        msg: Message,
+    ) -> crate::Result<(Topic, u64)> {
+        if !self.is_durable(&Topic::new(&topic_name)) {
+            return self.publish(topic_name, msg);
+        }
+        let store = self.clone();
+        task::spawn_blocking(move || store.publish(topic_name, msg)).await?
+    }
+
+    /// `commit` off the async workers, since it syncs the commit to disk
+    pub async fn commit_async(
+        &self,
+        topic_name: String,
+        consumer: String,
+        offset: u64,
+    ) -> crate::Result<Topic> {
+        let store = self.clone();
+        task::spawn_blocking(move || store.commit(topic_name, &consumer, offset)).await?
+    }
+
+    // whether the topic has a log, as far as can be told without waiting
+    fn is_durable(&self, topic: &Topic) -> bool {
+        self.state
+            .try_lock()
+            .is_ok_and(|s| s.topics.get(topic).is_some_and(|c| c.log.is_some()))
+    }
+
+    /// Publishes `msg`, returning the topic and the offset it was given.
+    /// It's given a new id too, and timestamped. Durable topics' messages
+    /// are written to their logs first, which blocks, so from async code
+    /// use `publish_async`.
+    pub fn publish(&self, topic_name: String, mut msg: Message) -> crate::Result<(Topic, u64)> {
+        let topic = Topic::new(topic_name);
+        msg.subject = topic.0.clone();
+        msg.id = Uuid::new_v4();
+        let (tx, log, groups, wildcards) = match self.state.try_lock() {
+            Ok(mut s) => {
+                let State { topics, wildcards, .. } = &mut *s;
+                // only whoever made an inbox gets its replies, so no pattern matches one
+                let wildcards: Vec<_> = if topic.is_reserved() {
+                    vec![]
+                } else {
+                    wildcards.matches(&topic.0).into_iter().cloned().collect()
+                };
+                match topics.get_mut(&topic) {
+                    Some(ch) => match &ch.log {
+                        Some(log) => (ch.tx.clone(), log.clone(), ch.groups.clone(), wildcards),
+                        // without a log, the offset is assigned and the message
+                        // sent while holding the state, so they go out in order
+                        None => {
+                            msg.timestamp = log::now();
+                            msg.offset = ch.next;
+                            ch.next += 1;
+                            let offset = msg.offset;
+                            Channel::send(&ch.tx, &ch.groups, &wildcards, msg);
+                            return Ok((topic, offset));
+                        }
+                    },
+                    None => return Err(Box::new(MessageStoreError::UnknownTopic(topic.0))),
+                }
+            }
+            Err(_) => return Err(Box::new(MessageStoreError::Busy)),
+        };
+
+        // timestamped while holding the log, so they don't go backwards in it
+        let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+        msg.timestamp = log::now();
+        msg.offset = log.append_at(&to_record(&msg), msg.timestamp)?;
+        let offset = msg.offset;
+        Channel::send(&tx, &groups, &wildcards, msg);
+        Ok((topic, offset)) // TODO: and number of subs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{MessageStoreDropGuard, Replay};
+    use crate::connection::Connection;
+    use crate::error::MessageStoreError;
+    use crate::log::{FsyncPolicy, LogConfig};
+    use crate::method::Request;
+    use crate::protocol::{Message, Start};
+    use crate::topic::Topic;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bus-broker-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config() -> LogConfig {
+        LogConfig {
+            segment_bytes: 512,
+            fsync: FsyncPolicy::Always,
+            ..LogConfig::default()
+        }
+    }
+
+    async fn replayed(mut replay: Replay) -> Vec<Bytes> {
+        let mut payloads = vec![];
+        loop {
+            let batch = replay.next_batch().await.unwrap();
+            if batch.is_empty() {
+                return payloads;
+            }
+            payloads.extend(batch.into_iter().map(|m| m.bytes));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_durable_topic_survives_restart() {
+        let dir = temp_dir("restart");
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        store.add_durable_topic("orders").unwrap();
+        store.add_topic("chatter").unwrap();
+        for i in 0..100 {
+            let msg = Message::new(Bytes::from(format!("order {}", i)));
+            store.publish("orders".to_string(), msg).unwrap();
+        }
+        drop((guard, store));
+
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        // only the durable topic comes back
+        assert!(store.subscribe("chatter").is_err());
+
+        let (replay, _) = store.subscribe_from("orders", Start::Earliest, None).unwrap();
+        let payloads = replayed(replay).await;
+        assert_eq!(payloads.len(), 100);
+        assert_eq!(payloads[42], Bytes::from("order 42"));
+
+        let (replay, _) = store.subscribe_from("orders", Start::Offset(98), None).unwrap();
+        assert_eq!(replayed(replay).await, vec![Bytes::from("order 98"), Bytes::from("order 99")]);
+
+        store.remove_topic_async("orders".to_string()).await.unwrap();
+        assert!(!dir.join("orders").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replayed_messages_keep_their_ids_and_headers() {
+        let dir = temp_dir("headers");
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        store.add_durable_topic("orders").unwrap();
+        let mut rx = store.subscribe("orders").unwrap();
+        for i in 0..2 {
+            let msg = Message {
+                headers: vec![("Trace-Id".to_string(), format!("trace-{}", i))],
+                ..Message::new(Bytes::from(format!("order {}", i)))
+            };
+            store.publish("orders".to_string(), msg).unwrap();
+        }
+        let live = vec![rx.try_recv().unwrap(), rx.try_recv().unwrap()];
+        assert_ne!(live[0].id, live[1].id);
+        assert!(live[0].timestamp > 0 && live[0].timestamp <= live[1].timestamp);
+        drop((guard, store));
+
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        let (mut replay, _) = store.subscribe_from("orders", Start::Earliest, None).unwrap();
+        let replayed = replay.next_batch().await.unwrap();
+        assert_eq!(replayed.len(), 2);
+        // everything but the subject, which the subscription knows
+        for (msg, live) in replayed.into_iter().zip(live) {
+            assert_eq!(Message { subject: live.subject.clone(), ..msg }, live);
+        }
+        drop((guard, store));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_hands_over_to_live_messages() {
+        let dir = temp_dir("handover");
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        store.add_durable_topic("events").unwrap();
+        for i in 0..3 {
+            store
+                .publish("events".to_string(), Message::new(Bytes::from(format!("old {}", i))))
+                .unwrap();
+        }
+
+        let (replay, mut rx) = store.subscribe_from("events", Start::Offset(1), None).unwrap();
+        store
+            .publish("events".to_string(), Message::new(Bytes::from("new")))
+            .unwrap();
+
+        // the replay stops where the receiver starts
+        assert_eq!(replayed(replay).await, vec![Bytes::from("old 1"), Bytes::from("old 2")]);
+        let msg = rx.try_recv().unwrap();
+        assert_eq!((msg.offset, msg.bytes), (3, Bytes::from("new")));
+        assert!(rx.try_recv().is_err());
+
+        drop((guard, store));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_making_a_topic_again_keeps_it() {
+        let dir = temp_dir("remake");
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        store.add_durable_topic("orders").unwrap();
+        let (_, mut rx) = store.subscribe_from("orders", Start::Latest, None).unwrap();
+        store
+            .publish("orders".to_string(), Message::new(Bytes::from("first")))
+            .unwrap();
+
+        // still durable, with the same subscribers and offsets
+        store.add_topic("orders").unwrap();
+        let (_, offset) = store
+            .publish("orders".to_string(), Message::new(Bytes::from("second")))
+            .unwrap();
+        assert_eq!(offset, 1);
+        assert_eq!(rx.try_recv().unwrap().bytes, Bytes::from("first"));
+        assert_eq!(rx.try_recv().unwrap().bytes, Bytes::from("second"));
+        let (replay, _) = store.subscribe_from("orders", Start::Earliest, None).unwrap();
+        assert_eq!(replayed(replay).await, vec![Bytes::from("first"), Bytes::from("second")]);
+
+        drop((guard, store));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_making_a_plain_topic_durable_is_refused() {
+        let dir = temp_dir("redurable");
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        store.add_topic("orders").unwrap();
+        let mut rx = store.subscribe("orders").unwrap();
+        store
+            .publish("orders".to_string(), Message::new(Bytes::from("first")))
+            .unwrap();
+
+        // the subscriber keeps getting messages, and their offsets carry on
+        let err = store.add_durable_topic("orders").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(MessageStoreError::AlreadyExists(_))));
+        let (_, offset) = store
+            .publish("orders".to_string(), Message::new(Bytes::from("second")))
+            .unwrap();
+        assert_eq!(offset, 1);
+        assert_eq!(rx.try_recv().unwrap().bytes, Bytes::from("first"));
+        assert_eq!(rx.try_recv().unwrap().bytes, Bytes::from("second"));
+        assert!(store.subscribe_from("orders", Start::Earliest, None).is_err());
+        assert!(!dir.join("orders").exists());
+
+        drop((guard, store));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_durable_topics_need_a_data_dir() {
+        let guard = MessageStoreDropGuard::new();
+        let store = guard.store();
+        assert!(store.add_durable_topic("orders").is_err());
+
+        store.add_topic("chatter").unwrap();
+        assert!(store.subscribe_from("chatter", Start::Earliest, None).is_err());
+
+        let dir = temp_dir("names");
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        assert!(guard.store().add_durable_topic("../escape").is_err());
+        drop(guard);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_messages_are_given_offsets() {
+        let guard = MessageStoreDropGuard::new();
+        let store = guard.store();
+        store.add_topic("chatter").unwrap();
+        let mut rx = store.subscribe("chatter").unwrap();
+        for i in 0..3 {
+            let (_, offset) = store
+                .publish("chatter".to_string(), Message::new(Bytes::from("hi")))
+                .unwrap();
+            assert_eq!(offset, i);
+            assert_eq!(rx.try_recv().unwrap().offset, i);
+        }
+    }
+
+    #[test]
+    fn test_wildcard_subscriptions() {
+        let guard = MessageStoreDropGuard::new();
+        let store = guard.store();
+        let mut created = store.subscribe("orders.*.created").unwrap();
+        let mut orders = store.subscribe("orders.>").unwrap();
+        let mut payments = store.subscribe("payments.>").unwrap();
+
+        // topics can be made after their wildcards are subscribed to
+        store.add_topic("orders.eu.created").unwrap();
+        store.add_topic("orders.eu.shipped").unwrap();
+        for topic in &["orders.eu.created", "orders.eu.shipped"] {
+            store
+                .publish(topic.to_string(), Message::new(Bytes::from("hi")))
+                .unwrap();
+        }
+
+        assert_eq!(created.try_recv().unwrap().subject, "orders.eu.created");
+        assert!(created.try_recv().is_err());
+        assert_eq!(orders.try_recv().unwrap().subject, "orders.eu.created");
+        assert_eq!(orders.try_recv().unwrap().subject, "orders.eu.shipped");
+        assert!(payments.try_recv().is_err());
+
+        // wildcards are only for subscribing
+        assert!(store.add_topic("orders.*").is_err());
+        assert!(store.subscribe("orders.>.created").is_err());
+    }
+
+    #[test]
+    fn test_inboxes_are_unique_topics() {
+        let guard = MessageStoreDropGuard::new();
+        let store = guard.store();
+        let first = store.add_inbox().unwrap();
+        let second = store.add_inbox().unwrap();
+        assert_ne!(first, second);
+        assert!(first.0.starts_with("_INBOX."));
+
+        // and only their own subscribers get their replies, not every
+        // wildcard that would match them
+        let mut wildcards: Vec<_> = ["_INBOX.*", ">", "*.*"]
+            .iter()
+            .map(|pattern| store.subscribe(*pattern).unwrap())
+            .collect();
+        let mut rx = store.subscribe(first.0.clone()).unwrap();
+        store
+            .publish(first.0.clone(), Message::new(Bytes::from("pong")))
+            .unwrap();
+        assert_eq!(rx.try_recv().unwrap().bytes, Bytes::from("pong"));
+        for wildcard in wildcards.iter_mut() {
+            assert!(wildcard.try_recv().is_err());
         }
     }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_requests_finish_while_the_store_is_busy() {
+        let guard = MessageStoreDropGuard::new();
+        let store = guard.store();
+        store.add_topic("work").unwrap();
+        let mut work = store.subscribe("work").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let mut conn = Connection::new(listener.accept().await.unwrap().0, 1024);
+        let requester = store.clone();
+        let req = tokio::spawn(async move {
+            let req = Request {
+                subject: "work".to_string(),
+                timeout: None,
+                bytes: Bytes::from("ping"),
+            };
+            req.apply(&requester, &mut conn).await
+        });
+
+        // the reply arrives while another connection has the store
+        let inbox = Topic::new(work.recv().await.unwrap().reply.unwrap());
+        {
+            let s = store.state.lock().unwrap();
+            let _ = s.topics[&inbox].tx.send(Message::new(Bytes::from("pong")));
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        req.await.unwrap().unwrap();
+
+        let mut buf = vec![0; 256];
+        let n = client.read(&mut buf).await.unwrap();
+        let res = String::from_utf8_lossy(&buf[..n]);
+        assert!(res.starts_with("MSG") && res.ends_with("pong\r\n"), "{}", res);
+        assert!(!store.state.lock().unwrap().topics.contains_key(&inbox));
+    }
+
+    #[test]
+    fn test_errors_say_what_went_wrong() {
+        fn cause<T>(res: crate::Result<T>) -> MessageStoreError {
+            match res {
+                Err(e) => *e.downcast::<MessageStoreError>().unwrap(),
+                Ok(_) => panic!("expected an error"),
+            }
+        }
+
+        let guard = MessageStoreDropGuard::new();
+        let store = guard.store();
+        let msg = || Message::new(Bytes::from("hi"));
+        assert!(matches!(
+            cause(store.publish("orders".to_string(), msg())),
+            MessageStoreError::UnknownTopic(t) if t == "orders"
+        ));
+        assert!(matches!(cause(store.remove_topic("orders")), MessageStoreError::UnknownTopic(_)));
+        assert!(matches!(cause(store.add_topic("orders.*")), MessageStoreError::InvalidName(_)));
+        assert!(matches!(cause(store.add_durable_topic("orders")), MessageStoreError::NoDataDir));
+
+        store.add_topic("orders").unwrap();
+        assert!(matches!(
+            cause(store.commit("orders", "billing", 0)),
+            MessageStoreError::NotDurable(_)
+        ));
+
+        // someone else holding the store doesn't block, it fails
+        let state = store.state.lock().unwrap();
+        assert!(matches!(
+            cause(store.publish("orders".to_string(), msg())),
+            MessageStoreError::Busy
+        ));
+        drop(state);
+        store.publish("orders".to_string(), msg()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_consumers_resume_after_their_commits() {
+        let dir = temp_dir("consumers");
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        store.add_durable_topic("orders").unwrap();
+        for i in 0..5 {
+            let msg = Message::new(Bytes::from(format!("order {}", i)));
+            store.publish("orders".to_string(), msg).unwrap();
+        }
+        // can't commit what hasn't been published
+        assert!(matches!(
+            *store.commit("orders", "billing", 5).unwrap_err().downcast().unwrap(),
+            MessageStoreError::InvalidOffset(5)
+        ));
+        assert!(store.commit("orders", "../billing", 1).is_err());
+        store.commit("orders", "billing", 2).unwrap();
+        drop((guard, store));
+
+        let guard = MessageStoreDropGuard::open(&dir, config()).unwrap();
+        let store = guard.store();
+        // the commit wins over where it asked to start
+        let (replay, _) = store.subscribe_from("orders", Start::Latest, Some("billing")).unwrap();
+        assert_eq!(replayed(replay).await, vec![Bytes::from("order 3"), Bytes::from("order 4")]);
+        // and a consumer without one starts where it asked
+        let (replay, _) = store.subscribe_from("orders", Start::Offset(4), Some("audit")).unwrap();
+        assert_eq!(replayed(replay).await, vec![Bytes::from("order 4")]);
+        let (replay, _) = store.subscribe_from("orders", Start::Latest, None).unwrap();
+        assert!(replayed(replay).await.is_empty());
+
+        drop((guard, store));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
 }
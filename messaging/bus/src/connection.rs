@@ -6,6 +6,7 @@ use tokio::net::TcpStream;
 use tokio::sync::broadcast;
 use tracing::info;
 
+use crate::error::ParsingError;
 use crate::protocol::{MethodFrames, Parser};
 
 const BUF_SIZE: usize = 4096;
@@ -13,6 +14,8 @@ const BUF_SIZE: usize = 4096;
 pub struct Connection {
     stream: BufWriter<TcpStream>,
     buffer: BytesMut,
+    // frames with more payload than this are rejected
+    max_payload: usize,
 }
 
 #[derive(Debug)]
@@ -23,10 +26,11 @@ pub struct Shutdown {
 }
 
 impl Connection {
-    pub fn new(socket: TcpStream) -> Self {
+    pub fn new(socket: TcpStream, max_payload: usize) -> Self {
         Connection {
             stream: BufWriter::new(socket),
             buffer: BytesMut::with_capacity(BUF_SIZE),
+            max_payload,
         }
     }
 
@@ -47,27 +51,43 @@ impl Connection {
 
     pub async fn write(&mut self, mut buf: Bytes) -> io::Result<()> {
         self.stream.write_buf(&mut buf).await?;
-        Ok(())
+        self.stream.flush().await
     }
 
     fn parse(&mut self) -> crate::Result<Option<MethodFrames>> {
         // not enough data for reading yet
-        if self.buffer.len() == 0 {
+        if self.buffer.is_empty() {
             return Ok(None);
         }
         let mut buf = Cursor::new(&self.buffer[..]);
 
-        match Parser::check(&mut buf) {
+        match Parser::check(&mut buf, self.max_payload) {
             Ok(_) => {
                 let len = buf.position() as usize;
                 buf.set_position(0);
-                let method = Parser::parse(&mut buf)?;
-                info!(method = ?method);
+                let method = Parser::parse(&mut buf);
+                // the frame's gone either way, so a bad one isn't read again
                 self.buffer.advance(len);
+                let method = method?;
+                info!(method = ?method);
 
                 Ok(Some(method))
             }
-            Err(_) => Err("parsing error!".into()),
+            // wait for the rest, unless it's more than a frame can
+            // be, e.g. a line that never ends
+            Err(ParsingError::Incomplete) if self.buffer.len() <= self.max_payload + BUF_SIZE => {
+                Ok(None)
+            }
+            Err(ParsingError::Incomplete) => Err(ParsingError::TooLarge.into()),
+            // skip the line that didn't parse, so the next one can. One
+            // with a payload is Unframed instead, and ends the connection.
+            Err(ParsingError::Invalid) => {
+                let end = self.buffer.windows(2).position(|w| w == b"\r\n");
+                let len = end.map_or(self.buffer.len(), |i| i + 2);
+                self.buffer.advance(len);
+                Err(ParsingError::Invalid.into())
+            }
+            Err(e) => Err(e.into()),
         }
     }
 }
@@ -2,20 +2,57 @@ use std::fmt;
 
 #[derive(Debug)]
 pub enum MessageStoreError {
-    AddTopic,
-    RemoveTopic,
-    Subscribe,
-    Publish,
+    UnknownTopic(String),
+    // someone else had the store locked
+    Busy,
+    // e.g. a wildcard to publish to, or a consumer that can't be a file name
+    InvalidName(String),
+    // replaying and committing need the topic to be durable
+    NotDurable(String),
+    // durable topics need a data dir to keep their logs in
+    NoDataDir,
+    // committing an offset that hasn't been published yet
+    InvalidOffset(u64),
+    // joining a group whose members have its messages assigned differently
+    AssignmentConflict(String),
+    // subjects only the broker can make, e.g. inboxes
+    PermissionDenied(String),
+    // making a durable topic that's already been made without a log
+    AlreadyExists(String),
 }
 
 #[derive(Debug)]
-pub struct ParsingError;
+pub enum ParsingError {
+    // the frame hasn't all arrived yet
+    Incomplete,
+    Invalid,
+    // an invalid method with a payload, which can't be told apart from the next frame
+    Unframed,
+    // more payload than the broker takes
+    TooLarge,
+}
 
 impl std::error::Error for MessageStoreError {}
 
 impl std::fmt::Display for MessageStoreError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "MessageStoreError")
+        match self {
+            MessageStoreError::UnknownTopic(topic) => write!(f, "no topic {}", topic),
+            MessageStoreError::Busy => write!(f, "the message store is busy"),
+            MessageStoreError::InvalidName(name) => write!(f, "invalid name {}", name),
+            MessageStoreError::NotDurable(topic) => write!(f, "{} isn't durable", topic),
+            MessageStoreError::NoDataDir => write!(f, "durable topics need a --data-dir"),
+            MessageStoreError::InvalidOffset(offset) => {
+                write!(f, "offset {} hasn't been published", offset)
+            }
+            MessageStoreError::AssignmentConflict(group) => {
+                write!(f, "group {} assigns messages differently", group)
+            }
+            MessageStoreError::PermissionDenied(topic) => write!(f, "{} is reserved", topic),
+            MessageStoreError::AlreadyExists(topic) => {
+                write!(f, "{} already exists and isn't durable", topic)
+            }
+        }
     }
 }
 
@@ -23,8 +60,12 @@ impl std::error::Error for ParsingError {}
 
 impl fmt::Display for ParsingError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        "parsing error".fmt(fmt)
+        match self {
+            ParsingError::Incomplete => "incomplete frame".fmt(fmt),
+            ParsingError::Invalid => "parsing error".fmt(fmt),
+            ParsingError::Unframed => "parsing error, payload lost".fmt(fmt),
+            ParsingError::TooLarge => "payload too large".fmt(fmt),
+        }
     }
 }
 
-// TODO: connection error
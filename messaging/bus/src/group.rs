@@ -0,0 +1,196 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use tokio::sync::mpsc;
+
+use crate::error::MessageStoreError;
+use crate::protocol::{Assignment, Message};
+
+// messages buffered for each member before it's skipped over
+const MEMBER_CAPACITY: usize = 1024;
+
+/// A topic's consumer groups. Each message published goes to every
+/// group, but only to one member of each, so they share the work.
+#[derive(Debug, Default)]
+pub struct Groups {
+    groups: HashMap<String, Group>,
+}
+
+#[derive(Debug)]
+struct Group {
+    assignment: Assignment,
+    members: Vec<mpsc::Sender<Message>>,
+    // the member the next round-robin message goes to
+    next: usize,
+}
+
+fn hash_key(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize
+}
+
+impl Group {
+    // members whose subscribers have gone leave the group,
+    // so what they'd have got is spread over the rest
+    fn rebalance(&mut self) {
+        self.members.retain(|m| !m.is_closed());
+        if self.next >= self.members.len() {
+            self.next = 0;
+        }
+    }
+
+    // the member the message should go to, if there are any
+    fn assign(&mut self, msg: &Message) -> Option<usize> {
+        if self.members.is_empty() {
+            return None;
+        }
+        match (self.assignment, &msg.key) {
+            (Assignment::Hash, Some(key)) => Some(hash_key(key) % self.members.len()),
+            // messages without a key have nothing to hash
+            _ => {
+                let member = self.next;
+                self.next = (self.next + 1) % self.members.len();
+                Some(member)
+            }
+        }
+    }
+
+    fn deliver(&mut self, msg: &Message) {
+        self.rebalance();
+        let first = match self.assign(msg) {
+            Some(member) => member,
+            None => return,
+        };
+        // a member that's fallen behind is skipped, rather than holding
+        // up the publisher, and if they all have the message is dropped
+        let n = self.members.len();
+        for i in 0..n {
+            if self.members[(first + i) % n].try_send(msg.clone()).is_ok() {
+                return;
+            }
+        }
+    }
+}
+
+impl Groups {
+    /// Adds a member to the group `name`, creating it if needed. Everyone
+    /// in a group has to agree on how messages are assigned to them.
+    pub fn join(
+        &mut self,
+        name: &str,
+        assignment: Assignment,
+    ) -> Result<mpsc::Receiver<Message>, MessageStoreError> {
+        let group = self.groups.entry(name.to_string()).or_insert_with(|| Group {
+            assignment,
+            members: vec![],
+            next: 0,
+        });
+        group.rebalance();
+        if group.members.is_empty() {
+            group.assignment = assignment;
+        } else if group.assignment != assignment {
+            return Err(MessageStoreError::AssignmentConflict(name.to_string()));
+        }
+
+        let (tx, rx) = mpsc::channel(MEMBER_CAPACITY);
+        group.members.push(tx);
+        Ok(rx)
+    }
+
+    pub fn deliver(&mut self, msg: &Message) {
+        for group in self.groups.values_mut() {
+            group.deliver(msg);
+        }
+        self.groups.retain(|_, g| !g.members.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashSet;
+
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn keyed(key: &str) -> Message {
+        Message {
+            key: Some(key.to_string()),
+            ..Message::new(Bytes::from(key.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_round_robin_delivers_each_message_once() {
+        let mut groups = Groups::default();
+        let mut members: Vec<_> = (0..3)
+            .map(|_| groups.join("workers", Assignment::RoundRobin).unwrap())
+            .collect();
+        let mut other = groups.join("audit", Assignment::RoundRobin).unwrap();
+
+        for i in 0..6 {
+            groups.deliver(&Message::new(Bytes::from(format!("job {}", i))));
+        }
+        for (i, rx) in members.iter_mut().enumerate() {
+            assert_eq!(rx.try_recv().unwrap().bytes, Bytes::from(format!("job {}", i)));
+            assert_eq!(rx.try_recv().unwrap().bytes, Bytes::from(format!("job {}", i + 3)));
+            assert!(rx.try_recv().is_err());
+        }
+        // each group gets every message
+        for i in 0..6 {
+            assert_eq!(other.try_recv().unwrap().bytes, Bytes::from(format!("job {}", i)));
+        }
+    }
+
+    #[test]
+    fn test_hash_keeps_keys_on_one_member() {
+        let mut groups = Groups::default();
+        let mut members: Vec<_> = (0..4)
+            .map(|_| groups.join("workers", Assignment::Hash).unwrap())
+            .collect();
+
+        for _ in 0..3 {
+            for key in &["alice", "bob", "carol", "dave", "erin"] {
+                groups.deliver(&keyed(key));
+            }
+        }
+        let mut seen = HashSet::new();
+        for rx in members.iter_mut() {
+            let mut mine = HashSet::new();
+            while let Ok(msg) = rx.try_recv() {
+                mine.insert(msg.bytes);
+            }
+            // no key is split across members
+            assert!(mine.is_disjoint(&seen));
+            seen.extend(mine);
+        }
+        assert_eq!(seen.len(), 5);
+
+        assert!(groups.join("workers", Assignment::RoundRobin).is_err());
+    }
+
+    #[test]
+    fn test_members_leaving_are_rebalanced() {
+        let mut groups = Groups::default();
+        let mut first = groups.join("workers", Assignment::Hash).unwrap();
+        let second = groups.join("workers", Assignment::Hash).unwrap();
+        drop(second);
+
+        for key in &["alice", "bob", "carol", "dave"] {
+            groups.deliver(&keyed(key));
+        }
+        for _ in 0..4 {
+            assert!(first.try_recv().is_ok());
+        }
+
+        // once everyone's gone the group is too, so can come back different
+        drop(first);
+        groups.deliver(&keyed("alice"));
+        assert!(groups.groups.is_empty());
+        assert!(groups.join("workers", Assignment::RoundRobin).is_ok());
+    }
+
+}
@@ -0,0 +1,86 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const ENTRY_SIZE: usize = 8;
+
+/// Maps some of a segment's offsets to where their records start in its
+/// log file. Only one record every `index_interval_bytes` gets an entry,
+/// so finding the others means scanning forward from the entry before.
+#[derive(Debug)]
+pub(super) struct OffsetIndex {
+    file: File,
+    // (offset relative to the segment's base, position in the log file)
+    entries: Vec<(u32, u32)>,
+}
+
+impl OffsetIndex {
+    /// An empty index at `path`, replacing anything already there
+    pub(super) fn create(path: &Path) -> io::Result<OffsetIndex> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(OffsetIndex {
+            file,
+            entries: vec![],
+        })
+    }
+
+    /// The index at `path`, or `None` if it's missing or damaged
+    /// and has to be rebuilt from the log file
+    pub(super) fn load(path: &Path) -> io::Result<Option<OffsetIndex>> {
+        let mut file = match OpenOptions::new().read(true).append(true).open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)?;
+        if buf.len() % ENTRY_SIZE != 0 {
+            return Ok(None);
+        }
+
+        let entries: Vec<(u32, u32)> = buf
+            .chunks(ENTRY_SIZE)
+            .map(|e| {
+                let relative = u32::from_be_bytes([e[0], e[1], e[2], e[3]]);
+                let position = u32::from_be_bytes([e[4], e[5], e[6], e[7]]);
+                (relative, position)
+            })
+            .collect();
+        // entries are appended in order, so anything else is garbage
+        if entries.windows(2).any(|w| w[0].0 >= w[1].0 || w[0].1 >= w[1].1) {
+            return Ok(None);
+        }
+        Ok(Some(OffsetIndex { file, entries }))
+    }
+
+    pub(super) fn append(&mut self, relative: u32, position: u32) -> io::Result<()> {
+        let mut entry = [0; ENTRY_SIZE];
+        entry[..4].copy_from_slice(&relative.to_be_bytes());
+        entry[4..].copy_from_slice(&position.to_be_bytes());
+        self.file.write_all(&entry)?;
+        self.entries.push((relative, position));
+        Ok(())
+    }
+
+    /// Where to start scanning for the record `relative` offsets into
+    /// the segment: the position of the closest entry at or before it
+    pub(super) fn lookup(&self, relative: u32) -> u32 {
+        match self.entries.partition_point(|(r, _)| *r <= relative) {
+            0 => 0,
+            i => self.entries[i - 1].1,
+        }
+    }
+
+    /// Every entry, as (relative offset, position), in order
+    pub(super) fn entries(&self) -> &[(u32, u32)] {
+        &self.entries
+    }
+
+    pub(super) fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
@@ -0,0 +1,447 @@
+//! An append-only log on disk, which durable topics write every message
+//! to so it can be replayed after subscribers reconnect or the broker
+//! restarts. As in Kafka, a log is a directory of segments: each one
+//! holds a contiguous run of offsets, named by the first of them, and
+//! only the newest is written to. Once it reaches `segment_bytes` it's
+//! synced and a new one is started.
+//!
+//! Records are `crc | length | offset | timestamp | payload`, so a
+//! record only partly written when the broker died is detected and cut
+//! off the end of the log when it's next opened.
+//!
+//! Named consumers commit how far through the log they've got, which
+//! is kept next to the segments in a `<consumer>.consumer` file.
+
+mod index;
+mod segment;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use segment::{Segment, Slice, HEADER_SIZE};
+
+/// When appended records are flushed from the OS to the disk. Whatever
+/// is still unsynced is lost if the machine (not just the broker) dies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every record, the slowest and safest
+    Always,
+    /// After every `n` records
+    Every(u64),
+    /// On the first append at least this long after the last sync
+    Interval(Duration),
+    /// Only when a segment is rolled over or the log is closed
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogConfig {
+    /// Size a segment can grow to before a new one is started
+    pub segment_bytes: u64,
+    /// Bytes of records between entries in a segment's offset index
+    pub index_interval_bytes: u64,
+    pub fsync: FsyncPolicy,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            segment_bytes: 16 * 1024 * 1024,
+            index_interval_bytes: 4096,
+            fsync: FsyncPolicy::Interval(Duration::from_secs(1)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub offset: u64,
+    // milliseconds since the epoch, when the record was appended
+    pub timestamp: u64,
+    pub payload: Bytes,
+}
+
+/// Milliseconds since the epoch, as records are timestamped
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[derive(Debug)]
+pub struct Log {
+    dir: PathBuf,
+    config: LogConfig,
+    // ordered by base offset, the last one being written to
+    segments: Vec<Segment>,
+    // records appended since the last sync
+    unsynced: u64,
+    last_sync: Instant,
+}
+
+impl Log {
+    /// Opens the log in `dir`, creating it if it doesn't exist
+    pub fn open(dir: impl AsRef<Path>, config: LogConfig) -> io::Result<Log> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut bases = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "log") {
+                if let Some(base) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                    bases.push(base);
+                }
+            }
+        }
+        bases.sort_unstable();
+
+        // segments that were rolled over end where the next one begins,
+        // only the last can have been cut short
+        let interval = config.index_interval_bytes;
+        let mut segments = vec![];
+        for (i, base) in bases.iter().enumerate() {
+            segments.push(match bases.get(i + 1) {
+                Some(next) => Segment::open(&dir, *base, *next, interval)?,
+                None => Segment::recover(&dir, *base, interval)?,
+            });
+        }
+        if segments.is_empty() {
+            segments.push(Segment::create(&dir, 0, interval)?);
+        }
+
+        Ok(Log {
+            dir,
+            config,
+            segments,
+            unsynced: 0,
+            last_sync: Instant::now(),
+        })
+    }
+
+    fn active(&mut self) -> &mut Segment {
+        self.segments.last_mut().expect("a log always has a segment")
+    }
+
+    /// The oldest offset still in the log
+    pub fn earliest(&self) -> u64 {
+        self.segments[0].base
+    }
+
+    /// The offset the next record appended will get
+    pub fn next_offset(&self) -> u64 {
+        self.segments[self.segments.len() - 1].next
+    }
+
+    /// Appends `payload` as arriving at `timestamp`, usually `now()`,
+    /// returning its offset. Timestamps shouldn't go backwards.
+    pub fn append_at(&mut self, payload: &[u8], timestamp: u64) -> io::Result<u64> {
+        // segments can't grow past what their index can point into
+        let limit = self.config.segment_bytes.min(u32::MAX as u64);
+        let active = self.active();
+        if active.size > 0 && active.size + HEADER_SIZE + payload.len() as u64 > limit {
+            self.roll()?;
+        }
+
+        let offset = self.active().append(timestamp, payload)?;
+        self.unsynced += 1;
+        let due = match self.config.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Every(n) => self.unsynced >= n,
+            FsyncPolicy::Interval(d) => self.last_sync.elapsed() >= d,
+            FsyncPolicy::Never => false,
+        };
+        if due {
+            self.sync()?;
+        }
+        Ok(offset)
+    }
+
+    // finishes the active segment and starts another after it
+    fn roll(&mut self) -> io::Result<()> {
+        self.sync()?;
+        let next = self.next_offset();
+        let segment = Segment::create(&self.dir, next, self.config.index_interval_bytes)?;
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    /// The records from offset `from` on, as far as they've been appended,
+    /// to be read later without holding the log
+    pub fn snapshot(&self, from: u64) -> Snapshot {
+        let from = from.max(self.earliest());
+        // the segment `from` is in, i.e. the last starting at or before it
+        let first = self.segments.partition_point(|s| s.base <= from) - 1;
+        let slices = self.segments[first..]
+            .iter()
+            .enumerate()
+            .map(|(i, s)| s.slice(if i == 0 { from } else { s.base }))
+            .collect();
+        Snapshot { from, slices }
+    }
+
+    /// The first offset appended at or after `timestamp`, or
+    /// the next offset if everything in the log is older
+    pub fn offset_for_timestamp(&self, timestamp: u64) -> io::Result<u64> {
+        for segment in self.segments.iter() {
+            if let Some(offset) = segment.offset_for_timestamp(timestamp)? {
+                return Ok(offset);
+            }
+        }
+        Ok(self.next_offset())
+    }
+
+    fn consumer_path(&self, consumer: &str) -> PathBuf {
+        self.dir.join(format!("{}.consumer", consumer))
+    }
+
+    /// Records that `consumer` has processed everything up to and
+    /// including `offset`. The commit is synced before returning.
+    pub fn commit(&self, consumer: &str, offset: u64) -> io::Result<()> {
+        // written aside then renamed, so a crash leaves the old
+        // commit or the new one but never half of one
+        let path = self.consumer_path(consumer);
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&offset.to_be_bytes())?;
+        file.sync_data()?;
+        fs::rename(tmp, path)
+    }
+
+    /// The last offset `consumer` committed, if it has
+    pub fn committed(&self, consumer: &str) -> io::Result<Option<u64>> {
+        match fs::read(self.consumer_path(consumer)) {
+            Ok(buf) => match <[u8; 8]>::try_from(buf.as_slice()) {
+                Ok(bytes) => Ok(Some(u64::from_be_bytes(bytes))),
+                Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "damaged consumer offset")),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.active().sync()?;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+/// Some of a log's records, which can be read without the log, and so
+/// without holding up appends while they're read. Whatever's appended
+/// after it's taken isn't in it.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    from: u64,
+    slices: Vec<Slice>,
+}
+
+impl Snapshot {
+    /// Up to `max` records, from the offset it was taken from
+    pub fn read(&self, max: usize) -> io::Result<Vec<Record>> {
+        let mut records = vec![];
+        for slice in self.slices.iter() {
+            if records.len() >= max {
+                break;
+            }
+            let start = records.last().map_or(self.from, |r: &Record| r.offset + 1);
+            records.extend(slice.read(start, max - records.len())?);
+        }
+        Ok(records)
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::{self, OpenOptions};
+    use std::path::PathBuf;
+
+    use super::segment::paths;
+    use super::{now, FsyncPolicy, Log, LogConfig};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bus-log-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // small segments and a dense index, so a few records exercise both
+    fn config() -> LogConfig {
+        LogConfig {
+            segment_bytes: 256,
+            index_interval_bytes: 64,
+            fsync: FsyncPolicy::Never,
+        }
+    }
+
+    fn payloads(log: &Log, from: u64, max: usize) -> Vec<String> {
+        log.snapshot(from).read(max)
+            .unwrap()
+            .into_iter()
+            .map(|r| String::from_utf8(r.payload.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_append_and_read_across_segments() {
+        let dir = temp_dir("segments");
+        let mut log = Log::open(&dir, config()).unwrap();
+        for i in 0..50u64 {
+            assert_eq!(log.append_at(format!("message {}", i).as_bytes(), now()).unwrap(), i);
+        }
+        assert_eq!(log.next_offset(), 50);
+        assert!(log.segments.len() > 5);
+
+        let all = log.snapshot(0).read(100).unwrap();
+        assert_eq!(all.len(), 50);
+        assert!(all.iter().enumerate().all(|(i, r)| r.offset == i as u64));
+
+        // from the middle of a segment, and stopping at `max`
+        assert_eq!(payloads(&log, 17, 3), vec!["message 17", "message 18", "message 19"]);
+        assert!(log.snapshot(50).read(10).unwrap().is_empty());
+
+        drop(log);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshots_read_what_was_there_when_taken() {
+        let dir = temp_dir("snapshot");
+        let mut log = Log::open(&dir, config()).unwrap();
+        for i in 0..10 {
+            log.append_at(format!("message {}", i).as_bytes(), now()).unwrap();
+        }
+        let snapshot = log.snapshot(8);
+        // appends carry on, into new segments too
+        for i in 10..30 {
+            log.append_at(format!("message {}", i).as_bytes(), now()).unwrap();
+        }
+
+        let records = snapshot.read(100).unwrap();
+        assert_eq!(records.iter().map(|r| r.offset).collect::<Vec<_>>(), vec![8, 9]);
+        assert_eq!(payloads(&log, 9, 2), vec!["message 9", "message 10"]);
+
+        drop(log);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_continues_the_log() {
+        let dir = temp_dir("reopen");
+        let mut log = Log::open(&dir, config()).unwrap();
+        for i in 0..20 {
+            log.append_at(format!("message {}", i).as_bytes(), now()).unwrap();
+        }
+        let segments = log.segments.len();
+        drop(log);
+
+        let mut log = Log::open(&dir, config()).unwrap();
+        assert_eq!(log.segments.len(), segments);
+        assert_eq!(log.next_offset(), 20);
+        assert_eq!(log.append_at(b"after restart", now()).unwrap(), 20);
+        assert_eq!(payloads(&log, 19, 10), vec!["message 19", "after restart"]);
+
+        drop(log);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_write_is_truncated() {
+        let dir = temp_dir("torn");
+        let config = LogConfig {
+            segment_bytes: 1 << 20,
+            ..config()
+        };
+        let mut log = Log::open(&dir, config).unwrap();
+        for i in 0..5 {
+            log.append_at(format!("message {}", i).as_bytes(), now()).unwrap();
+        }
+        drop(log);
+
+        // the broker died halfway through writing the last record
+        let (path, _) = paths(&dir, 0);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 4).unwrap();
+
+        let mut log = Log::open(&dir, config).unwrap();
+        assert_eq!(log.next_offset(), 4);
+        assert_eq!(log.append_at(b"replacement", now()).unwrap(), 4);
+        assert_eq!(payloads(&log, 3, 10), vec!["message 3", "replacement"]);
+
+        drop(log);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_offset_for_timestamp() {
+        let dir = temp_dir("timestamps");
+        let mut log = Log::open(&dir, config()).unwrap();
+        for i in 0..40u64 {
+            // two records a millisecond, across several segments
+            log.append_at(format!("message {}", i).as_bytes(), 1000 + i / 2).unwrap();
+        }
+        assert!(log.segments.len() > 2);
+
+        assert_eq!(log.offset_for_timestamp(0).unwrap(), 0);
+        assert_eq!(log.offset_for_timestamp(1000).unwrap(), 0);
+        assert_eq!(log.offset_for_timestamp(1007).unwrap(), 14);
+        assert_eq!(log.offset_for_timestamp(1019).unwrap(), 38);
+        assert_eq!(log.offset_for_timestamp(2000).unwrap(), 40);
+
+        drop(log);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_consumer_commits_survive_reopening() {
+        let dir = temp_dir("commits");
+        let log = Log::open(&dir, config()).unwrap();
+        assert_eq!(log.committed("billing").unwrap(), None);
+        log.commit("billing", 7).unwrap();
+        log.commit("billing", 12).unwrap();
+        log.commit("audit", 3).unwrap();
+        drop(log);
+
+        let log = Log::open(&dir, config()).unwrap();
+        assert_eq!(log.committed("billing").unwrap(), Some(12));
+        assert_eq!(log.committed("audit").unwrap(), Some(3));
+
+        drop(log);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_index_is_rebuilt() {
+        let dir = temp_dir("index");
+        let mut log = Log::open(&dir, config()).unwrap();
+        for i in 0..30 {
+            log.append_at(format!("message {}", i).as_bytes(), now()).unwrap();
+        }
+        drop(log);
+
+        let (_, index) = paths(&dir, 0);
+        fs::write(&index, b"garbage").unwrap();
+
+        let log = Log::open(&dir, config()).unwrap();
+        assert_eq!(payloads(&log, 2, 1), vec!["message 2"]);
+        assert_eq!(log.snapshot(0).read(100).unwrap().len(), 30);
+
+        drop(log);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+}
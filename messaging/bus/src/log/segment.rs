@@ -0,0 +1,283 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+
+use super::index::OffsetIndex;
+use super::Record;
+
+// crc, payload length, offset and timestamp
+pub(super) const HEADER_SIZE: u64 = 24;
+
+/// The files for the segment whose first offset is `base`, named
+/// so that listing a topic's directory sorts them by offset
+pub(super) fn paths(dir: &Path, base: u64) -> (PathBuf, PathBuf) {
+    (
+        dir.join(format!("{:020}.log", base)),
+        dir.join(format!("{:020}.index", base)),
+    )
+}
+
+fn encode(offset: u64, timestamp: u64, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_SIZE as usize + payload.len());
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&offset.to_be_bytes());
+    buf.extend_from_slice(&timestamp.to_be_bytes());
+    buf.extend_from_slice(payload);
+    let crc = crc32fast::hash(&buf[4..]);
+    buf[..4].copy_from_slice(&crc.to_be_bytes());
+    buf
+}
+
+// the next record, or `None` at the end of the file or at a
+// record that was only partly written before a crash
+fn read_record<R: Read>(src: &mut R) -> io::Result<Option<Record>> {
+    let mut header = [0; HEADER_SIZE as usize];
+    match src.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let field = |at: usize, len: usize| {
+        header[at..at + len]
+            .iter()
+            .fold(0u64, |n, b| (n << 8) | *b as u64)
+    };
+    let (crc, len) = (field(0, 4) as u32, field(4, 4) as usize);
+
+    // read as it arrives rather than trusting the length up front,
+    // since a damaged header can claim anything
+    let mut payload = Vec::new();
+    src.by_ref().take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() < len {
+        return Ok(None);
+    }
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[4..]);
+    hasher.update(&payload);
+    if hasher.finalize() != crc {
+        return Ok(None);
+    }
+
+    Ok(Some(Record {
+        offset: field(8, 8),
+        timestamp: field(16, 8),
+        payload: Bytes::from(payload),
+    }))
+}
+
+/// A contiguous run of a log's records, stored in one file, along
+/// with the index used to find records in it by offset
+#[derive(Debug)]
+pub(super) struct Segment {
+    pub(super) base: u64,
+    // the offset the next record appended gets
+    pub(super) next: u64,
+    // bytes in the log file
+    pub(super) size: u64,
+    path: PathBuf,
+    file: File,
+    index: OffsetIndex,
+    index_interval: u64,
+    // bytes appended since the last index entry
+    unindexed: u64,
+}
+
+impl Segment {
+    pub(super) fn create(dir: &Path, base: u64, index_interval: u64) -> io::Result<Segment> {
+        let (path, index_path) = paths(dir, base);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        file.set_len(0)?;
+        Ok(Segment {
+            base,
+            next: base,
+            size: 0,
+            path,
+            file,
+            index: OffsetIndex::create(&index_path)?,
+            index_interval,
+            unindexed: 0,
+        })
+    }
+
+    /// Opens a segment that's been rolled over, whose records must end
+    /// just before `next`. Its index is only rebuilt if it's damaged.
+    pub(super) fn open(dir: &Path, base: u64, next: u64, index_interval: u64) -> io::Result<Segment> {
+        let (path, index_path) = paths(dir, base);
+        match OffsetIndex::load(&index_path)? {
+            Some(index) => {
+                let file = OpenOptions::new().append(true).open(&path)?;
+                Ok(Segment {
+                    base,
+                    next,
+                    size: file.metadata()?.len(),
+                    path,
+                    file,
+                    index,
+                    index_interval,
+                    unindexed: 0,
+                })
+            }
+            None => Segment::recover(dir, base, index_interval),
+        }
+    }
+
+    /// Opens the segment that was being written to, checking every
+    /// record in it, cutting off any partly written one at the end and
+    /// rebuilding the index, which may be missing its last entries
+    pub(super) fn recover(dir: &Path, base: u64, index_interval: u64) -> io::Result<Segment> {
+        let (path, index_path) = paths(dir, base);
+        let mut segment = Segment {
+            base,
+            next: base,
+            size: 0,
+            path: path.clone(),
+            file: OpenOptions::new().append(true).open(&path)?,
+            index: OffsetIndex::create(&index_path)?,
+            index_interval,
+            unindexed: 0,
+        };
+
+        let mut reader = BufReader::new(File::open(&path)?);
+        while let Some(record) = read_record(&mut reader)? {
+            // a record that isn't the next offset can only
+            // be left over from something that went wrong
+            if record.offset != segment.next {
+                break;
+            }
+            let size = HEADER_SIZE + record.payload.len() as u64;
+            segment.index_record(size)?;
+            segment.size += size;
+            segment.next += 1;
+        }
+
+        // drop whatever follows the last whole record
+        segment.file.set_len(segment.size)?;
+        Ok(segment)
+    }
+
+    // adds an index entry for a record of `size` bytes about
+    // to be written at the end of the file, if one is due
+    fn index_record(&mut self, size: u64) -> io::Result<()> {
+        if self.size == 0 || self.unindexed >= self.index_interval {
+            self.index
+                .append((self.next - self.base) as u32, self.size as u32)?;
+            self.unindexed = 0;
+        }
+        self.unindexed += size;
+        Ok(())
+    }
+
+    /// Appends the record at offset `next`
+    pub(super) fn append(&mut self, timestamp: u64, payload: &[u8]) -> io::Result<u64> {
+        let offset = self.next;
+        let buf = encode(offset, timestamp, payload);
+        self.index_record(buf.len() as u64)?;
+        self.file.write_all(&buf)?;
+        self.size += buf.len() as u64;
+        self.next += 1;
+        Ok(offset)
+    }
+
+    /// Up to `max` records, starting at offset `from`
+    pub(super) fn read(&self, from: u64, max: usize) -> io::Result<Vec<Record>> {
+        self.slice(from).read(from, max)
+    }
+
+    /// Where the records from `from` on are, as far as they've been
+    /// written, so they can be read without the segment
+    pub(super) fn slice(&self, from: u64) -> Slice {
+        Slice {
+            path: self.path.clone(),
+            next: self.next,
+            size: self.size,
+            position: self.index.lookup(from.saturating_sub(self.base) as u32) as u64,
+        }
+    }
+
+    // the record starting at `position` in the file
+    fn read_at(&self, position: u32) -> io::Result<Option<Record>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(position as u64))?;
+        read_record(&mut BufReader::new(file))
+    }
+
+    /// The first offset appended at or after `timestamp`, if any are in
+    /// this segment. Timestamps only go up (unless the clock goes back),
+    /// so the index narrows it down to one interval, which is scanned.
+    pub(super) fn offset_for_timestamp(&self, timestamp: u64) -> io::Result<Option<u64>> {
+        let entries = self.index.entries();
+        let (mut lo, mut hi) = (0, entries.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.read_at(entries[mid].1)? {
+                Some(record) if record.timestamp < timestamp => lo = mid + 1,
+                _ => hi = mid,
+            }
+        }
+        // `lo` is the first entry at or after it, so start at the one before
+        let from = match lo {
+            0 => self.base,
+            i => self.base + entries[i - 1].0 as u64,
+        };
+
+        let mut next = from;
+        while next < self.next {
+            let records = self.read(next, 256)?;
+            if let Some(record) = records.iter().find(|r| r.timestamp >= timestamp) {
+                return Ok(Some(record.offset));
+            }
+            match records.last() {
+                Some(record) => next = record.offset + 1,
+                None => break,
+            }
+        }
+        Ok(None)
+    }
+
+    pub(super) fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.index.sync()
+    }
+}
+
+/// Part of a segment's file, from a record at or before the first one
+/// to be read up to the end of the last one that had been written.
+/// Segments are only appended to, so it stays the same as they grow.
+#[derive(Debug, Clone)]
+pub(super) struct Slice {
+    path: PathBuf,
+    // the offset after the last record in it
+    next: u64,
+    size: u64,
+    position: u64,
+}
+
+impl Slice {
+    /// Up to `max` records, starting at offset `from`, which can't
+    /// be before the one the slice was taken from
+    pub(super) fn read(&self, from: u64, max: usize) -> io::Result<Vec<Record>> {
+        let mut records = vec![];
+        if from >= self.next {
+            return Ok(records);
+        }
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.position))?;
+        let mut reader = BufReader::new(file.take(self.size - self.position));
+        while records.len() < max {
+            match read_record(&mut reader)? {
+                Some(record) if record.offset >= self.next => break,
+                Some(record) if record.offset >= from => records.push(record),
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(records)
+    }
+}
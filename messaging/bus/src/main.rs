@@ -1,3 +1,9 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
 use tokio::net::TcpListener;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
@@ -5,16 +11,103 @@ use tracing_subscriber::FmtSubscriber;
 mod broker;
 mod connection;
 mod error;
+mod group;
+mod log;
 mod method;
 mod protocol;
+mod response;
 mod server;
 mod topic;
+mod trie;
+
+use broker::MessageStoreDropGuard;
+use log::{FsyncPolicy, LogConfig};
+use protocol::DEFAULT_MAX_PAYLOAD;
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Result<T> = std::result::Result<T, Error>;
 
+const USAGE: &str = "usage: bus [--bind ADDR] [--data-dir DIR] [--fsync always|never|every:N|interval:MS]
+           [--segment-bytes N] [--max-payload BYTES]
+
+Durable topics (MAKE subject DURABLE) need a --data-dir to keep their logs in, and
+can be replayed with SUB subject EARLIEST|LATEST|OFFSET|@MS [consumer]. Workers can
+share a topic's messages with SUB subject GROUP name [ROUNDROBIN|HASH]. Subjects are
+dot-separated, and SUB takes * for any one token and a trailing > for the rest. REQ
+subject [timeout-ms] publishes a request and waits for the first reply to it. Ending
+a PUB or REQ with #bytes, e.g. #11, gives the payload's length, so it can hold
+anything; frames with more than --max-payload bytes are rejected. HPUB subject
+[reply] header-bytes total-bytes publishes with Name: value headers too.";
+
+struct Config {
+    bind: SocketAddr,
+    data_dir: Option<PathBuf>,
+    log: LogConfig,
+    max_payload: usize,
+}
+
+fn parse_fsync(value: &str) -> Option<FsyncPolicy> {
+    match value.split_once(':') {
+        None if value == "always" => Some(FsyncPolicy::Always),
+        None if value == "never" => Some(FsyncPolicy::Never),
+        Some(("every", n)) => n.parse().ok().filter(|n| *n > 0).map(FsyncPolicy::Every),
+        Some(("interval", ms)) => ms
+            .parse()
+            .ok()
+            .map(|ms| FsyncPolicy::Interval(Duration::from_millis(ms))),
+        _ => None,
+    }
+}
+
+fn parse_args(args: &[String]) -> std::result::Result<Config, String> {
+    let mut config = Config {
+        bind: "127.0.0.1:8080".parse().unwrap(),
+        data_dir: None,
+        log: LogConfig::default(),
+        max_payload: DEFAULT_MAX_PAYLOAD,
+    };
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        let invalid = || format!("invalid value for {}: {}", flag, value);
+        match flag.as_str() {
+            "--bind" => config.bind = value.parse().map_err(|_| invalid())?,
+            "--data-dir" => config.data_dir = Some(PathBuf::from(value)),
+            "--fsync" => config.log.fsync = parse_fsync(value).ok_or_else(invalid)?,
+            "--segment-bytes" => {
+                config.log.segment_bytes = value
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(invalid)?
+            }
+            "--max-payload" => {
+                config.max_payload = value
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(invalid)?
+            }
+            _ => return Err(format!("unknown flag: {}", flag)),
+        }
+    }
+    Ok(config)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match parse_args(&args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+
     let subscriber = FmtSubscriber::builder()
         // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
         // will be written to stdout.
@@ -24,9 +117,66 @@ async fn main() -> Result<()> {
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
+    let store = match &config.data_dir {
+        Some(dir) => match MessageStoreDropGuard::open(dir, config.log) {
+            Ok(store) => store,
+            Err(e) => {
+                eprintln!("couldn't open {}: {}", dir.display(), e);
+                process::exit(1);
+            }
+        },
+        None => MessageStoreDropGuard::new(),
+    };
+
+    let listener = TcpListener::bind(config.bind).await.unwrap();
     info!(listener=  ?listener, "Setup TCP listener: ");
 
-    tokio::spawn(async move { server::run(listener, tokio::signal::ctrl_c(), 250).await }).await?;
+    let max_payload = config.max_payload;
+    tokio::spawn(async move {
+        server::run(listener, store, tokio::signal::ctrl_c(), 250, max_payload).await
+    })
+    .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_flags() {
+        let config = parse_args(&args(
+            "--bind 127.0.0.1:9000 --data-dir /var/lib/bus --fsync every:100 --segment-bytes 1024",
+        ))
+        .unwrap();
+        assert_eq!(config.bind, "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(config.data_dir, Some(PathBuf::from("/var/lib/bus")));
+        assert_eq!(config.log.fsync, FsyncPolicy::Every(100));
+        assert_eq!(config.log.segment_bytes, 1024);
+
+        let config = parse_args(&args("--fsync interval:250")).unwrap();
+        assert_eq!(config.log.fsync, FsyncPolicy::Interval(Duration::from_millis(250)));
+        assert_eq!(parse_args(&args("--fsync always")).unwrap().log.fsync, FsyncPolicy::Always);
+        assert_eq!(parse_args(&args("--fsync never")).unwrap().log.fsync, FsyncPolicy::Never);
+        assert!(parse_args(&[]).unwrap().data_dir.is_none());
+        assert_eq!(parse_args(&[]).unwrap().max_payload, DEFAULT_MAX_PAYLOAD);
+        assert_eq!(parse_args(&args("--max-payload 64")).unwrap().max_payload, 64);
+    }
+
+    #[test]
+    fn rejects_bad_flags() {
+        assert!(parse_args(&args("--bind localhost")).is_err());
+        assert!(parse_args(&args("--data-dir")).is_err());
+        assert!(parse_args(&args("--fsync sometimes")).is_err());
+        assert!(parse_args(&args("--fsync every:0")).is_err());
+        assert!(parse_args(&args("--segment-bytes 0")).is_err());
+        assert!(parse_args(&args("--max-payload 1MB")).is_err());
+        assert!(parse_args(&args("--verbose true")).is_err());
+    }
+
+}
@@ -0,0 +1,20 @@
+use crate::{broker::MessageStore, connection::Connection, response};
+
+#[derive(Debug)]
+pub struct Commit {
+    pub subject: String,
+    pub consumer: String,
+    // the last offset the consumer has processed
+    pub offset: u64,
+}
+
+impl Commit {
+    pub async fn apply(self, store: &MessageStore, conn: &mut Connection) -> crate::Result<()> {
+        let topic = store
+            .commit_async(self.subject, self.consumer.clone(), self.offset)
+            .await?;
+        let offset = self.offset.to_string();
+        conn.write(response::ok(&["COMMIT", &topic.0, &self.consumer, &offset])).await?;
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
-use bytes::Bytes;
-
-use crate::{broker::MessageStore, connection::Connection};
+use crate::error::MessageStoreError;
+use crate::topic::Topic;
+use crate::{broker::MessageStore, connection::Connection, response};
 
 #[derive(Debug)]
 pub struct Delete {
@@ -9,9 +9,12 @@ pub struct Delete {
 
 impl Delete {
     pub async fn apply(self, store: &MessageStore, conn: &mut Connection) -> crate::Result<()> {
-        let topic = store.remove_topic(self.subject)?;
-        let res = Bytes::from(format!("ACK DEL {:?}", topic));
-        conn.write(res).await?;
+        // an inbox goes once its request is done with it
+        if Topic::new(&self.subject).is_reserved() {
+            return Err(Box::new(MessageStoreError::PermissionDenied(self.subject)));
+        }
+        let topic = store.remove_topic_async(self.subject).await?;
+        conn.write(response::ok(&["DEL", &topic.0])).await?;
         Ok(())
     }
 }
@@ -0,0 +1,17 @@
+use crate::connection::Connection;
+use crate::response;
+use crate::topic::Topic;
+
+pub struct List;
+
+impl List {
+    /// Replies with `topics`, those the connection is subscribed to
+    pub async fn apply(self, topics: Vec<Topic>, conn: &mut Connection) -> crate::Result<()> {
+        let mut words: Vec<&str> = topics.iter().map(|topic| topic.0.as_str()).collect();
+        words.sort_unstable();
+        words.insert(0, "SUBS");
+
+        conn.write(response::ok(&words)).await?;
+        Ok(())
+    }
+}
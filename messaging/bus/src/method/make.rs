@@ -1,17 +1,27 @@
-use crate::{broker::MessageStore, connection::Connection};
-use bytes::Bytes;
+use crate::error::MessageStoreError;
+use crate::topic::Topic;
+use crate::{broker::MessageStore, connection::Connection, response};
 
 #[derive(Debug)]
 pub struct Make {
     pub subject: String,
+    // whether the topic's messages are kept in a log on disk
+    pub durable: bool,
 }
 
 impl Make {
     pub async fn apply(self, store: &MessageStore, conn: &mut Connection) -> crate::Result<()> {
-        let topic = store.add_topic(self.subject)?;
+        // inboxes are only made by REQ
+        if Topic::new(&self.subject).is_reserved() {
+            return Err(Box::new(MessageStoreError::PermissionDenied(self.subject)));
+        }
+        let topic = if self.durable {
+            store.add_durable_topic(self.subject)?
+        } else {
+            store.add_topic(self.subject)?
+        };
 
-        let res = Bytes::from(format!("ACK MAKE {:?}", topic));
-        conn.write(res).await?;
+        conn.write(response::ok(&["MAKE", &topic.0])).await?;
         Ok(())
     }
 }
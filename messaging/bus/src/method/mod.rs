@@ -7,26 +7,78 @@ pub use del::Delete;
 mod publish;
 pub use publish::Publish;
 
+mod request;
+pub use request::Request;
+
 mod sub;
-pub use sub::Subscribe;
+pub use sub::{Subscribe, Subscriptions};
+
+mod unsub;
+pub use unsub::Unsubscribe;
+
+mod list;
+pub use list::List;
+
+mod commit;
+pub use commit::Commit;
+
+use tokio::time::Duration;
 
-use crate::{broker::MessageStore, connection::Connection, protocol::MethodFrames};
+use crate::broker::MessageStore;
+use crate::connection::{Connection, Shutdown};
+use crate::protocol::{MethodFrames, Start};
 
-// TODO: unsubscribe
 pub enum Method {
     Make(Make),
     Delete(Delete),
     Publish(Publish),
+    Request(Request),
     Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    List(List),
+    Commit(Commit),
 }
 
 impl Method {
     pub fn from_frames(frames: MethodFrames) -> Self {
         match frames {
             MethodFrames::Delete(subject) => Method::Delete(Delete { subject }),
-            MethodFrames::Make(subject) => Method::Make(Make { subject }),
-            MethodFrames::Publish(subject, bytes) => Method::Publish(Publish { subject, bytes }),
-            MethodFrames::Subscribe(subject) => Method::Subscribe(Subscribe { subject }),
+            MethodFrames::Make(subject, durable) => Method::Make(Make { subject, durable }),
+            MethodFrames::Publish(subject, reply, key, headers, bytes) => {
+                Method::Publish(Publish {
+                    subject,
+                    reply,
+                    key,
+                    headers,
+                    bytes,
+                })
+            }
+            MethodFrames::Request(subject, timeout, bytes) => Method::Request(Request {
+                subject,
+                timeout: timeout.map(Duration::from_millis),
+                bytes,
+            }),
+            MethodFrames::Subscribe(subject, start, consumer) => Method::Subscribe(Subscribe {
+                subject,
+                start,
+                consumer,
+                group: None,
+            }),
+            MethodFrames::SubscribeGroup(subject, group, assignment) => {
+                Method::Subscribe(Subscribe {
+                    subject,
+                    start: Start::Latest,
+                    consumer: None,
+                    group: Some((group, assignment)),
+                })
+            }
+            MethodFrames::Unsubscribe(subject) => Method::Unsubscribe(Unsubscribe { subject }),
+            MethodFrames::List => Method::List(List),
+            MethodFrames::Commit(subject, consumer, offset) => Method::Commit(Commit {
+                subject,
+                consumer,
+                offset,
+            }),
         }
     }
 
@@ -34,13 +86,18 @@ impl Method {
         self,
         store: &MessageStore,
         conn: &mut Connection,
-        // TODO: shutdown channel
+        shutdown: &mut Shutdown,
     ) -> crate::Result<()> {
         match self {
             Method::Make(m) => m.apply(store, conn).await?,
             Method::Delete(m) => m.apply(store, conn).await?,
             Method::Publish(m) => m.apply(store, conn).await?,
-            Method::Subscribe(m) => m.apply(store, conn).await?,
+            Method::Request(m) => m.apply(store, conn).await?,
+            Method::Subscribe(m) => m.apply(store, conn, shutdown).await?,
+            // outside of SUB mode there's nothing subscribed to
+            Method::Unsubscribe(m) => m.apply(&mut Subscriptions::new(), conn).await?,
+            Method::List(m) => m.apply(vec![], conn).await?,
+            Method::Commit(m) => m.apply(store, conn).await?,
         }
         Ok(())
     }
@@ -50,7 +107,11 @@ impl Method {
             Method::Make(_) => "MAKE",
             Method::Delete(_) => "DEL",
             Method::Publish(_) => "PUB",
+            Method::Request(_) => "REQ",
             Method::Subscribe(_) => "SUB",
+            Method::Unsubscribe(_) => "UNSUB",
+            Method::List(_) => "SUBS",
+            Method::Commit(_) => "COMMIT",
         }
     }
 }
@@ -1,18 +1,28 @@
 use bytes::Bytes;
 
-use crate::{broker::MessageStore, connection::Connection, protocol::Message};
+use crate::protocol::{Headers, Message};
+use crate::{broker::MessageStore, connection::Connection, response};
 
 pub struct Publish {
     pub subject: String,
+    // where replies should be published
+    pub reply: Option<String>,
+    // which consumer group member gets it, for groups assigning by hash
+    pub key: Option<String>,
+    pub headers: Headers,
     pub bytes: Bytes,
 }
 
 impl Publish {
     pub async fn apply(self, store: &MessageStore, conn: &mut Connection) -> crate::Result<()> {
-        let msg = Message::new(self.bytes);
-        let topic = store.publish(self.subject, msg)?;
-        let res = Bytes::from(format!("ACK PUB {:?}", topic));
-        conn.write(res).await?;
+        let msg = Message {
+            key: self.key,
+            reply: self.reply,
+            headers: self.headers,
+            ..Message::new(self.bytes)
+        };
+        let (topic, offset) = store.publish_async(self.subject, msg).await?;
+        conn.write(response::ok(&["PUB", &topic.0, &offset.to_string()])).await?;
         Ok(())
     }
 }
@@ -0,0 +1,49 @@
+use bytes::Bytes;
+use tokio::time::{self, Duration};
+
+use crate::response::{self, ErrorCode};
+use crate::{broker::MessageStore, connection::Connection, protocol::Message};
+
+// how long to wait for a reply, if the request doesn't say
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Request {
+    pub subject: String,
+    pub timeout: Option<Duration>,
+    pub bytes: Bytes,
+}
+
+impl Request {
+    /// Publishes the request with a new inbox to reply to, and replies
+    /// with the first message published to it, if one is in time
+    pub async fn apply(self, store: &MessageStore, conn: &mut Connection) -> crate::Result<()> {
+        let subject = self.subject.clone();
+        let inbox = store.add_inbox()?;
+        let res = self.send(store, &inbox.0).await;
+        // no one can reply to it any more, so it goes either way
+        store.remove_inbox(&inbox);
+
+        let res = match res? {
+            Some(reply) => reply.to_frame(),
+            None => response::error(ErrorCode::Timeout, format!("no reply to {}", subject)),
+        };
+        conn.write(res).await?;
+        Ok(())
+    }
+
+    async fn send(self, store: &MessageStore, inbox: &str) -> crate::Result<Option<Message>> {
+        // subscribed before publishing, so the reply can't be missed
+        let mut rx = store.subscribe(inbox)?;
+        let msg = Message {
+            reply: Some(inbox.to_string()),
+            ..Message::new(self.bytes)
+        };
+        store.publish_async(self.subject, msg).await?;
+
+        match time::timeout(self.timeout.unwrap_or(DEFAULT_TIMEOUT), rx.recv()).await {
+            Ok(Ok(reply)) => Ok(Some(reply)),
+            // a closed inbox can't get a reply either
+            Ok(Err(_)) | Err(_) => Ok(None),
+        }
+    }
+}
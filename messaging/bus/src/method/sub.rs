@@ -1,31 +1,76 @@
 use std::pin::Pin;
 
-use bytes::Bytes;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::{Stream, StreamExt, StreamMap};
+use tracing::error;
 
-use crate::broker::MessageStore;
-use crate::connection::Connection;
+use crate::broker::{MessageStore, Replay};
+use crate::connection::{Connection, Shutdown};
+use crate::error::MessageStoreError;
 use crate::method::Method;
-use crate::protocol::Message;
+use crate::protocol::{Assignment, Message, Start};
+use crate::response;
 use crate::topic::Topic;
 
 type MessageStream = Pin<Box<dyn Stream<Item = Message> + Send>>;
 
+/// A connection's subscriptions, by topic
+pub type Subscriptions = StreamMap<Topic, MessageStream>;
+
 pub struct Subscribe {
     pub subject: String,
+    // where to start in a durable topic's log, rather
+    // than only getting messages published from now on
+    pub start: Start,
+    // resumes from the last offset it committed, if any
+    pub consumer: Option<String>,
+    // shares the topic's messages with the rest of the group, instead
+    pub group: Option<(String, Assignment)>,
 }
 
 fn add_subscription(
-    subs: &mut StreamMap<Topic, MessageStream>,
+    subs: &mut Subscriptions,
     topic: Topic,
+    replay: Option<Replay>,
     mut rx: broadcast::Receiver<Message>,
 ) {
+    let subject = topic.0.clone();
     let rx = Box::pin(async_stream::stream! {
+        let mut replay = replay;
         loop {
+            if let Some(replay) = replay.as_mut() {
+                loop {
+                    match replay.next_batch().await {
+                        Ok(batch) if batch.is_empty() => break,
+                        Ok(batch) => {
+                            for msg in batch {
+                                yield Message {
+                                    subject: subject.clone(),
+                                    ..msg
+                                };
+                            }
+                        }
+                        // stop, rather than carry on with live messages
+                        // and leave a gap the subscriber can't see
+                        Err(e) => {
+                            error!(cause = %e, "failed to replay log");
+                            return;
+                        }
+                    }
+                }
+            }
             match rx.recv().await {
-                Ok(msg) => yield msg,
-                // If we lagged in consuming messages, just resume.
+                Ok(msg) => match replay.as_mut() {
+                    // it fills in whatever was missed by lagging before handing it over
+                    Some(replay) => {
+                        if let Some(msg) = replay.live(msg) {
+                            yield msg;
+                        }
+                    }
+                    None => yield msg,
+                },
+                // If we lagged in consuming messages, just resume. A
+                // durable topic's replay reads what was missed from its log.
                 Err(broadcast::error::RecvError::Lagged(_)) => {}
                 Err(_) => break,
             }
@@ -34,42 +79,151 @@ fn add_subscription(
     subs.insert(topic, rx);
 }
 
+// only gets the messages the group assigns to this member
+fn add_group_subscription(
+    subs: &mut Subscriptions,
+    topic: Topic,
+    mut rx: mpsc::Receiver<Message>,
+) {
+    let rx = Box::pin(async_stream::stream! {
+        while let Some(msg) = rx.recv().await {
+            yield msg;
+        }
+    });
+    subs.insert(topic, rx);
+}
+
 impl Subscribe {
-    pub async fn apply(self, store: &MessageStore, conn: &mut Connection) -> crate::Result<()> {
-        let rx = store.subscribe(self.subject.clone())?;
+    async fn add_to(
+        self,
+        store: &MessageStore,
+        subs: &mut Subscriptions,
+        conn: &mut Connection,
+    ) -> crate::Result<()> {
         let topic = Topic::new(self.subject.clone());
+        // only the connection that made an inbox gets its replies
+        if topic.is_reserved() {
+            return Err(Box::new(MessageStoreError::PermissionDenied(self.subject)));
+        }
+        match (self.group, self.start, self.consumer) {
+            (Some((group, assignment)), _, _) => {
+                let rx = store.join_group(self.subject, &group, assignment)?;
+                add_group_subscription(subs, topic.clone(), rx);
+            }
+            (None, Start::Latest, None) => {
+                let rx = store.subscribe(self.subject)?;
+                add_subscription(subs, topic.clone(), None, rx);
+            }
+            (None, start, consumer) => {
+                let (replay, rx) =
+                    store.subscribe_from(self.subject, start, consumer.as_deref())?;
+                add_subscription(subs, topic.clone(), Some(replay), rx);
+            }
+        }
+        conn.write(response::ok(&["SUB", &topic.0])).await?;
+        Ok(())
+    }
 
-        let mut subs = StreamMap::new();
-        add_subscription(&mut subs, topic, rx);
+    pub async fn apply(
+        self,
+        store: &MessageStore,
+        conn: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        let mut subs = Subscriptions::new();
+        self.add_to(store, &mut subs, conn).await?;
 
         loop {
             // 4 possible events:
             // -- receive a new message      (DONE)
             // -- subscribe to a new channel (DONE)
-            // -- unsubscribe from a channel
-            // -- get a shutdown signal
+            // -- unsubscribe from a channel (DONE)
+            // -- get a shutdown signal      (DONE)
             tokio::select! {
+                // the topic subscribed to may be a wildcard, so use the message's
                 Some((_, msg)) = subs.next() => {
-                    conn.write(msg.1).await?;
+                    conn.write(msg.to_frame()).await?;
                 }
                 res = conn.read() => {
-                    let frames = match res? {
-                        Some(frame) => frame,
-                        None => return Ok(())
-                    };
-                    // parse into cmd + apply
-                    match Method::from_frames(frames) {
-                        Method::Subscribe(sub) => {
-                            let sub_rx = store.subscribe(sub.subject.clone())?;
-                            let topic = Topic::new(sub.subject.clone());
-                            add_subscription(&mut subs, topic, sub_rx);
+                    let res = match res {
+                        Ok(Some(frames)) => match Method::from_frames(frames) {
+                            Method::Subscribe(sub) => sub.add_to(store, &mut subs, conn).await,
+                            Method::Unsubscribe(unsub) => unsub.apply(&mut subs, conn).await,
+                            Method::List(list) => {
+                                list.apply(subs.keys().cloned().collect(), conn).await
+                            }
+                            // anything else works as it would outside of SUB mode
+                            Method::Make(m) => m.apply(store, conn).await,
+                            Method::Delete(m) => m.apply(store, conn).await,
+                            Method::Publish(m) => m.apply(store, conn).await,
+                            Method::Request(m) => m.apply(store, conn).await,
+                            Method::Commit(m) => m.apply(store, conn).await,
                         },
-                        _ => {
-                            conn.write(Bytes::from("unsupported cmd in SUB mode")).await?;
+                        Ok(None) => return Ok(()),
+                        Err(e) => Err(e),
+                    };
+                    // keep the subscriptions going after a method fails,
+                    // unless the connection can't carry on at all
+                    match res {
+                        Err(e) if response::is_recoverable(&e) => {
+                            conn.write(response::from_error(&e)).await?
                         }
+                        res => res?,
                     }
                 }
+                _ = shutdown.recv() => return Ok(()),
             };
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio_stream::StreamExt;
+
+    use super::{add_subscription, Subscriptions};
+    use crate::broker::{MessageStoreDropGuard, CHAN_CAPACITY};
+    use crate::log::LogConfig;
+    use crate::protocol::{Message, Start};
+    use crate::topic::Topic;
+
+    #[tokio::test]
+    async fn test_lagging_replay_reads_what_it_missed_from_the_log() {
+        let dir = std::env::temp_dir().join(format!("bus-sub-{}-lag", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let guard = MessageStoreDropGuard::open(&dir, LogConfig::default()).unwrap();
+        let store = guard.store();
+        store.add_durable_topic("events").unwrap();
+        let publish = |n: usize| {
+            for _ in 0..n {
+                store
+                    .publish("events".to_string(), Message::new(Bytes::from("hi")))
+                    .unwrap();
+            }
+        };
+        publish(10);
+
+        let mut subs = Subscriptions::new();
+        let (replay, rx) = store.subscribe_from("events", Start::Earliest, None).unwrap();
+        add_subscription(&mut subs, Topic::new("events"), Some(replay), rx);
+        // part way through the replay, more is published than the receiver holds
+        let first = subs.next().await.unwrap().1;
+        publish(CHAN_CAPACITY + 500);
+
+        let total = 10 + CHAN_CAPACITY + 500;
+        let mut offsets = vec![first.offset];
+        while offsets.len() < total {
+            let next = tokio::time::timeout(Duration::from_secs(5), subs.next()).await;
+            offsets.push(next.unwrap().unwrap().1.offset);
+        }
+        assert_eq!(offsets, (0..total as u64).collect::<Vec<_>>());
+
+        drop((subs, guard, store));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
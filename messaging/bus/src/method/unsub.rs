@@ -0,0 +1,30 @@
+use crate::connection::Connection;
+use crate::method::Subscriptions;
+use crate::response;
+use crate::topic::Topic;
+
+pub struct Unsubscribe {
+    // every subscription, without one
+    pub subject: Option<String>,
+}
+
+impl Unsubscribe {
+    pub async fn apply(self, subs: &mut Subscriptions, conn: &mut Connection) -> crate::Result<()> {
+        let topics: Vec<Topic> = match self.subject {
+            Some(subject) => vec![Topic::new(subject)],
+            None => subs.keys().cloned().collect(),
+        };
+        // dropping the stream drops its receiver, which
+        // also takes it out of any consumer group
+        let mut removed: Vec<&str> = topics
+            .iter()
+            .filter(|topic| subs.remove(topic).is_some())
+            .map(|topic| topic.0.as_str())
+            .collect();
+        removed.sort_unstable();
+        removed.insert(0, "UNSUB");
+
+        conn.write(response::ok(&removed)).await?;
+        Ok(())
+    }
+}
@@ -2,23 +2,170 @@ use std::io::Cursor;
 use std::str;
 
 use bytes::{Bytes, BytesMut};
+use uuid::Uuid;
 
 use crate::error::ParsingError;
 
+/// The most payload a frame can have, unless the broker's told otherwise
+pub const DEFAULT_MAX_PAYLOAD: usize = 1024 * 1024;
+
+/// A message's headers, as name/value pairs in the order they were sent
+pub type Headers = Vec<(String, String)>;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Message(usize, pub Bytes);
+pub struct Message {
+    // set by the broker when it's published: a unique id,
+    // and milliseconds since the epoch
+    pub id: Uuid,
+    pub timestamp: u64,
+    // the topic it was published to, and its position
+    // in it, both set by the broker
+    pub subject: String,
+    pub offset: u64,
+    // which consumer group member gets it, when they're assigned by hash
+    pub key: Option<String>,
+    // where the publisher wants replies to go
+    pub reply: Option<String>,
+    // the publisher's own, which subscribers get as they were sent
+    pub headers: Headers,
+    pub bytes: Bytes,
+}
+
+/// Where a subscription starts. Anything but `Latest` replays
+/// the log, so needs a durable topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Start {
+    Earliest,
+    /// Only messages published from now on
+    Latest,
+    Offset(u64),
+    /// The first message published at or after these milliseconds since the epoch
+    Timestamp(u64),
+}
+
+/// How a consumer group shares out messages between its members
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment {
+    RoundRobin,
+    /// Messages with the same key go to the same member, while the group doesn't change
+    Hash,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MethodFrames {
-    Make(String),           // MAKE subject\r\n
-    Delete(String),         // DEL subject\r\n
-    Publish(String, Bytes), // PUB subject \r\n<payload>\r\n
-    Subscribe(String),      // SUB subject\r\n
+    Make(String, bool),                            // MAKE subject [DURABLE]\r\n
+    Delete(String),                                // DEL subject\r\n
+    // PUB subject [reply-subject] [KEY key] [#bytes]\r\n<payload>\r\n, where the
+    // length is written with its #, e.g. #11, or
+    // HPUB subject [reply-subject] [KEY key] header-bytes total-bytes\r\n<headers><payload>\r\n
+    Publish(String, Option<String>, Option<String>, Headers, Bytes),
+    // REQ subject [timeout-ms] [#bytes]\r\n<payload>\r\n
+    Request(String, Option<u64>, Bytes),
+    Subscribe(String, Start, Option<String>),      // SUB subject [start [consumer]]\r\n
+    SubscribeGroup(String, String, Assignment),    // SUB subject GROUP group [assignment]\r\n
+    Unsubscribe(Option<String>),                   // UNSUB [subject]\r\n
+    List,                                          // SUBS\r\n
+    Commit(String, String, u64),                   // COMMIT subject consumer offset\r\n
 }
 
 impl Message {
-    pub fn new(buf: Bytes) -> Self {
-        Message(buf.len(), buf)
+    pub fn new(bytes: Bytes) -> Self {
+        Message {
+            id: Uuid::nil(),
+            timestamp: 0,
+            subject: String::new(),
+            offset: 0,
+            key: None,
+            reply: None,
+            headers: vec![],
+            bytes,
+        }
+    }
+
+    /// How the message is delivered to subscribers, i.e.
+    /// `MSG subject offset id timestamp [reply-subject] bytes\r\n<payload>\r\n`,
+    /// or with headers, like HPUB,
+    /// `HMSG subject offset id timestamp [reply-subject] header-bytes total-bytes\r\n...`
+    pub fn to_frame(&self) -> Bytes {
+        let mut headers = BytesMut::new();
+        let mut line = if self.headers.is_empty() {
+            format!("MSG {} {} {} {}", self.subject, self.offset, self.id, self.timestamp)
+        } else {
+            write_headers(&self.headers, &mut headers);
+            format!("HMSG {} {} {} {}", self.subject, self.offset, self.id, self.timestamp)
+        };
+        if let Some(reply) = &self.reply {
+            line.push_str(&format!(" {}", reply));
+        }
+        if !headers.is_empty() {
+            line.push_str(&format!(" {}", headers.len()));
+        }
+        line.push_str(&format!(" {}\r\n", headers.len() + self.bytes.len()));
+
+        let mut buf = BytesMut::from(line.as_bytes());
+        buf.extend_from_slice(&headers);
+        buf.extend_from_slice(&self.bytes);
+        buf.extend_from_slice(b"\r\n");
+        buf.freeze()
+    }
+}
+
+// header names can't have spaces or colons, and
+// neither can have anything that'd end the line
+fn valid_header(name: &str, value: &str) -> bool {
+    !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_graphic() && b != b':')
+        && !value.contains(['\r', '\n'])
+}
+
+/// Headers as `Name: value\r\n` lines, ended by an empty one
+pub fn write_headers(headers: &[(String, String)], buf: &mut BytesMut) {
+    for (name, value) in headers {
+        buf.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    buf.extend_from_slice(b"\r\n");
+}
+
+/// The headers `write_headers` wrote, which have to be all of `block`
+pub fn parse_headers(block: &[u8]) -> Result<Headers, ParsingError> {
+    let block = str::from_utf8(block).map_err(|_| ParsingError::Invalid)?;
+    let lines = block.strip_suffix("\r\n").ok_or(ParsingError::Invalid)?;
+    if !lines.is_empty() && !lines.ends_with("\r\n") {
+        return Err(ParsingError::Invalid);
+    }
+    let mut headers = vec![];
+    for line in lines.split_terminator("\r\n") {
+        let (name, value) = line.split_once(':').ok_or(ParsingError::Invalid)?;
+        let value = value.trim_start_matches(' ');
+        if !valid_header(name, value) {
+            return Err(ParsingError::Invalid);
+        }
+        headers.push((name.to_string(), value.to_string()));
+    }
+    Ok(headers)
+}
+
+impl Start {
+    // EARLIEST, LATEST, an offset, or @ and a timestamp in milliseconds
+    fn parse(s: &str) -> Result<Start, ParsingError> {
+        match s {
+            "EARLIEST" => Ok(Start::Earliest),
+            "LATEST" => Ok(Start::Latest),
+            _ => match s.strip_prefix('@') {
+                Some(ms) => ms.parse().map(Start::Timestamp).map_err(|_| ParsingError::Invalid),
+                None => s.parse().map(Start::Offset).map_err(|_| ParsingError::Invalid),
+            },
+        }
+    }
+}
+
+impl Assignment {
+    fn parse(s: &str) -> Result<Assignment, ParsingError> {
+        match s {
+            "ROUNDROBIN" => Ok(Assignment::RoundRobin),
+            "HASH" => Ok(Assignment::Hash),
+            _ => Err(ParsingError::Invalid),
+        }
     }
 }
 
@@ -27,7 +174,7 @@ pub struct Parser;
 // used for the method + subject name
 fn get_string<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a str, ParsingError> {
     let start = src.position() as usize;
-    let end = src.get_ref().len() - 1;
+    let end = src.get_ref().len().saturating_sub(1);
 
     for i in start..end {
         // hit whitespace
@@ -36,7 +183,7 @@ fn get_string<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a str, ParsingError> {
             if let Ok(s) = str::from_utf8(&src.get_ref()[start..i]) {
                 return Ok(s);
             } else {
-                return Err(ParsingError);
+                return Err(ParsingError::Invalid);
             }
         }
 
@@ -46,58 +193,216 @@ fn get_string<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a str, ParsingError> {
             if let Ok(s) = str::from_utf8(&src.get_ref()[start..i]) {
                 return Ok(s);
             } else {
-                return Err(ParsingError);
+                return Err(ParsingError::Invalid);
             }
         }
     }
 
-    Err(ParsingError)
+    Err(ParsingError::Incomplete)
+}
+
+// an optional last argument, if the line didn't end with the word before
+fn get_optional<'a>(src: &mut Cursor<&'a [u8]>) -> Result<Option<&'a str>, ParsingError> {
+    let pos = src.position() as usize;
+    if pos >= 2 && &src.get_ref()[pos - 2..pos] == b"\r\n" {
+        return Ok(None);
+    }
+    get_string(src).map(Some)
+}
+
+// the rest of the words on the line
+fn get_args<'a>(src: &mut Cursor<&'a [u8]>) -> Result<Vec<&'a str>, ParsingError> {
+    let mut args = vec![];
+    while let Some(arg) = get_optional(src)? {
+        if arg.is_empty() {
+            return Err(ParsingError::Invalid);
+        }
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn get_number<T: str::FromStr>(s: &str) -> Result<T, ParsingError> {
+    s.parse().map_err(|_| ParsingError::Invalid)
 }
 
-// used for the payload
-fn get_bulk<'a>(src: &mut Cursor<&'a [u8]>) -> Result<Bytes, ParsingError> {
+// used for the payload, when it runs to the end of the line
+fn get_bulk(src: &mut Cursor<&[u8]>, max_payload: usize) -> Result<Bytes, ParsingError> {
     let start = src.position() as usize;
-    let end = src.get_ref().len() - 1;
+    let end = src.get_ref().len().saturating_sub(1);
     for i in start..end {
         if src.get_ref()[i] == b'\r' && src.get_ref()[i + 1] == b'\n' {
+            if i - start > max_payload {
+                return Err(ParsingError::TooLarge);
+            }
             src.set_position((i + 2) as u64);
             let b = BytesMut::from(&src.get_ref()[start..i]);
             return Ok(b.freeze());
         }
     }
-    Err(ParsingError)
+    if end.saturating_sub(start) > max_payload {
+        return Err(ParsingError::TooLarge);
+    }
+    Err(ParsingError::Incomplete)
 }
 
-impl Parser {
-    pub fn check(buf: &mut Cursor<&[u8]>) -> Result<(), ParsingError> {
-        let method = get_string(buf)?;
-        let _ = get_string(buf)?;
+// used for a payload that said how long it is, which can then be
+// anything, \r\n included. Too long is rejected before it's all arrived.
+fn get_sized(
+    src: &mut Cursor<&[u8]>,
+    len: usize,
+    max_payload: usize,
+) -> Result<Bytes, ParsingError> {
+    if len > max_payload {
+        return Err(ParsingError::TooLarge);
+    }
+    let start = src.position() as usize;
+    let end = start + len;
+    if src.get_ref().len() < end + 2 {
+        return Err(ParsingError::Incomplete);
+    }
+    if &src.get_ref()[end..end + 2] != b"\r\n" {
+        return Err(ParsingError::Invalid);
+    }
+    src.set_position((end + 2) as u64);
+    Ok(Bytes::copy_from_slice(&src.get_ref()[start..end]))
+}
 
-        match method {
-            "PUB" => {
-                let _ = get_bulk(buf)?;
-                Ok(())
-            }
-            "SUB" => Ok(()),
-            "MAKE" => Ok(()),
-            "DEL" => Ok(()),
-            _ => Err(ParsingError),
-        }
+// a payload length is marked with a #, so a reply subject or key
+// that happens to be a number isn't taken for one
+fn get_len(arg: &str) -> Option<Result<usize, ParsingError>> {
+    arg.strip_prefix('#').map(get_number)
+}
+
+// a trailing #bytes is the length of the payload
+fn split_len<'a, 'b>(
+    args: &'b [&'a str],
+) -> Result<(&'b [&'a str], Option<usize>), ParsingError> {
+    match args.split_last() {
+        Some((len, rest)) => match get_len(len) {
+            Some(len) => Ok((rest, Some(len?))),
+            None => Ok((args, None)),
+        },
+        None => Ok((args, None)),
+    }
+}
+
+// what PUB and HPUB have between the subject and any lengths, neither
+// of which can start with a #, so can't be mistaken for a length
+fn get_reply_and_key(args: &[&str]) -> Result<(Option<String>, Option<String>), ParsingError> {
+    if args.iter().any(|arg| arg.starts_with('#')) {
+        return Err(ParsingError::Invalid);
+    }
+    let s = |arg: &str| arg.to_string();
+    match args {
+        [] => Ok((None, None)),
+        ["KEY", key] => Ok((None, Some(s(key)))),
+        [reply] => Ok((Some(s(reply)), None)),
+        [reply, "KEY", key] => Ok((Some(s(reply)), Some(s(key)))),
+        _ => Err(ParsingError::Invalid),
+    }
+}
+
+fn get_payload(
+    src: &mut Cursor<&[u8]>,
+    len: Option<usize>,
+    max_payload: usize,
+) -> Result<Bytes, ParsingError> {
+    match len {
+        Some(len) => get_sized(src, len, max_payload),
+        None => get_bulk(src, max_payload),
+    }
+}
+
+impl Parser {
+    /// Checks there's a whole, valid frame at the start of `buf`, with
+    /// no more than `max_payload` bytes of payload
+    pub fn check(buf: &mut Cursor<&[u8]>, max_payload: usize) -> Result<(), ParsingError> {
+        Parser::parse_frame(buf, max_payload).map(|_| ())
     }
 
     pub fn parse(buf: &mut Cursor<&[u8]>) -> Result<MethodFrames, ParsingError> {
+        Parser::parse_frame(buf, usize::MAX)
+    }
+
+    fn parse_frame(
+        buf: &mut Cursor<&[u8]>,
+        max_payload: usize,
+    ) -> Result<MethodFrames, ParsingError> {
         let method = get_string(buf)?;
-        let subject = get_string(buf)?.to_string();
+        let res = Parser::parse_method(method, buf, max_payload);
+        // there's no telling where a bad one's payload ends, so
+        // the rest of the frame can't be skipped like a bad line
+        match (method, res) {
+            ("PUB" | "HPUB" | "REQ", Err(ParsingError::Invalid)) => Err(ParsingError::Unframed),
+            (_, res) => res,
+        }
+    }
+
+    fn parse_method(
+        method: &str,
+        buf: &mut Cursor<&[u8]>,
+        max_payload: usize,
+    ) -> Result<MethodFrames, ParsingError> {
+        let args = get_args(buf)?;
+        let s = |arg: &str| arg.to_string();
 
-        match method {
-            "PUB" => {
-                let bytes = get_bulk(buf)?;
-                Ok(MethodFrames::Publish(subject, bytes))
+        match (method, &args[..]) {
+            ("PUB", [subject, rest @ ..]) => {
+                let (rest, len) = split_len(rest)?;
+                let (reply, key) = get_reply_and_key(rest)?;
+                let bytes = get_payload(buf, len, max_payload)?;
+                Ok(MethodFrames::Publish(s(subject), reply, key, vec![], bytes))
+            }
+            // the headers count towards the max payload too
+            ("HPUB", [subject, rest @ .., header_len, len]) => {
+                let (reply, key) = get_reply_and_key(rest)?;
+                let header_len: usize = get_number(header_len)?;
+                let bytes = get_sized(buf, get_number(len)?, max_payload)?;
+                if header_len > bytes.len() {
+                    return Err(ParsingError::Invalid);
+                }
+                let headers = parse_headers(&bytes[..header_len])?;
+                let bytes = bytes.slice(header_len..);
+                Ok(MethodFrames::Publish(s(subject), reply, key, headers, bytes))
+            }
+            ("REQ", [subject, rest @ ..]) => {
+                let (rest, len) = split_len(rest)?;
+                let timeout = match rest {
+                    [] => None,
+                    [timeout] => Some(get_number(timeout)?),
+                    _ => return Err(ParsingError::Invalid),
+                };
+                let bytes = get_payload(buf, len, max_payload)?;
+                Ok(MethodFrames::Request(s(subject), timeout, bytes))
+            }
+            ("SUB", [subject, "GROUP", group, rest @ ..]) => {
+                let assignment = match rest {
+                    [] => Assignment::RoundRobin,
+                    [assignment] => Assignment::parse(assignment)?,
+                    _ => return Err(ParsingError::Invalid),
+                };
+                Ok(MethodFrames::SubscribeGroup(s(subject), s(group), assignment))
+            }
+            ("SUB", [subject]) => Ok(MethodFrames::Subscribe(s(subject), Start::Latest, None)),
+            ("SUB", [subject, start]) => {
+                Ok(MethodFrames::Subscribe(s(subject), Start::parse(start)?, None))
             }
-            "SUB" => Ok(MethodFrames::Subscribe(subject)),
-            "MAKE" => Ok(MethodFrames::Make(subject)),
-            "DEL" => Ok(MethodFrames::Delete(subject)),
-            _ => Err(ParsingError),
+            ("SUB", [subject, start, consumer]) => Ok(MethodFrames::Subscribe(
+                s(subject),
+                Start::parse(start)?,
+                Some(s(consumer)),
+            )),
+            ("UNSUB", []) => Ok(MethodFrames::Unsubscribe(None)),
+            ("UNSUB", [subject]) => Ok(MethodFrames::Unsubscribe(Some(s(subject)))),
+            ("SUBS", []) => Ok(MethodFrames::List),
+            ("COMMIT", [subject, consumer, offset]) => {
+                Ok(MethodFrames::Commit(s(subject), s(consumer), get_number(offset)?))
+            }
+            ("MAKE", [subject]) => Ok(MethodFrames::Make(s(subject), false)),
+            ("MAKE", [subject, "DURABLE"]) => Ok(MethodFrames::Make(s(subject), true)),
+            ("DEL", [subject]) => Ok(MethodFrames::Delete(s(subject))),
+            _ => Err(ParsingError::Invalid),
         }
     }
 }
@@ -127,25 +432,169 @@ mod tests {
         let buf = BytesMut::from(&s[0..4]).freeze();
         let mut cursor = Cursor::new(&s[..]);
 
-        assert_eq!(buf, get_bulk(&mut cursor).unwrap());
+        assert_eq!(buf, get_bulk(&mut cursor, DEFAULT_MAX_PAYLOAD).unwrap());
+    }
+
+    #[test]
+    fn test_sized_pub_method_parsing_from_bytes() {
+        let pub_buf = b"PUB test_topic inbox #9\r\nbin\r\n\x00ary\r\n";
+        let mut pub_cursor = Cursor::new(&pub_buf[..]);
+        assert!(Parser::check(&mut pub_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+        assert_eq!(pub_cursor.position() as usize, pub_buf.len());
+
+        pub_cursor.set_position(0);
+        let expected = MethodFrames::Publish(
+            "test_topic".to_string(),
+            Some("inbox".to_string()),
+            None,
+            vec![],
+            Bytes::from(&b"bin\r\n\x00ary"[..]),
+        );
+        assert_eq!(Parser::parse(&mut pub_cursor).unwrap(), expected);
+
+        let keyed_buf = b"PUB t KEY user-42 #2\r\nhi\r\n";
+        let key = Some("user-42".to_string());
+        let expected =
+            MethodFrames::Publish("t".to_string(), None, key, vec![], Bytes::from("hi"));
+        assert_eq!(Parser::parse(&mut Cursor::new(&keyed_buf[..])).unwrap(), expected);
+
+        // the payload has to be as long as it said
+        let short_buf = b"PUB t #3\r\nhi\r\n\r\n";
+        assert!(matches!(
+            Parser::parse(&mut Cursor::new(&short_buf[..])),
+            Err(ParsingError::Unframed)
+        ));
+    }
+
+    #[test]
+    fn test_numeric_reply_and_key_parsing_from_bytes() {
+        // without a # a number is a reply subject or key, not a length
+        let cases = vec![
+            (&b"PUB t KEY 42\r\nresize avatar\r\n"[..], None, Some("42")),
+            (&b"PUB t 12345\r\nresize avatar\r\n"[..], Some("12345"), None),
+            (&b"PUB t 12345 KEY 42 #13\r\nresize avatar\r\n"[..], Some("12345"), Some("42")),
+        ];
+        for (buf, reply, key) in cases {
+            let mut cursor = Cursor::new(buf);
+            assert!(Parser::check(&mut cursor, DEFAULT_MAX_PAYLOAD).is_ok(), "{:?}", buf);
+            assert_eq!(cursor.position() as usize, buf.len());
+
+            cursor.set_position(0);
+            let expected = MethodFrames::Publish(
+                "t".to_string(),
+                reply.map(str::to_string),
+                key.map(str::to_string),
+                vec![],
+                Bytes::from("resize avatar"),
+            );
+            assert_eq!(Parser::parse(&mut cursor).unwrap(), expected);
+        }
+
+        // and nothing but the length can start with one
+        for buf in &[&b"PUB t #inbox\r\nhi\r\n"[..], &b"PUB t KEY #42 #2\r\nhi\r\n"[..]] {
+            assert!(Parser::parse(&mut Cursor::new(*buf)).is_err(), "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn test_incomplete_frames() {
+        let pub_buf = b"PUB test_topic #11\r\nhello world\r\n";
+        for len in 1..pub_buf.len() {
+            let mut cursor = Cursor::new(&pub_buf[..len]);
+            assert!(matches!(
+                Parser::check(&mut cursor, DEFAULT_MAX_PAYLOAD),
+                Err(ParsingError::Incomplete)
+            ));
+        }
+        assert!(Parser::check(&mut Cursor::new(&pub_buf[..]), DEFAULT_MAX_PAYLOAD).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_payloads() {
+        // rejected as soon as the length says so, before the payload arrives
+        let sized_buf = b"PUB test_topic #11\r\nhello";
+        assert!(matches!(
+            Parser::check(&mut Cursor::new(&sized_buf[..]), 10),
+            Err(ParsingError::TooLarge)
+        ));
+
+        let line_buf = b"PUB test_topic\r\nhello world\r\n";
+        assert!(matches!(
+            Parser::check(&mut Cursor::new(&line_buf[..]), 10),
+            Err(ParsingError::TooLarge)
+        ));
+        assert!(Parser::check(&mut Cursor::new(&line_buf[..]), 11).is_ok());
+    }
+
+    #[test]
+    fn test_trailing_args_are_rejected() {
+        let cases = vec![
+            &b"MAKE t DURABLE please\r\n"[..],
+            &b"DEL t now\r\n"[..],
+            &b"SUBS t\r\n"[..],
+            &b"SUB t EARLIEST billing again\r\n"[..],
+            &b"SUB t GROUP\r\n"[..],
+            &b"COMMIT t billing\r\n41\r\n"[..],
+            &b"PUB t a b\r\nhi\r\n"[..],
+            &b"PUB  t\r\nhi\r\n"[..],
+        ];
+        for buf in cases {
+            assert!(Parser::parse(&mut Cursor::new(buf)).is_err(), "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn test_bad_frames_with_payloads_are_unframed() {
+        let cases = vec![
+            &b"PUB t a b\r\nDEL orders\r\n"[..],
+            &b"HPUB t 2\r\n\r\n\r\n"[..],
+            &b"REQ t soon\r\nDEL orders\r\n"[..],
+        ];
+        for buf in cases {
+            assert!(
+                matches!(Parser::parse(&mut Cursor::new(buf)), Err(ParsingError::Unframed)),
+                "{:?}",
+                buf
+            );
+        }
+        // while a bad line without one is just invalid
+        assert!(matches!(
+            Parser::parse(&mut Cursor::new(&b"DEL t now\r\n"[..])),
+            Err(ParsingError::Invalid)
+        ));
     }
 
     #[test]
     fn test_make_method_parsing_from_bytes() {
         let make_buf = b"MAKE test_topic\r\n";
         let mut make_cursor = Cursor::new(&make_buf[..]);
-        assert!(Parser::check(&mut make_cursor).is_ok());
+        assert!(Parser::check(&mut make_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+
+        make_cursor.set_position(0);
+        let expected = MethodFrames::Make("test_topic".to_string(), false);
+        assert_eq!(Parser::parse(&mut make_cursor).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_durable_make_method_parsing_from_bytes() {
+        let make_buf = b"MAKE test_topic DURABLE\r\n";
+        let mut make_cursor = Cursor::new(&make_buf[..]);
+        assert!(Parser::check(&mut make_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+        assert_eq!(make_cursor.position() as usize, make_buf.len());
 
         make_cursor.set_position(0);
-        let expected = MethodFrames::Make("test_topic".to_string());
+        let expected = MethodFrames::Make("test_topic".to_string(), true);
         assert_eq!(Parser::parse(&mut make_cursor).unwrap(), expected);
+
+        let bad_buf = b"MAKE test_topic FOREVER\r\n";
+        assert!(Parser::parse(&mut Cursor::new(&bad_buf[..])).is_err());
     }
 
     #[test]
     fn test_del_method_parsing_from_bytes() {
         let del_buf = b"DEL test_topic\r\n";
         let mut del_cursor = Cursor::new(&del_buf[..]);
-        assert!(Parser::check(&mut del_cursor).is_ok());
+        assert!(Parser::check(&mut del_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
 
         del_cursor.set_position(0);
         let expected = MethodFrames::Delete("test_topic".to_string());
@@ -156,22 +605,243 @@ mod tests {
     fn test_pub_method_parsing_from_bytes() {
         let pub_buf = b"PUB test_topic\r\nmy test payload\r\n";
         let mut pub_cursor = Cursor::new(&pub_buf[..]);
-        assert!(Parser::check(&mut pub_cursor).is_ok());
+        assert!(Parser::check(&mut pub_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
 
         pub_cursor.set_position(0);
-        let expected =
-            MethodFrames::Publish("test_topic".to_string(), Bytes::from("my test payload"));
+        let expected = MethodFrames::Publish(
+            "test_topic".to_string(),
+            None,
+            None,
+            vec![],
+            Bytes::from("my test payload"),
+        );
+        assert_eq!(Parser::parse(&mut pub_cursor).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_keyed_pub_method_parsing_from_bytes() {
+        let pub_buf = b"PUB test_topic KEY user-42\r\nmy test payload\r\n";
+        let mut pub_cursor = Cursor::new(&pub_buf[..]);
+        assert!(Parser::check(&mut pub_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+        assert_eq!(pub_cursor.position() as usize, pub_buf.len());
+
+        pub_cursor.set_position(0);
+        let expected = MethodFrames::Publish(
+            "test_topic".to_string(),
+            None,
+            Some("user-42".to_string()),
+            vec![],
+            Bytes::from("my test payload"),
+        );
         assert_eq!(Parser::parse(&mut pub_cursor).unwrap(), expected);
+
+        let bad_buf = b"PUB test_topic inbox LOCK user-42\r\nmy test payload\r\n";
+        assert!(Parser::parse(&mut Cursor::new(&bad_buf[..])).is_err());
+    }
+
+    #[test]
+    fn test_pub_with_reply_parsing_from_bytes() {
+        let cases = vec![
+            (&b"PUB t inbox\r\nhi\r\n"[..], None),
+            (&b"PUB t inbox KEY user-42\r\nhi\r\n"[..], Some("user-42".to_string())),
+        ];
+        for (buf, key) in cases {
+            let mut cursor = Cursor::new(buf);
+            let reply = Some("inbox".to_string());
+            let expected =
+                MethodFrames::Publish("t".to_string(), reply, key, vec![], Bytes::from("hi"));
+            assert_eq!(Parser::parse(&mut cursor).unwrap(), expected);
+            assert_eq!(cursor.position() as usize, buf.len());
+        }
+    }
+
+    #[test]
+    fn test_hpub_method_parsing_from_bytes() {
+        let hpub_buf = b"HPUB t inbox KEY user-42 28 30\r\n\
+            Trace-Id: abc\r\nRetry:  2\r\n\r\nhi\r\n";
+        let mut hpub_cursor = Cursor::new(&hpub_buf[..]);
+        assert!(Parser::check(&mut hpub_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+        assert_eq!(hpub_cursor.position() as usize, hpub_buf.len());
+
+        hpub_cursor.set_position(0);
+        let headers = vec![
+            ("Trace-Id".to_string(), "abc".to_string()),
+            ("Retry".to_string(), "2".to_string()),
+        ];
+        let expected = MethodFrames::Publish(
+            "t".to_string(),
+            Some("inbox".to_string()),
+            Some("user-42".to_string()),
+            headers,
+            Bytes::from("hi"),
+        );
+        assert_eq!(Parser::parse(&mut hpub_cursor).unwrap(), expected);
+
+        // no headers is just the empty line, and the payload can be empty too
+        let empty_buf = b"HPUB t 2 2\r\n\r\n\r\n";
+        let expected = MethodFrames::Publish("t".to_string(), None, None, vec![], Bytes::new());
+        assert_eq!(Parser::parse(&mut Cursor::new(&empty_buf[..])).unwrap(), expected);
+
+        let cases = vec![
+            // more header bytes than there are bytes
+            &b"HPUB t 9 4\r\nA: b\r\n\r\n"[..],
+            // headers that don't end with an empty line
+            &b"HPUB t 6 8\r\nA: b\r\nhi\r\n"[..],
+            &b"HPUB t 10 10\r\nA b: c\r\n\r\n\r\n"[..],
+            &b"HPUB t 8 8\r\nnope\r\n\r\n\r\n"[..],
+            &b"HPUB t 2\r\n\r\n\r\n"[..],
+        ];
+        for buf in cases {
+            assert!(Parser::parse(&mut Cursor::new(buf)).is_err(), "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn test_req_method_parsing_from_bytes() {
+        let req_buf = b"REQ t 250\r\nping\r\nREQ t\r\nping\r\nREQ t 250 #6\r\npi\r\nng\r\n";
+        let mut req_cursor = Cursor::new(&req_buf[..]);
+        let expected = MethodFrames::Request("t".to_string(), Some(250), Bytes::from("ping"));
+        assert_eq!(Parser::parse(&mut req_cursor).unwrap(), expected);
+
+        let expected = MethodFrames::Request("t".to_string(), None, Bytes::from("ping"));
+        assert_eq!(Parser::parse(&mut req_cursor).unwrap(), expected);
+
+        let expected = MethodFrames::Request("t".to_string(), Some(250), Bytes::from("pi\r\nng"));
+        assert_eq!(Parser::parse(&mut req_cursor).unwrap(), expected);
+
+        let sized_buf = b"REQ t #4\r\nping\r\n";
+        let expected = MethodFrames::Request("t".to_string(), None, Bytes::from("ping"));
+        assert_eq!(Parser::parse(&mut Cursor::new(&sized_buf[..])).unwrap(), expected);
+
+        let bad_buf = b"REQ t soon\r\nping\r\n";
+        assert!(Parser::parse(&mut Cursor::new(&bad_buf[..])).is_err());
     }
 
     #[test]
     fn test_sub_method_parsing_from_bytes() {
         let sub_buf = b"SUB test_topic\r\n";
         let mut sub_cursor = Cursor::new(&sub_buf[..]);
-        assert!(Parser::check(&mut sub_cursor).is_ok());
+        assert!(Parser::check(&mut sub_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+
+        sub_cursor.set_position(0);
+        let expected = MethodFrames::Subscribe("test_topic".to_string(), Start::Latest, None);
+        assert_eq!(Parser::parse(&mut sub_cursor).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_sub_from_offset_parsing_from_bytes() {
+        let sub_buf = b"SUB test_topic 42\r\nSUB other\r\n";
+        let mut sub_cursor = Cursor::new(&sub_buf[..]);
+        assert!(Parser::check(&mut sub_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+        assert_eq!(sub_cursor.position(), 19);
 
         sub_cursor.set_position(0);
-        let expected = MethodFrames::Subscribe("test_topic".to_string());
+        let expected = MethodFrames::Subscribe("test_topic".to_string(), Start::Offset(42), None);
         assert_eq!(Parser::parse(&mut sub_cursor).unwrap(), expected);
+
+        let bad_buf = b"SUB test_topic soonish\r\n";
+        assert!(Parser::parse(&mut Cursor::new(&bad_buf[..])).is_err());
+    }
+
+    #[test]
+    fn test_sub_start_positions_parsing_from_bytes() {
+        let cases = vec![
+            (&b"SUB t EARLIEST\r\n"[..], Start::Earliest, None),
+            (&b"SUB t LATEST\r\n"[..], Start::Latest, None),
+            (&b"SUB t @1700000000000\r\n"[..], Start::Timestamp(1_700_000_000_000), None),
+            (&b"SUB t EARLIEST billing\r\n"[..], Start::Earliest, Some("billing".to_string())),
+        ];
+        for (buf, start, consumer) in cases {
+            let expected = MethodFrames::Subscribe("t".to_string(), start, consumer);
+            assert_eq!(Parser::parse(&mut Cursor::new(buf)).unwrap(), expected);
+        }
+        assert!(Parser::parse(&mut Cursor::new(&b"SUB t @soon\r\n"[..])).is_err());
+    }
+
+    #[test]
+    fn test_sub_group_parsing_from_bytes() {
+        let cases = vec![
+            (&b"SUB t GROUP workers\r\n"[..], Assignment::RoundRobin),
+            (&b"SUB t GROUP workers ROUNDROBIN\r\n"[..], Assignment::RoundRobin),
+            (&b"SUB t GROUP workers HASH\r\n"[..], Assignment::Hash),
+        ];
+        for (buf, assignment) in cases {
+            let expected =
+                MethodFrames::SubscribeGroup("t".to_string(), "workers".to_string(), assignment);
+            assert_eq!(Parser::parse(&mut Cursor::new(buf)).unwrap(), expected);
+        }
+        assert!(Parser::parse(&mut Cursor::new(&b"SUB t GROUP\r\n"[..])).is_err());
+        assert!(Parser::parse(&mut Cursor::new(&b"SUB t GROUP workers RANDOM\r\n"[..])).is_err());
+    }
+
+    #[test]
+    fn test_unsub_method_parsing_from_bytes() {
+        let unsub_buf = b"UNSUB test_topic\r\nUNSUB\r\n";
+        let mut unsub_cursor = Cursor::new(&unsub_buf[..]);
+        let expected = MethodFrames::Unsubscribe(Some("test_topic".to_string()));
+        assert_eq!(Parser::parse(&mut unsub_cursor).unwrap(), expected);
+        assert_eq!(unsub_cursor.position(), 18);
+
+        // without a subject it's every subscription
+        assert_eq!(Parser::parse(&mut unsub_cursor).unwrap(), MethodFrames::Unsubscribe(None));
+        assert_eq!(unsub_cursor.position() as usize, unsub_buf.len());
+    }
+
+    #[test]
+    fn test_subs_method_parsing_from_bytes() {
+        let subs_buf = b"SUBS\r\n";
+        let mut subs_cursor = Cursor::new(&subs_buf[..]);
+        assert!(Parser::check(&mut subs_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+
+        subs_cursor.set_position(0);
+        assert_eq!(Parser::parse(&mut subs_cursor).unwrap(), MethodFrames::List);
+
+        let bad_buf = b"SUBS test_topic\r\n";
+        assert!(Parser::parse(&mut Cursor::new(&bad_buf[..])).is_err());
+    }
+
+    #[test]
+    fn test_commit_method_parsing_from_bytes() {
+        let commit_buf = b"COMMIT test_topic billing 41\r\n";
+        let mut commit_cursor = Cursor::new(&commit_buf[..]);
+        assert!(Parser::check(&mut commit_cursor, DEFAULT_MAX_PAYLOAD).is_ok());
+
+        commit_cursor.set_position(0);
+        let expected = MethodFrames::Commit("test_topic".to_string(), "billing".to_string(), 41);
+        assert_eq!(Parser::parse(&mut commit_cursor).unwrap(), expected);
+
+        let bad_buf = b"COMMIT test_topic billing\r\n";
+        assert!(Parser::parse(&mut Cursor::new(&bad_buf[..])).is_err());
+    }
+
+    #[test]
+    fn test_message_frame() {
+        let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let msg = Message {
+            id: id.parse().unwrap(),
+            timestamp: 1000,
+            subject: "greetings".to_string(),
+            offset: 7,
+            ..Message::new(Bytes::from("hello"))
+        };
+        let expected = format!("MSG greetings 7 {} 1000 5\r\nhello\r\n", id);
+        assert_eq!(msg.to_frame(), Bytes::from(expected));
+
+        let msg = Message {
+            reply: Some("_INBOX.1".to_string()),
+            ..msg
+        };
+        let expected = format!("MSG greetings 7 {} 1000 _INBOX.1 5\r\nhello\r\n", id);
+        assert_eq!(msg.to_frame(), Bytes::from(expected));
+
+        let msg = Message {
+            headers: vec![("Trace-Id".to_string(), "abc".to_string())],
+            ..msg
+        };
+        let expected = format!(
+            "HMSG greetings 7 {} 1000 _INBOX.1 17 22\r\nTrace-Id: abc\r\n\r\nhello\r\n",
+            id
+        );
+        assert_eq!(msg.to_frame(), Bytes::from(expected));
     }
 }
@@ -0,0 +1,117 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::error::{MessageStoreError, ParsingError};
+
+/// What went wrong, for the client to act on
+/// without having to read the message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnknownTopic,
+    Parse,
+    // the store was locked by someone else, so it's worth trying again
+    Busy,
+    PermissionDenied,
+    // a well-formed method the broker can't do, e.g. a bad name or offset
+    Invalid,
+    TooLarge,
+    Timeout,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn of(e: &crate::Error) -> ErrorCode {
+        if let Some(e) = e.downcast_ref::<MessageStoreError>() {
+            return match e {
+                MessageStoreError::UnknownTopic(_) => ErrorCode::UnknownTopic,
+                MessageStoreError::Busy => ErrorCode::Busy,
+                MessageStoreError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+                _ => ErrorCode::Invalid,
+            };
+        }
+        match e.downcast_ref::<ParsingError>() {
+            Some(ParsingError::TooLarge) => ErrorCode::TooLarge,
+            Some(_) => ErrorCode::Parse,
+            None => ErrorCode::Internal,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match self {
+            ErrorCode::UnknownTopic => "UNKNOWN_TOPIC",
+            ErrorCode::Parse => "PARSE",
+            ErrorCode::Busy => "BUSY",
+            ErrorCode::PermissionDenied => "PERMISSION_DENIED",
+            ErrorCode::Invalid => "INVALID",
+            ErrorCode::TooLarge => "TOO_LARGE",
+            ErrorCode::Timeout => "TIMEOUT",
+            ErrorCode::Internal => "INTERNAL",
+        };
+        code.fmt(f)
+    }
+}
+
+/// `+OK` followed by what was done, e.g. `+OK PUB orders 3`
+pub fn ok(words: &[&str]) -> Bytes {
+    let mut res = String::from("+OK");
+    for word in words {
+        res.push(' ');
+        res.push_str(word);
+    }
+    res.push_str("\r\n");
+    Bytes::from(res)
+}
+
+/// `-ERR` followed by the code and a message for people to read
+pub fn error(code: ErrorCode, message: impl fmt::Display) -> Bytes {
+    Bytes::from(format!("-ERR {} {}\r\n", code, message))
+}
+
+pub fn from_error(e: &crate::Error) -> Bytes {
+    error(ErrorCode::of(e), e)
+}
+
+/// Whether the connection can carry on after replying with `e`. The
+/// store's errors leave nothing half done, and a line that doesn't parse
+/// is skipped, but a payload that went with one can't be, one that's too
+/// large is still arriving, and anything else (e.g. the socket or a log
+/// failing) probably won't get better.
+pub fn is_recoverable(e: &crate::Error) -> bool {
+    e.is::<MessageStoreError>() || matches!(e.downcast_ref(), Some(ParsingError::Invalid))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_responses() {
+        assert_eq!(ok(&["PUB", "orders", "3"]), Bytes::from("+OK PUB orders 3\r\n"));
+        assert_eq!(ok(&["SUBS"]), Bytes::from("+OK SUBS\r\n"));
+
+        let e: crate::Error = Box::new(MessageStoreError::UnknownTopic("orders".to_string()));
+        assert_eq!(from_error(&e), Bytes::from("-ERR UNKNOWN_TOPIC no topic orders\r\n"));
+        assert!(is_recoverable(&e));
+
+        let e: crate::Error = Box::new(ParsingError::Invalid);
+        assert_eq!(from_error(&e), Bytes::from("-ERR PARSE parsing error\r\n"));
+        assert!(is_recoverable(&e));
+
+        let e: crate::Error = Box::new(ParsingError::Unframed);
+        assert_eq!(ErrorCode::of(&e), ErrorCode::Parse);
+        assert!(!is_recoverable(&e));
+
+        let e: crate::Error = Box::new(ParsingError::TooLarge);
+        assert_eq!(ErrorCode::of(&e), ErrorCode::TooLarge);
+        assert!(!is_recoverable(&e));
+
+        let e: crate::Error = "connection reset by peer".into();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::Internal);
+        assert!(!is_recoverable(&e));
+    }
+
+}
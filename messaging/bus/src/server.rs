@@ -4,11 +4,12 @@ use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{broadcast, mpsc, Semaphore};
 use tokio::time::{self, Duration};
-use tracing::{error, info};
+use tracing::{debug, error, info};
 
 use crate::broker::{MessageStore, MessageStoreDropGuard};
 use crate::connection::{Connection, Shutdown};
 use crate::method::Method;
+use crate::response;
 
 struct Handler {
     // a shared handle to the message store
@@ -20,6 +21,10 @@ struct Handler {
 
     // handle shutdown signals
     shutdown: Shutdown,
+
+    // dropped once the connection is done, so the server
+    // knows when every connection has finished
+    _shutdown_complete: mpsc::Sender<()>,
 }
 
 /// The main server running and listening to connections
@@ -34,24 +39,36 @@ pub struct Server {
     // limit the number of connections via a semaphore
     limit_connections: Arc<Semaphore>,
 
+    // the most payload a connection takes in one frame
+    max_payload: usize,
+
     // broadcasts a shutdown signal to all active connections
     shutdown_sender: broadcast::Sender<()>,
 
+    // every handler holds a sender, so the receiver
+    // closes once they've all been dropped
     shutdown_complete_tx: mpsc::Sender<()>,
     shutdown_complete_rx: mpsc::Receiver<()>,
 }
 
 /// Main entrypoint
-pub async fn run(listener: TcpListener, shutdown: impl Future, n_permits: usize) {
+pub async fn run(
+    listener: TcpListener,
+    message_store: MessageStoreDropGuard,
+    shutdown: impl Future,
+    n_permits: usize,
+    max_payload: usize,
+) {
     let (notify_shutdown, _) = broadcast::channel(1);
     let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
 
     info!(permits = n_permits);
 
     let mut server = Server {
-        message_store: MessageStoreDropGuard::new(),
+        message_store,
         listener,
         limit_connections: Arc::new(Semaphore::new(n_permits)),
+        max_payload,
         shutdown_sender: notify_shutdown,
         shutdown_complete_tx,
         shutdown_complete_rx,
@@ -67,28 +84,51 @@ pub async fn run(listener: TcpListener, shutdown: impl Future, n_permits: usize)
             info!("shutting down");
         }
     }
+
+    // tell every connection to stop, then wait for them to finish
+    // so the store (and any logs it's writing) is closed cleanly
+    let Server {
+        shutdown_sender,
+        shutdown_complete_tx,
+        mut shutdown_complete_rx,
+        ..
+    } = server;
+    drop(shutdown_sender);
+    drop(shutdown_complete_tx);
+    let _ = shutdown_complete_rx.recv().await;
 }
 
 impl Handler {
     async fn run(&mut self) -> crate::Result<()> {
         while !self.shutdown.is_shutdown() {
             let maybe_method = tokio::select! {
-                res = self.connection.read() => res?,
+                res = self.connection.read() => res,
                 _ = self.shutdown.recv() => {
                     return Ok(());
                 }
             };
 
-            let method_frames = match maybe_method {
-                Some(m) => m,
-                None => return Ok(()),
-            };
+            let res = match maybe_method {
+                Ok(Some(method_frames)) => {
+                    let method = Method::from_frames(method_frames);
+                    debug!(method = method.get_name());
 
-            let method = Method::from_frames(method_frames);
+                    method
+                        .apply(&self.message_store, &mut self.connection, &mut self.shutdown)
+                        .await
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => Err(e),
+            };
 
-            method
-                .apply(&self.message_store, &mut self.connection)
-                .await?;
+            // every error gets a reply, but only some leave
+            // the connection in a state to carry on after
+            if let Err(e) = res {
+                self.connection.write(response::from_error(&e)).await?;
+                if !response::is_recoverable(&e) {
+                    return Err(e);
+                }
+            }
         }
         Ok(())
     }
@@ -112,13 +152,15 @@ impl Server {
                 // get a handle on the message store
                 message_store: self.message_store.store(),
 
-                connection: Connection::new(socket),
+                connection: Connection::new(socket, self.max_payload),
 
                 // pass the semaphore to connection to give
                 // the permit back when it's finished
                 limit_connections: self.limit_connections.clone(),
 
                 shutdown: Shutdown::new(self.shutdown_sender.subscribe()),
+
+                _shutdown_complete: self.shutdown_complete_tx.clone(),
             };
 
             tokio::spawn(async move {
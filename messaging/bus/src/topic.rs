@@ -1,3 +1,6 @@
+// inboxes get a topic under this, e.g. _INBOX.<uuid>
+pub const INBOX_PREFIX: &str = "_INBOX";
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Topic(pub String);
 
@@ -5,4 +8,45 @@ impl Topic {
     pub fn new(name: impl ToString) -> Self {
         Topic(name.to_string())
     }
+
+    /// Whether it's a pattern for subscribing to other topics, having
+    /// a `*` or `>` for one of its dot-separated tokens
+    pub fn is_wildcard(&self) -> bool {
+        self.0.split('.').any(|t| t == "*" || t == ">")
+    }
+
+    /// Whether it's a subject or pattern at all: no empty tokens, and
+    /// nothing after a `>`, which matches the rest of the subject
+    pub fn is_valid(&self) -> bool {
+        let tokens: Vec<&str> = self.0.split('.').collect();
+        tokens.iter().all(|t| !t.is_empty())
+            && !tokens[..tokens.len() - 1].contains(&">")
+    }
+
+    /// Whether only the broker can make it, or subscribe to it, by name
+    /// or by wildcard, i.e. it's an inbox. Anyone can publish replies to one.
+    pub fn is_reserved(&self) -> bool {
+        self.0.split('.').next() == Some(INBOX_PREFIX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_subjects() {
+        for name in &["orders", "orders.eu.created", "orders.*.created", "orders.>", "*"] {
+            assert!(Topic::new(name).is_valid(), "{}", name);
+        }
+        for name in &["", "orders.", ".orders", "orders..eu", "orders.>.created"] {
+            assert!(!Topic::new(name).is_valid(), "{}", name);
+        }
+        assert!(Topic::new("orders.*").is_wildcard());
+        assert!(!Topic::new("orders.a*").is_wildcard());
+        assert!(Topic::new("_INBOX.abc").is_reserved());
+        assert!(!Topic::new("orders._INBOX").is_reserved());
+    }
+
 }
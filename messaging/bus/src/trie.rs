@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+/// Values stored by dot-separated pattern, where `*` matches any one
+/// token and `>` one or more at the end. Finding the patterns that match
+/// a subject walks one path down per wildcard, rather than every pattern.
+#[derive(Debug)]
+pub struct SubjectTrie<V> {
+    root: Node<V>,
+}
+
+#[derive(Debug)]
+struct Node<V> {
+    children: HashMap<String, Node<V>>,
+    value: Option<V>,
+}
+
+impl<V> Default for SubjectTrie<V> {
+    fn default() -> Self {
+        SubjectTrie { root: Node::default() }
+    }
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Node {
+            children: HashMap::new(),
+            value: None,
+        }
+    }
+}
+
+impl<V> Node<V> {
+    fn matches<'a>(&'a self, tokens: &[&str], found: &mut Vec<&'a V>) {
+        let (token, rest) = match tokens.split_first() {
+            Some(split) => split,
+            None => {
+                found.extend(self.value.as_ref());
+                return;
+            }
+        };
+        if let Some(tail) = self.children.get(">") {
+            found.extend(tail.value.as_ref());
+        }
+        if let Some(child) = self.children.get("*") {
+            child.matches(rest, found);
+        }
+        if let Some(child) = self.children.get(*token) {
+            child.matches(rest, found);
+        }
+    }
+
+    // whether the node can go, i.e. nothing is left under it
+    fn retain(&mut self, keep: &mut impl FnMut(&V) -> bool) -> bool {
+        if !self.value.as_ref().is_some_and(&mut *keep) {
+            self.value = None;
+        }
+        self.children.retain(|_, child| !child.retain(keep));
+        self.value.is_none() && self.children.is_empty()
+    }
+}
+
+impl<V> SubjectTrie<V> {
+    /// The value stored for `pattern`, inserting one from `f` if there isn't
+    pub fn get_or_insert_with(&mut self, pattern: &str, f: impl FnOnce() -> V) -> &mut V {
+        let node = pattern.split('.').fold(&mut self.root, |node, token| {
+            node.children.entry(token.to_string()).or_default()
+        });
+        node.value.get_or_insert_with(f)
+    }
+
+    /// The values of every pattern matching `subject`, which
+    /// mustn't have any wildcards in it itself
+    pub fn matches(&self, subject: &str) -> Vec<&V> {
+        let tokens: Vec<&str> = subject.split('.').collect();
+        let mut found = vec![];
+        self.root.matches(&tokens, &mut found);
+        found
+    }
+
+    /// Removes the patterns whose values `keep` returns false for
+    pub fn retain(&mut self, mut keep: impl FnMut(&V) -> bool) {
+        self.root.retain(&mut keep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn trie(patterns: &[&'static str]) -> SubjectTrie<&'static str> {
+        let mut trie = SubjectTrie::default();
+        for p in patterns {
+            trie.get_or_insert_with(p, || *p);
+        }
+        trie
+    }
+
+    fn matching(trie: &SubjectTrie<&'static str>, subject: &str) -> Vec<&'static str> {
+        let mut found: Vec<_> = trie.matches(subject).into_iter().copied().collect();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn test_wildcards_match() {
+        let trie = trie(&[
+            "orders.*.created",
+            "orders.>",
+            "orders.eu.created",
+            "*",
+            ">",
+            "orders.*",
+        ]);
+
+        assert_eq!(
+            matching(&trie, "orders.eu.created"),
+            vec![">", "orders.*.created", "orders.>", "orders.eu.created"]
+        );
+        assert_eq!(matching(&trie, "orders.us"), vec![">", "orders.*", "orders.>"]);
+        // > needs at least one token after it
+        assert_eq!(matching(&trie, "orders"), vec!["*", ">"]);
+        assert_eq!(matching(&trie, "payments.eu.created"), vec![">"]);
+    }
+
+    #[test]
+    fn test_retain_prunes_empty_branches() {
+        let mut trie = trie(&["orders.*.created", "orders.>"]);
+        trie.retain(|p| *p != "orders.*.created");
+        assert_eq!(matching(&trie, "orders.eu.created"), vec!["orders.>"]);
+        assert_eq!(trie.root.children["orders"].children.len(), 1);
+
+        trie.retain(|_| false);
+        assert!(trie.root.children.is_empty());
+    }
+
+}